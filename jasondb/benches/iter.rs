@@ -0,0 +1,78 @@
+//! Compares full scans of a file-based database with `Database::iter` and `Database::iter_sequential`.
+//!
+//! Run with `cargo bench --bench iter`, optionally followed by `-- <entries>` to change the number of entries.
+
+use jasondb::Database;
+
+use humphrey_json::prelude::*;
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// The number of entries written to the database by default.
+const DEFAULT_ENTRIES: usize = 200_000;
+
+/// The number of times each scan is repeated, of which the fastest is reported.
+const RUNS: usize = 5;
+
+#[derive(FromJson, IntoJson)]
+struct Person {
+    name: String,
+    year_of_birth: u16,
+}
+
+fn main() {
+    let entries = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_ENTRIES);
+
+    let path = std::env::temp_dir().join(format!("jasondb_bench_iter_{}.jdb", std::process::id()));
+    let mut database: Database<Person> = Database::create(&path).unwrap();
+
+    for i in 0..entries {
+        let person = Person {
+            name: format!("Person {}", i),
+            year_of_birth: (1600 + i % 400) as u16,
+        };
+
+        database.set(format!("person_{}", i), person).unwrap();
+    }
+
+    let iter = fastest(
+        || database.iter().filter(|entry| entry.is_ok()).count(),
+        entries,
+    );
+    let iter_sequential = fastest(
+        || {
+            database
+                .iter_sequential()
+                .filter(|entry| entry.is_ok())
+                .count()
+        },
+        entries,
+    );
+
+    println!("{} entries, fastest of {} runs:", entries, RUNS);
+    println!("  iter:            {:>8.1?}", iter);
+    println!("  iter_sequential: {:>8.1?}", iter_sequential);
+    println!(
+        "  speedup:         {:>8.2}x",
+        iter.as_secs_f64() / iter_sequential.as_secs_f64()
+    );
+
+    drop(database);
+    fs::remove_file(path).unwrap();
+}
+
+/// Runs the scan `RUNS` times, checking that it finds every entry, and returns the fastest time.
+fn fastest(mut scan: impl FnMut() -> usize, entries: usize) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            assert_eq!(scan(), entries);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
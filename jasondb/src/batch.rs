@@ -0,0 +1,24 @@
+//! Provides the `Op` and `OpResult` types, which describe the operations of a batch executed with
+//!   [`Database::batch`](crate::Database::batch).
+
+/// Represents a single operation in a batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op<T> {
+    /// Gets the value with the given key, like `Database::get`.
+    Get(String),
+    /// Sets the value with the given key, like `Database::set`.
+    Set(String, T),
+    /// Deletes the value with the given key, like `Database::delete`.
+    Delete(String),
+}
+
+/// Represents the result of a successful operation in a batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpResult<T> {
+    /// The value which was read by an `Op::Get`.
+    Get(T),
+    /// The value was set by an `Op::Set`.
+    Set,
+    /// The value was deleted by an `Op::Delete`.
+    Delete,
+}
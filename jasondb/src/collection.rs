@@ -0,0 +1,159 @@
+//! Provides the `Collection` type, a namespaced view of a database.
+
+use crate::database::{Database, Iter};
+use crate::error::JasonError;
+use crate::query::Query;
+use crate::sources::Source;
+
+use humphrey_json::prelude::*;
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+
+/// The separator between the name of a collection and the keys within it.
+const SEPARATOR: char = '/';
+
+/// Represents a collection within a database, created with [`Database::collection`].
+///
+/// A collection is a namespace of keys which share the prefix `name/`, so the key `alice` in the collection `users` is
+///   stored in the database as `users/alice`. Keys passed to and returned from the collection do not include the prefix.
+///
+/// ## Example
+/// ```
+/// let mut db: Database<Person> = Database::new("database.jdb")?;
+///
+/// db.collection("users").set("alice", alice)?;
+/// db.collection("admins").set("bob", bob)?;
+///
+/// assert_eq!(db.collection("users").len(), 1);
+/// assert_eq!(db.get("users/alice")?, alice);
+/// ```
+pub struct Collection<'a, T, S>
+where
+    T: IntoJson + FromJson + 'static,
+    S: Source,
+{
+    database: &'a mut Database<T, S>,
+    prefix: String,
+}
+
+impl<'a, T, S> Collection<'a, T, S>
+where
+    T: IntoJson + FromJson + 'static,
+    S: Source,
+{
+    /// Creates a view of the collection with the given name in the database.
+    pub(crate) fn new(database: &'a mut Database<T, S>, name: impl AsRef<str>) -> Self {
+        Self {
+            database,
+            prefix: format!("{}{}", name.as_ref(), SEPARATOR),
+        }
+    }
+
+    /// Returns the name of the collection.
+    pub fn name(&self) -> &str {
+        &self.prefix[..self.prefix.len() - SEPARATOR.len_utf8()]
+    }
+
+    /// Returns the number of keys in the collection.
+    pub fn len(&self) -> usize {
+        self.database
+            .primary_indexes
+            .keys()
+            .filter(|k| k.starts_with(&self.prefix))
+            .count()
+    }
+
+    /// Returns `true` if the collection contains no keys.
+    pub fn is_empty(&self) -> bool {
+        !self
+            .database
+            .primary_indexes
+            .keys()
+            .any(|k| k.starts_with(&self.prefix))
+    }
+
+    /// Gets the value with the given key in the collection.
+    ///
+    /// Returns `Err(JasonError::InvalidKey)` if the key is not found, or another error if the source fails.
+    pub fn get(&mut self, key: impl AsRef<str>) -> Result<T, JasonError> {
+        let key = self.key(key);
+
+        self.database.get(key)
+    }
+
+    /// Sets the value with the given key in the collection to the given value.
+    pub fn set(&mut self, key: impl AsRef<str>, value: impl Borrow<T>) -> Result<(), JasonError> {
+        let key = self.key(key);
+
+        self.database.set(key, value)
+    }
+
+    /// Deletes the value with the given key from the collection.
+    pub fn delete(&mut self, key: impl AsRef<str>) -> Result<(), JasonError> {
+        let key = self.key(key);
+
+        self.database.delete(key)
+    }
+
+    /// Deletes every value in the collection, leaving the rest of the database untouched.
+    pub fn clear(&mut self) -> Result<(), JasonError> {
+        for key in self.database.keys_with_prefix(&self.prefix) {
+            self.database.delete(key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the keys in the collection in the order in which they are stored.
+    pub fn keys(&self) -> Vec<String> {
+        self.database
+            .keys_with_prefix(&self.prefix)
+            .into_iter()
+            .map(|k| k[self.prefix.len()..].to_string())
+            .collect()
+    }
+
+    /// Creates an iterator over the collection in the order in which it is stored.
+    pub fn iter(&mut self) -> impl Iterator<Item = Result<(String, T), JasonError>> + '_ {
+        let prefix_len = self.prefix.len();
+
+        self.database
+            .iter_prefix(&self.prefix)
+            .map(move |entry| entry.map(|(k, v)| (k[prefix_len..].to_string(), v)))
+    }
+
+    /// Executes the given query on the collection.
+    ///
+    /// The query is executed against the whole database and the results are then restricted to the collection, so
+    ///   it costs the same as querying the database directly.
+    pub fn query(
+        &mut self,
+        query: Query,
+    ) -> Result<impl Iterator<Item = Result<(String, T), JasonError>> + '_, JasonError> {
+        let prefix_len = self.prefix.len();
+        let offsets = self
+            .database
+            .primary_indexes
+            .iter()
+            .filter(|(k, _)| k.starts_with(&self.prefix))
+            .map(|(_, offset)| *offset)
+            .collect::<HashSet<_>>();
+
+        let Iter { database, keys } = query.execute(self.database)?;
+        let keys = keys
+            .filter(|offset| offsets.contains(offset))
+            .collect::<Vec<_>>();
+
+        Ok(Iter {
+            database,
+            keys: keys.into_iter(),
+        }
+        .map(move |entry| entry.map(|(k, v)| (k[prefix_len..].to_string(), v))))
+    }
+
+    /// Returns the full key in the database of the given key in the collection.
+    fn key(&self, key: impl AsRef<str>) -> String {
+        format!("{}{}", self.prefix, key.as_ref())
+    }
+}
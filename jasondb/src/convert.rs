@@ -0,0 +1,113 @@
+//! Provides a converter from the format of the JasonDB server to the log-structured format of the library.
+
+use crate::error::JasonError;
+use crate::sources::{self, FileSource, Source};
+use crate::util::{canonical, quiet_assert};
+
+use std::fs;
+use std::path::Path;
+
+/// The length of a tar block, which every header and file in the archive is padded to.
+const BLOCK_LEN: usize = 512;
+
+/// Converts a database from the JasonDB server into a new log-structured database file, which can be opened with
+///   `Database::open`.
+///
+/// Databases from the server are tar archives which share the `.jdb` extension but not the format, so they can't be
+///   opened directly. Each document in the archive is stored as a file at `collection/id.json`, which is written to the
+///   new database under the key `collection/id`, so that it can be accessed with `Database::collection`. Paths may
+///   start with `./`, as they do in archives created from inside the database's directory, which is removed.
+///   Documents are written in the order in which they appear in the archive, so if a document appears more than once,
+///   the last copy is kept.
+///
+/// Returns `Err(JasonError::BadFormat)` if the legacy file is not a tar archive or contains entries other than files and
+///   directories, or `Err(JasonError::JsonError)` if a document is not valid JSON. If the new file already exists,
+///   `Err(JasonError::Io)` is returned and it is left untouched. Otherwise, if the conversion fails, the new file is removed.
+///
+/// ## Example
+/// ```
+/// jasondb::convert_isam("legacy.jdb", "database.jdb")?;
+///
+/// let mut db: Database<User> = Database::open("database.jdb")?;
+/// let alice = db.collection("users").get("alice")?;
+/// ```
+pub fn convert_isam(
+    legacy_path: impl AsRef<Path>,
+    new_path: impl AsRef<Path>,
+) -> Result<(), JasonError> {
+    let archive = fs::read(legacy_path).map_err(|_| JasonError::Io)?;
+    quiet_assert(sources::is_tar_header(&archive), JasonError::BadFormat)?;
+
+    let mut source = FileSource::create(&new_path)?;
+    let result = write_documents(&archive, &mut source);
+    drop(source);
+
+    if result.is_err() {
+        fs::remove_file(new_path).ok();
+    }
+
+    result
+}
+
+/// Writes every document in the archive to the source.
+fn write_documents(archive: &[u8], source: &mut FileSource) -> Result<(), JasonError> {
+    let mut offset = 0;
+
+    // The archive ends with empty blocks, but its length is also accepted as the end in case they are missing.
+    while offset + BLOCK_LEN <= archive.len()
+        && archive[offset..offset + BLOCK_LEN] != [0; BLOCK_LEN]
+    {
+        let header = &archive[offset..offset + BLOCK_LEN];
+        quiet_assert(sources::is_tar_header(header), JasonError::BadFormat)?;
+
+        let size = octal(&header[124..136]).ok_or(JasonError::BadFormat)?;
+        let start = offset + BLOCK_LEN;
+        quiet_assert(size <= archive.len() - start, JasonError::BadFormat)?;
+
+        match header[156] {
+            b'0' | 0 => {
+                let name = name(header)?;
+                let name = name.trim_start_matches("./");
+                let key = name.strip_suffix(".json").unwrap_or(name);
+                let value = canonical::canonicalise(&archive[start..start + size], false)?;
+
+                source.write_entry(key, value)?;
+            }
+            b'5' => (),
+            _ => return Err(JasonError::BadFormat),
+        }
+
+        offset = start + size.div_ceil(BLOCK_LEN) * BLOCK_LEN;
+    }
+
+    source.flush()
+}
+
+/// Reads the path of the file described by the tar header, including the prefix used by USTAR archives for long paths.
+fn name(header: &[u8]) -> Result<String, JasonError> {
+    let name = field(&header[..100]);
+
+    let path = if &header[257..262] == b"ustar" && header[345] != 0 {
+        [field(&header[345..500]), b"/", name].concat()
+    } else {
+        name.to_vec()
+    };
+
+    String::from_utf8(path).map_err(|_| JasonError::BadFormat)
+}
+
+/// Returns the contents of a NUL-terminated header field.
+fn field(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    &bytes[..len]
+}
+
+/// Parses a numeric header field, which is stored as octal digits padded with spaces or NULs.
+fn octal(bytes: &[u8]) -> Option<usize> {
+    let digits = std::str::from_utf8(bytes)
+        .ok()?
+        .trim_matches(|c: char| c == ' ' || c == '\0');
+
+    usize::from_str_radix(digits, 8).ok()
+}
@@ -0,0 +1,40 @@
+//! Provides the `Cursor` type, an opaque token for resuming iteration with [`Database::iter_from`](crate::Database::iter_from).
+
+use crate::error::JasonError;
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Represents a position in the database, after which iteration can be resumed with
+///   [`Database::iter_from`](crate::Database::iter_from).
+///
+/// Entries are iterated in the order in which they are stored, and new or updated values are always appended, so a
+///   cursor never skips or repeats an entry because of writes made since it was created. An updated value is moved to
+///   the end, so it will be returned again after the cursor.
+///
+/// Cursors can be converted to and from strings with `to_string` and `parse` so they can be given to clients, but
+///   their contents should be treated as opaque. Compacting the database moves every entry, so cursors created
+///   before a compaction must not be used after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    pub(crate) offset: u64,
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.offset)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = JasonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.len() == 16 && s.bytes().all(|c| c.is_ascii_hexdigit()) {
+            true => u64::from_str_radix(s, 16)
+                .map(|offset| Self { offset })
+                .map_err(|_| JasonError::InvalidCursor),
+            false => Err(JasonError::InvalidCursor),
+        }
+    }
+}
@@ -1,18 +1,30 @@
 //! Provides the core database API for JasonDB.
 
+use crate::batch::{Op, OpResult};
+use crate::collection::Collection;
+use crate::cursor::Cursor;
+use crate::entry::Entry;
 use crate::error::JasonError;
-use crate::query::Query;
-use crate::replica::{Replica, Replicator};
-use crate::sources::{FileSource, InMemory, Source};
-use crate::util::{indexing, quiet_assert};
+use crate::keys;
+use crate::query::{CompiledQuery, Query};
+use crate::replica::{Replica, Replicator, TypedReplica};
+use crate::schedule::ScheduledDatabase;
+use crate::sources::{
+    is_tombstone, BackgroundCompaction, CompactStrategy, CompactionStats, FileSource, InMemory,
+    SharedRead, Source,
+};
+use crate::util::ordering::{self, OrderedValue};
+use crate::util::{canonical, indexing, quiet_assert, trace};
 
 use humphrey_json::prelude::*;
 use humphrey_json::Value;
 
 use std::borrow::Borrow;
 use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 use std::path::Path;
+use std::time::Duration;
 use std::vec::IntoIter;
 
 /// Represents a JasonDB database.
@@ -60,11 +72,76 @@ where
 {
     pub(crate) primary_indexes: HashMap<String, u64>,
     pub(crate) secondary_indexes: HashMap<String, HashMap<Value, BTreeSet<u64>>>,
+    pub(crate) computed_indexes: HashMap<String, ComputedIndex>,
+    pub(crate) sorted_indexes: HashMap<String, BTreeSet<OrderedValue>>,
     pub(crate) source: S,
     pub(crate) replicas: Vec<Replicator<T>>,
+    pub(crate) typed_replicas: Vec<Box<dyn TypedReplica<T>>>,
+    pub(crate) observers: Vec<Observer<T>>,
+    pub(crate) sort_keys: bool,
+    pub(crate) max_value_size: Option<usize>,
     marker: PhantomData<T>,
 }
 
+/// Represents a change to the database, as passed to functions registered with [`Database::on_change`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChangeEvent<'a, T> {
+    /// The key was set to the value.
+    Set {
+        /// The key which was set.
+        key: &'a str,
+        /// The new value of the key.
+        value: &'a T,
+    },
+    /// The key was deleted.
+    Delete {
+        /// The key which was deleted.
+        key: &'a str,
+    },
+}
+
+/// Describes the effect of compacting a database, as returned by [`Database::compact_report`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// The size of the source in bytes before compaction.
+    pub bytes_before: u64,
+    /// The size of the source in bytes after compaction.
+    pub bytes_after: u64,
+    /// The number of entries which were kept, which is the number of keys in the database.
+    pub entries_kept: usize,
+    /// The number of overwritten and deleted entries which were removed.
+    pub entries_dropped: usize,
+}
+
+impl CompactionReport {
+    /// Returns the number of bytes which were reclaimed by compaction.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// Estimates the effect of compacting a database without compacting it, as returned by [`Database::compaction_estimate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactionEstimate {
+    /// The current size of the source in bytes.
+    pub bytes_current: u64,
+    /// The total size of the latest entry for every key in bytes, which is the size the source would be after compaction.
+    pub bytes_live: u64,
+}
+
+impl CompactionEstimate {
+    /// Returns the number of bytes which compaction would reclaim.
+    pub fn bytes_reclaimable(&self) -> u64 {
+        self.bytes_current.saturating_sub(self.bytes_live)
+    }
+}
+
+/// A function which computes the value to index from the JSON representation of a value.
+pub(crate) type ComputedIndex = Box<dyn Fn(&Value) -> Value + Send>;
+
+/// A function to be called whenever the database changes.
+pub(crate) type Observer<T> = Box<dyn FnMut(ChangeEvent<'_, T>) + Send>;
+
 impl<T> Database<T, FileSource>
 where
     T: IntoJson + FromJson,
@@ -88,6 +165,16 @@ where
         Self::from_source(source)
     }
 
+    /// Creates a new empty database at the given path, or opens it if it exists but contains no entries.
+    ///
+    /// Unlike `create`, this succeeds if the file has already been created empty, for example by a previous run.
+    ///   If the file already contains entries, `JasonError::AlreadyExists` will be thrown.
+    pub fn create_exclusive(path: impl AsRef<Path>) -> Result<Self, JasonError> {
+        let source = FileSource::create_exclusive(path)?;
+
+        Self::from_source(source)
+    }
+
     /// Opens an existing database at the given path.
     ///
     /// If the file doesn't exist, an error will be thrown.
@@ -97,6 +184,70 @@ where
         Self::from_source(source)
     }
 
+    /// Starts compacting the database on a background thread, so that it can keep being used while the live entries are copied.
+    ///
+    /// The compaction takes effect when it is passed to `finish_compaction`. Until then, reads and writes work as normal,
+    ///   and any writes are copied to the compacted database when it is finished.
+    ///
+    /// ## Example
+    /// ```
+    /// let compaction = db.start_compaction()?;
+    /// db.set("key", value)?;
+    /// db.finish_compaction(compaction)?;
+    /// ```
+    pub fn start_compaction(&self) -> Result<BackgroundCompaction, JasonError> {
+        self.source.start_compaction(&self.primary_indexes)
+    }
+
+    /// Finishes a compaction started with `start_compaction`, waiting for the background thread if necessary,
+    ///   then swaps the compacted file into place and reloads the indexes.
+    ///
+    /// Returns `Err(JasonError::Index)` if the database has been compacted, migrated or truncated since the compaction started.
+    ///   Replicas are then compacted as they are by `compact`, which blocks until they have finished.
+    pub fn finish_compaction(
+        &mut self,
+        compaction: BackgroundCompaction,
+    ) -> Result<(), JasonError> {
+        self.source.finish_compaction(compaction)?;
+
+        self.reload()?;
+        self.compact_replicas()
+    }
+
+    /// Opens an existing database at the given path as read-only, so it can be read while it is open for writing elsewhere.
+    ///
+    /// Any attempt to write to the database will return an error. To pick up writes made elsewhere, use `reload`.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self, JasonError> {
+        let source = FileSource::open_read_only(path)?;
+
+        Self::from_source(source)
+    }
+
+    /// Configures the database to synchronise every write to stable storage before it returns.
+    ///
+    /// This trades write throughput for durability, since otherwise writes are only durable once `flush` is called.
+    ///   See `FileSource::with_sync` for details.
+    ///
+    /// ## Example
+    /// ```
+    /// let mut db: Database<Transaction> = Database::open("ledger.jdb")?.with_sync();
+    /// ```
+    pub fn with_sync(mut self) -> Self {
+        self.source = self.source.with_sync(true);
+        self
+    }
+
+    /// Performs compaction on the database using the given strategy.
+    ///
+    /// [`Database::compact`] uses [`CompactStrategy::TempFile`], which needs to create a temporary file next to the database.
+    ///   Where that isn't possible, [`CompactStrategy::InMemory`] compacts the database in memory instead.
+    ///   Replicas are compacted as they are by `compact`, using their own default strategy.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn compact_with(&mut self, strategy: CompactStrategy) -> Result<(), JasonError> {
+        self.compact_source(|source, indexes| source.compact_with(indexes, strategy))
+            .map(|_| ())
+    }
+
     /// Converts the file-based database into an in-memory database by copying the contents of the file into memory.
     ///
     /// **Warning:** changes made to the new in-memory database will not be reflected in the original file-based database.
@@ -104,8 +255,14 @@ where
         Ok(Database {
             primary_indexes: self.primary_indexes,
             secondary_indexes: self.secondary_indexes,
+            computed_indexes: self.computed_indexes,
+            sorted_indexes: self.sorted_indexes,
             source: self.source.into_memory()?,
             replicas: self.replicas,
+            typed_replicas: self.typed_replicas,
+            observers: self.observers,
+            sort_keys: self.sort_keys,
+            max_value_size: self.max_value_size,
             marker: PhantomData,
         })
     }
@@ -120,13 +277,27 @@ where
         Self::default()
     }
 
+    /// Returns a copy of the raw bytes of the in-memory database.
+    ///
+    /// The database can be restored from the bytes with `InMemory::from_bytes` and `Database::from_source`,
+    ///   although secondary indexes and replicas must be configured again.
+    pub fn snapshot_bytes(&self) -> Vec<u8> {
+        self.source.to_bytes()
+    }
+
     /// Writes the in-memory database to a new file at the given path.
     pub fn into_file(self, path: impl AsRef<Path>) -> Result<Database<T>, JasonError> {
         Ok(Database {
             primary_indexes: self.primary_indexes,
             secondary_indexes: self.secondary_indexes,
+            computed_indexes: self.computed_indexes,
+            sorted_indexes: self.sorted_indexes,
             source: self.source.into_file(path)?,
             replicas: self.replicas,
+            typed_replicas: self.typed_replicas,
+            observers: self.observers,
+            sort_keys: self.sort_keys,
+            max_value_size: self.max_value_size,
             marker: PhantomData,
         })
     }
@@ -140,27 +311,88 @@ where
         Self {
             primary_indexes: HashMap::new(),
             secondary_indexes: HashMap::new(),
+            computed_indexes: HashMap::new(),
+            sorted_indexes: HashMap::new(),
             source: InMemory::new(),
             replicas: Vec::new(),
+            typed_replicas: Vec::new(),
+            observers: Vec::new(),
+            sort_keys: false,
+            max_value_size: None,
             marker: PhantomData,
         }
     }
 }
 
+impl<T, K> Extend<(K, T)> for Database<T, InMemory>
+where
+    T: IntoJson + FromJson,
+    K: AsRef<str>,
+{
+    /// Sets each key in the database to its corresponding value.
+    ///
+    /// ## Panics
+    /// Since `extend` cannot return an error, this function will panic if any of the writes fail.
+    ///   For an in-memory database, this can only happen if the indexes are corrupt.
+    ///   To handle errors instead, use `try_extend`.
+    fn extend<I: IntoIterator<Item = (K, T)>>(&mut self, iter: I) {
+        self.try_extend(iter).unwrap();
+    }
+}
+
+impl<T, S> Debug for Database<T, S>
+where
+    T: IntoJson + FromJson,
+    S: Source,
+{
+    /// Formats a summary of the database without reading any of its values.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut indexed_fields = self.secondary_indexes.keys().collect::<Vec<_>>();
+        indexed_fields.sort_unstable();
+
+        f.debug_struct("Database")
+            .field("keys", &self.primary_indexes.len())
+            .field("indexed_fields", &indexed_fields)
+            .field(
+                "replicas",
+                &(self.replicas.len() + self.typed_replicas.len()),
+            )
+            .field("source", &self.source.kind())
+            .finish_non_exhaustive()
+    }
+}
+
 impl<T, S> Database<T, S>
 where
     T: IntoJson + FromJson,
     S: Source,
 {
     /// Creates a new database backed by the given source.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(source = source.kind()))
+    )]
     pub fn from_source(mut source: S) -> Result<Self, JasonError> {
         let indexes = source.load_indexes()?;
 
+        trace!(
+            tracing::Level::DEBUG,
+            entries = indexes.len(),
+            size = source.size(),
+            "loaded indexes"
+        );
+
         Ok(Self {
             primary_indexes: indexes,
             secondary_indexes: HashMap::new(),
+            computed_indexes: HashMap::new(),
+            sorted_indexes: HashMap::new(),
             source,
             replicas: Vec::new(),
+            typed_replicas: Vec::new(),
+            observers: Vec::new(),
+            sort_keys: false,
+            max_value_size: None,
             marker: PhantomData,
         })
     }
@@ -175,6 +407,28 @@ where
         Ok(self)
     }
 
+    /// Starts compacting the database every `interval` on a background thread, so long-lived databases don't need their
+    ///   own timer for compaction.
+    ///
+    /// Compaction needs exclusive access to the database, so it is moved into a [`ScheduledDatabase`], which shares it
+    ///   with the background thread and must be locked to use it. Compaction is skipped if there is nothing to reclaim.
+    ///
+    /// ## Example
+    /// ```
+    /// let db = Database::new("database.jdb")?
+    ///     .with_index("age")?
+    ///     .with_background_compaction(Duration::from_secs(3600));
+    ///
+    /// let adults = db.lock().query_keys(query!(age >= 18))?;
+    /// ```
+    pub fn with_background_compaction(self, interval: Duration) -> ScheduledDatabase<T, S>
+    where
+        T: Send + 'static,
+        S: Send + 'static,
+    {
+        ScheduledDatabase::new(self, interval)
+    }
+
     /// Configures the database to use the given secondary index.
     /// This is intended for use in a builder pattern as the example below shows.
     ///
@@ -187,14 +441,142 @@ where
     ///     .with_index(field!(my_field.my_subfield))?
     ///     .with_index("my_field.my_other_subfield")?;
     /// ```
+    ///
+    /// If any entry is not valid JSON, `Err(JasonError::InvalidEntry)` is returned with the key of the first such entry.
     pub fn with_index(mut self, field: impl AsRef<str>) -> Result<Self, JasonError> {
         let field = field.as_ref().to_string();
+        self.computed_indexes.remove(&field);
+        self.sorted_indexes.remove(&field);
+        let indexes = self.source.index_on(&field, &self.primary_indexes)?;
+        self.secondary_indexes.insert(field, indexes);
+
+        Ok(self)
+    }
+
+    /// Configures the database to use secondary indexes on all of the given fields.
+    ///
+    /// This is equivalent to calling `with_index` for each field, but it only reads through the database once.
+    ///
+    /// ## Example
+    /// ```
+    /// let mut db = Database::new("database.jdb")?.with_indexes(["name", "year_of_birth"])?;
+    /// ```
+    pub fn with_indexes<I, K>(mut self, fields: I) -> Result<Self, JasonError>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        let fields = fields
+            .into_iter()
+            .map(|field| field.as_ref().to_string())
+            .collect::<Vec<_>>();
+
+        for field in &fields {
+            self.computed_indexes.remove(field);
+            self.sorted_indexes.remove(field);
+        }
+
+        let indexes = self.source.index_on_many(&fields, &self.primary_indexes)?;
+        self.secondary_indexes.extend(indexes);
+
+        Ok(self)
+    }
+
+    /// Configures the database to use a secondary index on a value computed from each entry, rather than on a field.
+    ///
+    /// The function is given the JSON representation of each value, and the index is maintained on every `set` and `delete`.
+    ///   Queries refer to the computed value by the index's name as though it were a field, so the name should not clash
+    ///   with a real field.
+    ///
+    /// ## Example
+    /// ```
+    /// let mut db = Database::new(source)?
+    ///     .with_computed_index("century", |person| {
+    ///         let year = person["year_of_birth"].as_number().unwrap_or(0.0);
+    ///         Value::Number((year / 100.0).floor() + 1.0)
+    ///     })?;
+    ///
+    /// let composers = db.query(query!(century == 19))?;
+    /// ```
+    pub fn with_computed_index<F>(mut self, name: impl AsRef<str>, f: F) -> Result<Self, JasonError>
+    where
+        F: Fn(&Value) -> Value + Send + 'static,
+    {
+        let name = name.as_ref().to_string();
+        self.computed_indexes.insert(name.clone(), Box::new(f));
+        self.sorted_indexes.remove(&name);
+
+        let indexes = build_index(
+            &mut self.source,
+            &self.computed_indexes,
+            &name,
+            &self.primary_indexes,
+        )?;
+        self.secondary_indexes.insert(name, indexes);
+
+        Ok(self)
+    }
+
+    /// Configures the database to use the given secondary index, checking that the field exists.
+    ///
+    /// This is the same as `with_index`, except that it returns `Err(JasonError::UnknownField)` if the database is not empty
+    ///   but no entry has a non-null value for the field, which usually means that the field name is wrong.
+    pub fn with_index_strict(mut self, field: impl AsRef<str>) -> Result<Self, JasonError> {
+        let field = field.as_ref().to_string();
+        self.computed_indexes.remove(&field);
+        self.sorted_indexes.remove(&field);
         let indexes = self.source.index_on(&field, &self.primary_indexes)?;
+
+        quiet_assert(
+            self.primary_indexes.is_empty() || indexes.keys().any(|v| *v != Value::Null),
+            JasonError::UnknownField,
+        )?;
+
         self.secondary_indexes.insert(field, indexes);
 
         Ok(self)
     }
 
+    /// Configures the database to use a sorted secondary index on the given field, so that range queries can be answered
+    ///   by scanning only the indexed values within the range.
+    ///
+    /// This is the same as `with_index`, but the distinct values of the field are also kept in order. Queries using `>`,
+    ///   `>=`, `<`, `<=` or `in` on the field then only visit the values which could match, rather than every distinct value,
+    ///   at the cost of some extra memory and a little extra work on every write. This is worthwhile for fields with many
+    ///   distinct values which are queried by range, such as timestamps. Equality queries use the index as normal.
+    ///
+    /// ## Example
+    /// ```
+    /// let mut db = Database::new("readings.jdb")?.with_sorted_index("time")?;
+    /// let recent = db.query(query!(time >= 1700000000))?;
+    /// ```
+    pub fn with_sorted_index(self, field: impl AsRef<str>) -> Result<Self, JasonError> {
+        let field = field.as_ref().to_string();
+        let mut database = self.with_index(&field)?;
+
+        let sorted = sorted_values(&database.secondary_indexes[&field]);
+        database.sorted_indexes.insert(field, sorted);
+
+        Ok(database)
+    }
+
+    /// Configures the database to sort the keys of JSON objects before they are written.
+    ///
+    /// This ensures that logically-equal values are byte-equal on disk, but it changes the order of keys in the stored JSON.
+    pub fn with_sorted_keys(mut self) -> Self {
+        self.sort_keys = true;
+        self
+    }
+
+    /// Configures the database to reject values whose JSON representation is larger than the given number of bytes.
+    ///
+    /// Writes of larger values return `Err(JasonError::ValueTooLarge)` without writing anything, including writes replicated
+    ///   from another database. By default, values can be any size.
+    pub fn with_max_value_size(mut self, bytes: usize) -> Self {
+        self.max_value_size = Some(bytes);
+        self
+    }
+
     /// Adds a synchronous replica to the database.
     ///
     /// This is useful to add persistence to an in-memory database. By having an in-memory database with a synchronous
@@ -247,150 +629,1004 @@ where
     where
         R: Replica<T>,
     {
-        self.replicas.push(Replicator::new_async(replica));
+        self.replicas.push(Replicator::new_async(replica, None));
         self
     }
 
-    /// Gets the value with the given key.
+    /// Adds an asynchronous replica to the database with a name, and starts a background thread to replicate writes to it.
     ///
-    /// Returns `Err(JasonError::InvalidKey)` if the index is not found, or another error if the source fails.
-    pub fn get(&mut self, key: impl AsRef<str>) -> Result<T, JasonError> {
-        let index = *self
-            .primary_indexes
-            .get(key.as_ref())
-            .ok_or(JasonError::InvalidKey)?;
+    /// Each asynchronous replica has its own thread, so if one fails, the others keep replicating. Once a replica has
+    ///   failed, it stops receiving writes, and every subsequent write or checkpoint returns `JasonError::NamedReplicaError`
+    ///   with its name, so you can tell which of several replicas broke.
+    ///
+    /// ## Example
+    /// ```rs
+    /// let mut db = Database::new_in_memory()
+    ///     .with_async_replica_named("local", Database::create("local_replica.jdb")?)
+    ///     .with_async_replica_named("backup", Database::create("/mnt/backup/replica.jdb")?);
+    ///
+    /// if let Err(JasonError::NamedReplicaError(name)) = db.checkpoint() {
+    ///     println!("replica {} failed", name);
+    /// }
+    /// ```
+    pub fn with_async_replica_named<R>(mut self, name: impl Into<String>, replica: R) -> Self
+    where
+        R: Replica<T>,
+    {
+        self.replicas
+            .push(Replicator::new_async(replica, Some(name.into())));
+        self
+    }
 
-        Ok(self.get_at_index(index)?.1)
+    /// Adds a synchronous replica to the database which receives values as `T` rather than as serialized JSON.
+    ///
+    /// This avoids re-parsing each value for in-process replicas which store typed values, such as another [`Database`].
+    ///   Typed replicas are replicated to alongside the JSON-based ones added with `with_replica`.
+    ///
+    /// ## Example
+    /// ```rs
+    /// let mut db = Database::new_in_memory()
+    ///     .with_typed_replica(Database::create("typed_file_replica.jdb")?);
+    /// ```
+    pub fn with_typed_replica<R>(mut self, replica: R) -> Self
+    where
+        R: TypedReplica<T>,
+    {
+        self.typed_replicas.push(Box::new(replica));
+        self
     }
 
-    /// Gets the value at the given index.
-    /// Returns both the key and the value.
-    pub(crate) fn get_at_index(&mut self, index: u64) -> Result<(String, T), JasonError> {
-        let (k, v) = self.source.read_entry(index)?;
-        let json = unsafe { String::from_utf8_unchecked(v) };
+    /// Returns an estimate of the number of bytes of memory used by the primary and secondary indexes.
+    ///
+    /// This accounts for the keys, offsets and indexed values along with the approximate overhead of the maps which hold them,
+    ///   but not for any memory used by the source itself, such as the buffer of an [`InMemory`] source.
+    pub fn index_memory_estimate(&self) -> usize {
+        let primary = map_memory_estimate(&self.primary_indexes)
+            + self
+                .primary_indexes
+                .keys()
+                .map(|k| k.capacity())
+                .sum::<usize>();
+
+        let secondary = self
+            .secondary_indexes
+            .iter()
+            .map(|(field, index)| {
+                field.capacity()
+                    + map_memory_estimate(index)
+                    + index
+                        .iter()
+                        .map(|(value, offsets)| {
+                            value_memory_estimate(value) + set_memory_estimate(offsets)
+                        })
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+
+        // Sorted indexes hold a copy of each distinct value, in B-tree nodes which are assumed to be full like those of offsets.
+        let sorted = self
+            .sorted_indexes
+            .iter()
+            .map(|(field, values)| {
+                field.capacity()
+                    + values.len().div_ceil(11) * (11 * std::mem::size_of::<OrderedValue>() + 16)
+                    + values
+                        .iter()
+                        .map(|value| value_memory_estimate(&value.0))
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+
+        primary
+            + map_memory_estimate(&self.secondary_indexes)
+            + secondary
+            + map_memory_estimate(&self.sorted_indexes)
+            + sorted
+    }
 
-        if json == "null" {
-            Err(JasonError::InvalidKey)
-        } else {
-            Ok((
-                k,
-                humphrey_json::from_str(json).map_err(|_| JasonError::JsonError)?,
-            ))
-        }
+    /// Returns the number of entries in the database.
+    ///
+    /// This does not read from the source.
+    pub fn len(&self) -> usize {
+        self.primary_indexes.len()
     }
 
-    /// Sets the value with the given key to the given value.
+    /// Returns the number of entries in the database, without reading or deserialising any values.
     ///
-    /// Updates all indexes with the new value.
-    pub fn set(&mut self, key: impl AsRef<str>, value: impl Borrow<T>) -> Result<(), JasonError> {
-        let json = humphrey_json::to_string(value.borrow());
-        let index = self.source.write_entry(key.as_ref(), json.as_bytes())?;
+    /// This is equivalent to `len`, and is much cheaper than `iter().count()`. To count the values matching a query,
+    ///   use `query_count`.
+    pub fn count(&self) -> usize {
+        self.primary_indexes.len()
+    }
 
-        // Replace the primary index and get the old index.
-        let old_index = self.primary_indexes.insert(key.as_ref().to_string(), index);
+    /// Returns `true` if the database contains no entries.
+    ///
+    /// This does not read from the source.
+    pub fn is_empty(&self) -> bool {
+        self.primary_indexes.is_empty()
+    }
 
-        // Get the old value for secondary indexes.
-        let old_value = if let Some(old_index) = old_index {
-            Some(self.get_at_index(old_index)?.1.to_json())
-        } else {
-            None
-        };
+    /// Returns an iterator over the keys in the database, in no particular order.
+    ///
+    /// This does not read from the source.
+    pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.primary_indexes.keys().map(String::as_str)
+    }
 
-        for (index_path, indexes) in &mut self.secondary_indexes {
-            // Get the value used for the secondary index.
-            let indexed_value = indexing::get_value(index_path, &value.borrow().to_json());
+    /// Returns `true` if the database contains a value with the given key.
+    ///
+    /// This does not read from the source.
+    pub fn contains_key(&self, key: impl AsRef<str>) -> bool {
+        self.primary_indexes.contains_key(key.as_ref())
+    }
+
+    /// Adds a synchronous replica to the database, first bringing it up to date by replicating every entry written
+    ///   at or after the given offset.
+    ///
+    /// Use an offset of zero to replicate the entire history of the database to a fresh replica, or the offset returned
+    ///   by a previous call to `replicate_from` to resume replication to an existing one.
+    ///
+    /// ## Example
+    /// ```rs
+    /// let mut db = Database::open("database.jdb")?
+    ///     .with_replica_from(Database::create("backup.jdb")?, 0)?;
+    /// ```
+    pub fn with_replica_from<R>(
+        mut self,
+        mut replica: R,
+        since_offset: u64,
+    ) -> Result<Self, JasonError>
+    where
+        R: Replica<T>,
+    {
+        self.replicate_from(&mut replica, since_offset)?;
 
-            let set = indexes
-                .entry(indexed_value.clone())
-                .or_insert_with(BTreeSet::new);
+        Ok(self.with_replica(replica))
+    }
 
-            // If the entire JSON value has changed but the secondary index value hasn't, remove the old index
-            //   from the existing list.
-            if let Some(old_index) = old_index {
-                set.remove(&old_index);
-            }
+    /// Adds an asynchronous replica to the database, first bringing it up to date by replicating every entry written
+    ///   at or after the given offset.
+    ///
+    /// The existing entries are replicated by the background thread, so this returns as soon as they have been read.
+    pub fn with_async_replica_from<R>(
+        mut self,
+        replica: R,
+        since_offset: u64,
+    ) -> Result<Self, JasonError>
+    where
+        R: Replica<T>,
+    {
+        let mut replicator = Replicator::new_async(replica, None);
+        self.replay_from(since_offset, |k, v| match v {
+            Some(v) => replicator.set(k, v),
+            None => replicator.delete(k),
+        })?;
+        self.replicas.push(replicator);
 
-            // Add the new index to the list.
-            set.insert(index);
+        Ok(self)
+    }
 
-            // If the value has changed, check if the indexed value has also changed.
-            if let Some(old_value) = &old_value {
-                let old_indexed_value = indexing::get_value(index_path, old_value);
+    /// Replicates every entry written at or after the given offset to the replica, including overwritten values and deletions,
+    ///   in the order in which they were written.
+    ///
+    /// The offset must be zero, the offset of an entry, or an offset previously returned by this function.
+    ///   Returns the high-water offset, which can be passed to a subsequent call to replicate only the entries written since.
+    ///
+    /// **Note:** offsets are invalidated by compaction and migration.
+    pub fn replicate_from<R>(
+        &mut self,
+        replica: &mut R,
+        since_offset: u64,
+    ) -> Result<u64, JasonError>
+    where
+        R: Replica<T>,
+    {
+        self.replay_from(since_offset, |k, v| match v {
+            Some(v) => replica.set(k, v),
+            None => replica.delete(k),
+        })
+    }
 
-                if old_indexed_value != indexed_value {
-                    let set = indexes
-                        .entry(old_indexed_value)
-                        .or_insert_with(BTreeSet::new);
+    /// Calls the function with the key and JSON value of every entry written at or after the given offset,
+    ///   where a value of `None` represents a deletion.
+    /// Returns the offset at which the next entry will be written.
+    fn replay_from<F>(&mut self, since_offset: u64, mut f: F) -> Result<u64, JasonError>
+    where
+        F: FnMut(&str, Option<&str>) -> Result<(), JasonError>,
+    {
+        let version = self.source.format_version();
 
-                    // Remove the old index from the list.
-                    set.remove(&old_index.unwrap());
-                }
-            }
-        }
+        for entry in self.source.iter_raw_from(since_offset) {
+            let (k, v, _) = entry?;
 
-        for replica in &mut self.replicas {
-            replica.set(key.as_ref(), &json)?;
+            if is_tombstone(version, &v) {
+                f(&k, None)?;
+            } else {
+                let json = unsafe { String::from_utf8_unchecked(v) };
+                f(&k, Some(&json))?;
+            }
         }
 
-        Ok(())
+        Ok(self.source.size())
     }
 
-    /// Sets the value with the given key to the given raw bytes.
+    /// Registers a function to be called whenever a value is set or deleted.
     ///
-    /// ## Panics
-    /// This function will panic if there are any secondary indexes, as these cannot be updated
-    ///   from raw bytes.
-    pub(crate) fn set_raw(&mut self, key: &str, value: &[u8]) -> Result<(), JasonError> {
-        quiet_assert(self.secondary_indexes.is_empty(), JasonError::Index)?;
-
-        let index = self.source.write_entry(key, value)?;
-        self.primary_indexes.insert(key.to_string(), index);
-
-        Ok(())
+    /// The function is called synchronously after the write has succeeded and been replicated, and it receives the typed value,
+    ///   so it is a more convenient alternative to a [`Replica`] for in-process side effects such as updating a search index.
+    ///   Writes which are themselves replicated from another database do not trigger it.
+    ///
+    /// ## Example
+    /// ```
+    /// db.on_change(|event| match event {
+    ///     ChangeEvent::Set { key, value } => println!("{} was set to {:?}", key, value),
+    ///     ChangeEvent::Delete { key } => println!("{} was deleted", key),
+    /// });
+    /// ```
+    pub fn on_change<F>(&mut self, f: F)
+    where
+        F: FnMut(ChangeEvent<'_, T>) + Send + 'static,
+    {
+        self.observers.push(Box::new(f));
     }
 
-    /// Deletes the value with the given key.
+    /// Gets the value with the given key.
     ///
-    /// This appends a null value to the end of the database, and updates all indexes.
-    pub fn delete(&mut self, key: impl AsRef<str>) -> Result<(), JasonError> {
-        let index = self
+    /// Returns `Err(JasonError::InvalidKey)` if the index is not found, or another error if the source fails.
+    pub fn get(&mut self, key: impl AsRef<str>) -> Result<T, JasonError> {
+        let index = *self
             .primary_indexes
-            .remove(key.as_ref())
+            .get(key.as_ref())
             .ok_or(JasonError::InvalidKey)?;
 
-        let value = self.get_at_index(index)?.1.to_json();
+        Ok(self.get_at_offset(index)?.1)
+    }
 
-        for (index_path, indexes) in &mut self.secondary_indexes {
-            let indexed_value = indexing::get_value(index_path, &value);
+    /// Gets the values with the given keys as a map of keys to values, omitting any keys which do not exist.
+    ///
+    /// The values are read in the order in which they are stored, rather than the order of the keys.
+    pub fn get_by_keys<K, I>(&mut self, keys: I) -> Result<HashMap<String, T>, JasonError>
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = K>,
+    {
+        let mut offsets = keys
+            .into_iter()
+            .filter_map(|k| self.primary_indexes.get(k.as_ref()).copied())
+            .collect::<Vec<_>>();
+
+        offsets.sort_unstable();
+        offsets.dedup();
 
-            indexes
-                .get_mut(&indexed_value)
-                .ok_or(JasonError::InvalidKey)?
-                .remove(&index);
+        Iter {
+            database: self,
+            keys: offsets.into_iter(),
         }
+        .into_map()
+    }
+
+    /// Gets the values with the given keys, returning them in the same order as the keys with `None` for any keys which
+    ///   do not exist.
+    ///
+    /// All the offsets are looked up first, then the values are read in the order in which they are stored, which avoids
+    ///   seeking back and forth through a file. A key which is given more than once is read once for each time.
+    pub fn get_many<K>(&mut self, keys: &[K]) -> Result<Vec<Option<T>>, JasonError>
+    where
+        K: AsRef<str>,
+    {
+        let mut offsets = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, k)| Some((self.primary_indexes.get(k.as_ref()).copied()?, i)))
+            .collect::<Vec<_>>();
 
-        self.source.write_entry(key.as_ref(), "null")?;
+        offsets.sort_unstable();
 
-        for replica in &mut self.replicas {
-            replica.set(key.as_ref(), "null")?;
+        let mut values = std::iter::repeat_with(|| None)
+            .take(keys.len())
+            .collect::<Vec<_>>();
+
+        for (offset, i) in offsets {
+            values[i] = Some(self.get_at_offset(offset)?.1);
         }
 
-        Ok(())
+        Ok(values)
     }
 
-    /// Executes the given query on the database.
+    /// Returns the entry for the given key, for conditionally inserting or modifying its value.
     ///
-    /// Queries are typically constructed with the `query!` macro.
-    pub fn query(&mut self, query: Query) -> Result<Iter<T, S>, JasonError> {
-        query.execute(self)
+    /// See [`Entry`] for more information.
+    pub fn entry(&mut self, key: impl Into<String>) -> Entry<'_, T, S> {
+        Entry::new(self, key.into())
     }
 
-    /// Creates an iterator over the database.
+    /// Returns the offset in the source of the entry with the given key, or `None` if it does not exist.
     ///
-    /// This only reads from the database when it is used, so is very cheap to create. It does, however,
-    ///   sort the keys so it can iterate over the database in the order in which it is stored on disk.
+    /// **Warning:** offsets are invalidated by compaction and migration, so any offsets stored externally must be
+    ///   refreshed afterwards.
+    pub fn offset_of(&self, key: impl AsRef<str>) -> Option<u64> {
+        self.primary_indexes.get(key.as_ref()).copied()
+    }
+
+    /// Gets the entry at the given offset in the source, as returned by `offset_of`.
+    /// Returns both the key and the value.
+    ///
+    /// Returns `Err(JasonError::InvalidKey)` if the entry marks a deletion, or `Err(JasonError::Index)` if the offset or
+    ///   the lengths read from it run past the end of the source. If the offset is not the start of an entry, an error
+    ///   will usually be returned, but this is not guaranteed.
+    ///
+    /// **Warning:** offsets are invalidated by compaction and migration, so any offsets stored externally must be
+    ///   refreshed afterwards.
+    pub fn get_at_offset(&mut self, offset: u64) -> Result<(String, T), JasonError> {
+        let (k, v) = self.source.read_entry(offset)?;
+
+        parse_entry(k, v)
+    }
+
+    /// Sets the value with the given key to the given value.
+    ///
+    /// Updates all indexes with the new value.
+    pub fn set(&mut self, key: impl AsRef<str>, value: impl Borrow<T>) -> Result<(), JasonError> {
+        self.set_returning_offset(key, value).map(|_| ())
+    }
+
+    /// Sets the value with the given key to the given value, only if the key does not already have a value.
+    ///
+    /// Returns `Err(JasonError::DuplicateKey)` without writing anything if the key already has a value. This is useful
+    ///   when keys are meant to be unique, such as in an event log, so that accidental overwrites are detected.
+    pub fn insert(
+        &mut self,
+        key: impl AsRef<str>,
+        value: impl Borrow<T>,
+    ) -> Result<(), JasonError> {
+        quiet_assert(
+            !self.primary_indexes.contains_key(key.as_ref()),
+            JasonError::DuplicateKey,
+        )?;
+
+        self.set(key, value)
+    }
+
+    /// Sets the value with the given key to the given value, exactly like `set`. Returns `true` if the key already had a
+    ///   value which was overwritten, or `false` if the key was new.
+    ///
+    /// ## Example
+    /// ```
+    /// let status = if db.upsert("alice", &person)? { 200 } else { 201 };
+    /// ```
+    pub fn upsert(
+        &mut self,
+        key: impl AsRef<str>,
+        value: impl Borrow<T>,
+    ) -> Result<bool, JasonError> {
+        let existed = self.primary_indexes.contains_key(key.as_ref());
+        self.set(key, value)?;
+
+        Ok(existed)
+    }
+
+    /// Sets the value with the given key to the given value, like `set`. Returns the offset of the new entry in the source,
+    ///   which can be read back with `get_at_offset`.
+    ///
+    /// **Warning:** offsets are invalidated by compaction and migration, so any offsets stored externally must be
+    ///   refreshed afterwards.
+    pub fn set_returning_offset(
+        &mut self,
+        key: impl AsRef<str>,
+        value: impl Borrow<T>,
+    ) -> Result<u64, JasonError> {
+        // The value is converted to JSON once, and used both to write the entry and to update the secondary indexes.
+        self.set_json(key.as_ref(), value.borrow().to_json(), Some(value.borrow()))
+    }
+
+    /// Sets the value with the given key to the given JSON value, without converting it to `T`.
+    ///
+    /// Secondary indexes are updated from the JSON value directly. If the database has typed replicas or change
+    ///   observers, the value must be converted to `T` for them, so `Err(JasonError::JsonError)` is returned without
+    ///   writing anything if it doesn't fit. Otherwise, the value is not checked until it is read back as `T`.
+    pub fn set_value(&mut self, key: impl AsRef<str>, value: &Value) -> Result<(), JasonError> {
+        self.set_untyped(key.as_ref(), value.clone())
+    }
+
+    /// Modifies the value with the given key with `f` and writes it back to the database.
+    ///
+    /// Returns `Err(JasonError::InvalidKey)` if the key has no value, in which case `f` is not called. The new value is
+    ///   written as it is by `set`, so secondary indexes, replicas and observers are all updated.
+    ///
+    /// ## Example
+    /// ```
+    /// db.update("visits", |counter| counter.count += 1)?;
+    /// ```
+    pub fn update<F>(&mut self, key: impl AsRef<str>, f: F) -> Result<(), JasonError>
+    where
+        F: FnOnce(&mut T),
+    {
+        let mut value = self.get(key.as_ref())?;
+        f(&mut value);
+
+        self.set(key, &value)
+    }
+
+    /// Sets a single field of the value with the given key, without converting the value to `T`.
+    ///
+    /// The field is given as a dot-separated path, like indexes. Missing fields along the path are created, and `null`
+    ///   ones are replaced with empty objects. Returns `Err(JasonError::InvalidKey)` if the key has no value, and
+    ///   `Err(JasonError::JsonError)` if part of the path is neither an object nor `null`.
+    ///
+    /// The whole value is still rewritten, since entries are never modified in place, but it is only parsed as JSON.
+    ///   Secondary indexes, replicas and observers are updated as they are by `set_value`.
+    ///
+    /// ## Example
+    /// ```
+    /// db.patch("alice", "address.city", Value::String("London".to_string()))?;
+    /// ```
+    pub fn patch(
+        &mut self,
+        key: impl AsRef<str>,
+        path: &str,
+        new_value: Value,
+    ) -> Result<(), JasonError> {
+        let offset = *self
+            .primary_indexes
+            .get(key.as_ref())
+            .ok_or(JasonError::InvalidKey)?;
+
+        let mut value = self.get_value_at_offset(offset)?;
+        let field = indexing::get_value_mut(path, &mut value).ok_or(JasonError::JsonError)?;
+        *field = new_value;
+
+        self.set_untyped(key.as_ref(), value)
+    }
+
+    /// Sets the value with the given key to the given JSON value, converting it to `T` only if it is needed.
+    fn set_untyped(&mut self, key: &str, value: Value) -> Result<(), JasonError> {
+        if self.typed_replicas.is_empty() && self.observers.is_empty() {
+            self.set_json(key, value, None).map(|_| ())
+        } else {
+            let typed = T::from_json(&value).map_err(|_| JasonError::JsonError)?;
+            self.set_json(key, value, Some(&typed)).map(|_| ())
+        }
+    }
+
+    /// Writes the given JSON value to the source and updates the indexes, replicas and observers.
+    ///
+    /// The typed value is only used for typed replicas and observers, so it may only be `None` if there are neither.
+    fn set_json(
+        &mut self,
+        key: &str,
+        mut new_value: Value,
+        value: Option<&T>,
+    ) -> Result<u64, JasonError> {
+        if self.sort_keys {
+            canonical::sort_object_keys(&mut new_value);
+        }
+
+        let json = new_value.serialize();
+        self.check_value_size(key, json.len())?;
+
+        // Get the old value so that it can be removed from the secondary indexes, which is not needed without any.
+        //   It is read before the new value is written, so that if it can't be read, the indexes still match the source.
+        //   It is read as JSON rather than `T`, since it may have been set with `set_value`.
+        let old_entry = match self.primary_indexes.get(key) {
+            Some(&old_index) if !self.secondary_indexes.is_empty() => {
+                Some((old_index, self.get_value_at_offset(old_index)?))
+            }
+            _ => None,
+        };
+
+        let index = self.source.write_entry(key, json.as_bytes())?;
+        insert_offset(&mut self.primary_indexes, key, index);
+
+        if !self.secondary_indexes.is_empty() {
+            for (index_path, indexes) in &mut self.secondary_indexes {
+                let mut sorted = self.sorted_indexes.get_mut(index_path);
+
+                if let Some((old_index, old_value)) = &old_entry {
+                    let old_indexed_value =
+                        index_value(&self.computed_indexes, index_path, old_value);
+                    unindex(
+                        indexes,
+                        sorted.as_deref_mut(),
+                        &old_indexed_value,
+                        *old_index,
+                    );
+                }
+
+                let indexed_value = index_value(&self.computed_indexes, index_path, &new_value);
+
+                if let Some(sorted) = sorted {
+                    sorted.insert(OrderedValue(indexed_value.clone()));
+                }
+
+                indexes.entry(indexed_value).or_default().insert(index);
+            }
+        }
+
+        // Every replica is written to even if an earlier one fails, so that one broken replica doesn't hold back the others.
+        let mut result = Ok(());
+
+        for replica in &mut self.replicas {
+            result = result.and(replica.set(key, &json));
+        }
+
+        if let Some(value) = value {
+            for replica in &mut self.typed_replicas {
+                result = result.and(replica.set_typed(key, value));
+            }
+        }
+
+        result?;
+
+        if let Some(value) = value {
+            for observer in &mut self.observers {
+                observer(ChangeEvent::Set { key, value });
+            }
+        }
+
+        // Evicting values moves the new entry, so its offset must be looked up again.
+        if self.enforce_capacity()? {
+            return self.offset_of(key).ok_or(JasonError::Index);
+        }
+
+        Ok(index)
+    }
+
+    /// Reads the JSON value of the entry at the given offset, without converting it to `T`.
+    fn get_value_at_offset(&mut self, offset: u64) -> Result<Value, JasonError> {
+        let (_, v) = self.source.read_entry(offset)?;
+        quiet_assert(!v.is_empty(), JasonError::InvalidKey)?;
+
+        let json = unsafe { String::from_utf8_unchecked(v) };
+        Value::parse(json).map_err(|_| JasonError::JsonError)
+    }
+
+    /// Sets the value with the given binary key, which is stored hex-encoded as described in the [`keys`] module.
+    ///
+    /// The key can be read back with `get_bytes_key`, and keys returned by other methods can be decoded with `keys::decode`.
+    pub fn set_bytes_key(&mut self, key: &[u8], value: impl Borrow<T>) -> Result<(), JasonError> {
+        self.set(keys::encode(key), value)
+    }
+
+    /// Gets the value with the given binary key, as set by `set_bytes_key`.
+    pub fn get_bytes_key(&mut self, key: &[u8]) -> Result<T, JasonError> {
+        self.get(keys::encode(key))
+    }
+
+    /// Deletes the value with the given binary key, as set by `set_bytes_key`.
+    pub fn delete_bytes_key(&mut self, key: &[u8]) -> Result<(), JasonError> {
+        self.delete(keys::encode(key))
+    }
+
+    /// Sets each key in the database to its corresponding value, stopping at the first error.
+    ///
+    /// Any values set before the error occurred will remain in the database.
+    pub fn try_extend<K, I>(&mut self, iter: I) -> Result<(), JasonError>
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = (K, T)>,
+    {
+        for (k, v) in iter {
+            self.set(k, v)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes each operation in turn, returning the result of each one in the same order.
+    ///
+    /// Unlike `try_extend`, an error doesn't stop the batch, so later operations are still executed and the results
+    ///   show which ones succeeded. Each operation sees the effects of the ones before it.
+    ///
+    /// ## Example
+    /// ```
+    /// let results = db.batch(vec![
+    ///     Op::Set("alice".to_string(), alice),
+    ///     Op::Get("alice".to_string()),
+    ///     Op::Delete("bob".to_string()),
+    /// ]);
+    ///
+    /// assert_eq!(results[1], Ok(OpResult::Get(alice)));
+    /// ```
+    pub fn batch(&mut self, ops: Vec<Op<T>>) -> Vec<Result<OpResult<T>, JasonError>> {
+        ops.into_iter()
+            .map(|op| match op {
+                Op::Get(key) => self.get(key).map(OpResult::Get),
+                Op::Set(key, value) => self.set(key, value).map(|_| OpResult::Set),
+                Op::Delete(key) => self.delete(key).map(|_| OpResult::Delete),
+            })
+            .collect()
+    }
+
+    /// Sets the value with the given key to the given raw JSON bytes.
+    ///
+    /// The JSON is parsed and re-serialized in canonical minified form before it is written, so formatting
+    ///   differences in the raw JSON do not affect what is stored.
+    ///
+    /// ## Panics
+    /// This function will panic if there are any secondary indexes, as these cannot be updated
+    ///   from raw bytes.
+    pub(crate) fn set_raw(&mut self, key: &str, value: &[u8]) -> Result<(), JasonError> {
+        quiet_assert(self.secondary_indexes.is_empty(), JasonError::Index)?;
+
+        let value = canonical::canonicalise(value, self.sort_keys)?;
+        self.check_value_size(key, value.len())?;
+        let index = self.source.write_entry(key, value)?;
+        insert_offset(&mut self.primary_indexes, key, index);
+        self.enforce_capacity()?;
+
+        Ok(())
+    }
+
+    /// Checks that a serialised value of the given size is not larger than the maximum value size, and that its entry
+    ///   would fit within the capacity of the source.
+    fn check_value_size(&self, key: &str, size: usize) -> Result<(), JasonError> {
+        if let Some(capacity) = self.source.capacity() {
+            let entry_size = key.len() as u64 + size as u64 + 16;
+            quiet_assert(entry_size <= capacity, JasonError::ValueTooLarge)?;
+        }
+
+        match self.max_value_size {
+            Some(max) => quiet_assert(size <= max, JasonError::ValueTooLarge),
+            None => Ok(()),
+        }
+    }
+
+    /// Evicts the least recently written values until the source is within its capacity, if it has one.
+    ///
+    /// Returns `true` if the source was compacted, which invalidates any offsets.
+    fn enforce_capacity(&mut self) -> Result<bool, JasonError> {
+        let capacity = match self.source.capacity() {
+            Some(capacity) if self.source.size() > capacity => capacity,
+            _ => return Ok(false),
+        };
+
+        let mut entries = self
+            .primary_indexes
+            .iter()
+            .map(|(k, &offset)| (offset, k.clone()))
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+
+        let mut sizes = Vec::with_capacity(entries.len());
+        for (offset, _) in &entries {
+            sizes.push(self.source.entry_size(*offset)?);
+        }
+
+        // Overwritten and deleted entries will be compacted away, so only the live entries count towards the capacity.
+        let mut live_size = sizes.iter().sum::<u64>();
+        let mut evicted = Vec::new();
+
+        for ((_, key), size) in entries.into_iter().zip(sizes) {
+            if live_size <= capacity {
+                break;
+            }
+
+            live_size -= size;
+            self.primary_indexes.remove(&key);
+            evicted.push(key);
+        }
+
+        trace!(
+            tracing::Level::DEBUG,
+            evicted = evicted.len(),
+            "evicting values to stay within capacity"
+        );
+
+        // The secondary indexes are rebuilt by compaction, so only the replicas and observers need to be told.
+        self.compact_local(S::compact)?;
+
+        let mut result = Ok(());
+
+        for key in &evicted {
+            for replica in &mut self.replicas {
+                result = result.and(replica.delete(key));
+            }
+
+            for replica in &mut self.typed_replicas {
+                result = result.and(replica.delete_typed(key));
+            }
+        }
+
+        result?;
+
+        for key in &evicted {
+            for observer in &mut self.observers {
+                observer(ChangeEvent::Delete { key });
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Deletes the value with the given key without reading it.
+    ///
+    /// ## Panics
+    /// This function will panic if there are any secondary indexes, as these cannot be updated
+    ///   without reading the value.
+    pub(crate) fn delete_raw(&mut self, key: &str) -> Result<(), JasonError> {
+        quiet_assert(self.secondary_indexes.is_empty(), JasonError::Index)?;
+
+        self.source.write_entry(key, b"")?;
+        self.primary_indexes.remove(key);
+        self.enforce_capacity()?;
+
+        Ok(())
+    }
+
+    /// Deletes the value with the given key.
+    ///
+    /// This appends an empty entry to the end of the database to mark the key as deleted, and updates all indexes.
+    ///   Since an empty entry is never valid JSON, this is distinct from setting the value to `null`.
+    pub fn delete(&mut self, key: impl AsRef<str>) -> Result<(), JasonError> {
+        let index = *self
+            .primary_indexes
+            .get(key.as_ref())
+            .ok_or(JasonError::InvalidKey)?;
+
+        let value = self.get_value_at_offset(index)?;
+
+        // The indexes are only updated once the deletion has been written, so a failed write leaves the key intact.
+        self.source.write_entry(key.as_ref(), b"")?;
+        self.primary_indexes.remove(key.as_ref());
+
+        for (index_path, indexes) in &mut self.secondary_indexes {
+            let indexed_value = index_value(&self.computed_indexes, index_path, &value);
+
+            quiet_assert(
+                unindex(
+                    indexes,
+                    self.sorted_indexes.get_mut(index_path),
+                    &indexed_value,
+                    index,
+                ),
+                JasonError::InvalidKey,
+            )?;
+        }
+
+        let mut result = Ok(());
+
+        for replica in &mut self.replicas {
+            result = result.and(replica.delete(key.as_ref()));
+        }
+
+        for replica in &mut self.typed_replicas {
+            result = result.and(replica.delete_typed(key.as_ref()));
+        }
+
+        result?;
+
+        for observer in &mut self.observers {
+            observer(ChangeEvent::Delete { key: key.as_ref() });
+        }
+
+        self.enforce_capacity()?;
+
+        Ok(())
+    }
+
+    /// Deletes the value with the given key by physically removing every entry for it from the source, instead of appending
+    ///   an empty entry like `delete`.
+    ///
+    /// This keeps the size of an in-memory database bounded under heavy deletion, at the cost of scanning the source and
+    ///   moving every later entry. If the source cannot remove entries, as is the case for [`FileSource`], this is
+    ///   equivalent to `delete`.
+    ///
+    /// **Warning:** like compaction, this invalidates the offsets of later entries, including those used for incremental replication.
+    pub fn remove(&mut self, key: impl AsRef<str>) -> Result<(), JasonError> {
+        let index = *self
+            .primary_indexes
+            .get(key.as_ref())
+            .ok_or(JasonError::InvalidKey)?;
+
+        let value = self.get_value_at_offset(index)?;
+
+        // Earlier versions of the key must also be removed, otherwise they would be restored when the indexes are reloaded.
+        let mut offsets = Vec::new();
+
+        for entry in self.source.iter_raw() {
+            let (k, _, offset) = entry?;
+
+            if k == key.as_ref() {
+                offsets.push(offset);
+            }
+        }
+
+        // Entries are removed from the end so that the offsets of the remaining ones are unaffected.
+        let mut removed = Vec::with_capacity(offsets.len());
+
+        for (i, &offset) in offsets.iter().rev().enumerate() {
+            match self.source.remove_entry(offset) {
+                Ok(Some(len)) => removed.push((offset, len)),
+                Ok(None) if i == 0 => return self.delete(key),
+                Ok(None) => {
+                    self.reload()?;
+                    return Err(JasonError::Index);
+                }
+                Err(e) => {
+                    self.reload()?;
+                    return Err(e);
+                }
+            }
+        }
+
+        removed.reverse();
+
+        let shift = |offset: u64| {
+            offset
+                - removed
+                    .iter()
+                    .take_while(|(removed_offset, _)| *removed_offset < offset)
+                    .map(|(_, len)| len)
+                    .sum::<u64>()
+        };
+
+        self.primary_indexes.remove(key.as_ref());
+
+        for offset in self.primary_indexes.values_mut() {
+            *offset = shift(*offset);
+        }
+
+        for (index_path, indexes) in &mut self.secondary_indexes {
+            let indexed_value = index_value(&self.computed_indexes, index_path, &value);
+
+            quiet_assert(
+                unindex(
+                    indexes,
+                    self.sorted_indexes.get_mut(index_path),
+                    &indexed_value,
+                    index,
+                ),
+                JasonError::InvalidKey,
+            )?;
+
+            for offsets in indexes.values_mut() {
+                *offsets = offsets.iter().map(|&offset| shift(offset)).collect();
+            }
+        }
+
+        let mut result = Ok(());
+
+        for replica in &mut self.replicas {
+            result = result.and(replica.delete(key.as_ref()));
+        }
+
+        for replica in &mut self.typed_replicas {
+            result = result.and(replica.delete_typed(key.as_ref()));
+        }
+
+        result?;
+
+        for observer in &mut self.observers {
+            observer(ChangeEvent::Delete { key: key.as_ref() });
+        }
+
+        Ok(())
+    }
+
+    /// Gets the keys of all entries where the given field is equal to the given value, in the order in which they are stored.
+    ///
+    /// If the field is indexed, this is answered from the index, so only the matching entries are read from the source
+    ///   to find their keys. Otherwise, every entry is read and parsed as JSON, but never deserialized into `T`.
+    pub fn keys_with_value(
+        &mut self,
+        field: impl AsRef<str>,
+        value: &Value,
+    ) -> Result<Vec<String>, JasonError> {
+        let mut matches: Vec<(u64, String)> =
+            if let Some(index) = self.secondary_indexes.get(field.as_ref()) {
+                let mut matches = Vec::new();
+
+                for &offset in index.get(value).into_iter().flatten() {
+                    let (k, _) = self.source.read_entry(offset)?;
+                    matches.push((offset, k));
+                }
+
+                matches
+            } else {
+                let mut matches = Vec::new();
+
+                for &offset in self.primary_indexes.values() {
+                    let (k, v) = self.source.read_entry(offset)?;
+                    let json = unsafe { String::from_utf8_unchecked(v) };
+                    let json = Value::parse(json).map_err(|_| JasonError::JsonError)?;
+
+                    if indexing::get_value(field.as_ref(), &json) == *value {
+                        matches.push((offset, k));
+                    }
+                }
+
+                matches
+            };
+
+        matches.sort_unstable_by_key(|(offset, _)| *offset);
+
+        Ok(matches.into_iter().map(|(_, k)| k).collect())
+    }
+
+    /// Checks whether any entry has the given field equal to the given value.
+    ///
+    /// If the field is indexed, this only checks the index. Otherwise, entries are read and parsed as JSON until the
+    ///   first match is found.
+    pub fn exists_where(
+        &mut self,
+        field: impl AsRef<str>,
+        value: &Value,
+    ) -> Result<bool, JasonError> {
+        if let Some(index) = self.secondary_indexes.get(field.as_ref()) {
+            return Ok(index.get(value).is_some_and(|offsets| !offsets.is_empty()));
+        }
+
+        for &offset in self.primary_indexes.values() {
+            let (_, v) = self.source.read_entry(offset)?;
+            let json = unsafe { String::from_utf8_unchecked(v) };
+            let json = Value::parse(json).map_err(|_| JasonError::JsonError)?;
+
+            if indexing::get_value(field.as_ref(), &json) == *value {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Gets every key which starts with the given prefix, in the order in which they are stored.
+    pub(crate) fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut keys = self
+            .primary_indexes
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .collect::<Vec<_>>();
+
+        keys.sort_unstable_by_key(|(_, offset)| **offset);
+
+        keys.into_iter().map(|(k, _)| k.clone()).collect()
+    }
+
+    /// Executes the given query on the database.
+    ///
+    /// Queries are typically constructed with the `query!` macro.
+    pub fn query(&mut self, query: Query) -> Result<Iter<'_, T, S>, JasonError> {
+        query.execute(self)
+    }
+
+    /// Executes the given query on the database, yielding matching values lazily as they are found.
+    ///
+    /// This is useful for finding the first few matches in a large database, since the scan stops as soon as the iterator does.
+    ///   See `Query::stream` for more information.
+    pub fn stream_query(
+        &mut self,
+        query: Query,
+    ) -> Result<impl Iterator<Item = Result<(String, T), JasonError>> + '_, JasonError> {
+        query.stream(self)
+    }
+
+    /// Executes the given compiled query on the database.
+    ///
+    /// Compiled queries are created with `Query::compile`, and are useful for executing the same query many times.
+    pub fn query_compiled(&mut self, query: &CompiledQuery) -> Result<Iter<'_, T, S>, JasonError> {
+        query.execute(self)
+    }
+
+    /// Executes the given query on the database, returning only the keys of the matching values.
+    ///
+    /// This is cheaper than `query` when the values themselves aren't needed, especially if every predicate is indexed.
+    pub fn query_keys(&mut self, query: Query) -> Result<Vec<String>, JasonError> {
+        query.execute_keys(self)
+    }
+
+    /// Executes the given query on the database, returning only the number of matching values.
+    ///
+    /// If every predicate is indexed, this doesn't read any values. See `Query::count` for details.
+    pub fn query_count(&mut self, query: Query) -> Result<usize, JasonError> {
+        query.count(self)
+    }
+
+    /// Creates an iterator over the database.
+    ///
+    /// This only reads from the database when it is used, so is very cheap to create. It does, however,
+    ///   sort the keys so it can iterate over the database in the order in which it is stored on disk.
     ///   To avoid this behaviour, use the `iter_unordered` method instead.
-    pub fn iter(&mut self) -> Iter<T, S> {
+    pub fn iter(&mut self) -> Iter<'_, T, S> {
         let mut keys = self.primary_indexes.values().cloned().collect::<Vec<_>>();
 
         keys.sort_unstable();
@@ -401,10 +1637,89 @@ where
         }
     }
 
+    /// Creates an iterator over the database which only needs a shared reference, so other shared references to the database
+    ///   can be held while iterating.
+    ///
+    /// This iterates in the same order as `iter`, but it requires a source which can be read through a shared reference,
+    ///   such as [`FileSource`] or [`InMemory`].
+    pub fn iter_ref(&self) -> IterRef<'_, T, S>
+    where
+        S: SharedRead,
+    {
+        let mut keys = self.primary_indexes.values().cloned().collect::<Vec<_>>();
+
+        keys.sort_unstable();
+
+        IterRef {
+            database: self,
+            keys: keys.into_iter(),
+        }
+    }
+
+    /// Creates an iterator over the entries stored after the given cursor, or over the whole database if it is `None`.
+    ///
+    /// The returned cursor marks the last entry the iterator will return, and is `None` if there are no entries after
+    ///   the given cursor. Passing it to the next call resumes where this one finished, so a client can page through a
+    ///   database while it is being written to without skipping or repeating entries. See [`Cursor`] for more information.
+    ///
+    /// ## Example
+    /// ```
+    /// let (page, cursor) = db.iter_from(None);
+    /// let first_page = page.collect::<Result<Vec<_>, JasonError>>()?;
+    ///
+    /// // Later, with values written in between...
+    /// let (page, cursor) = db.iter_from(cursor);
+    /// ```
+    pub fn iter_from(&mut self, cursor: Option<Cursor>) -> (Iter<'_, T, S>, Option<Cursor>) {
+        let after = cursor.map(|cursor| cursor.offset);
+
+        let mut keys = self
+            .primary_indexes
+            .values()
+            .filter(|&&offset| after.is_none_or(|after| offset > after))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        keys.sort_unstable();
+
+        let cursor = keys.last().map(|&offset| Cursor { offset });
+
+        let iter = Iter {
+            database: self,
+            keys: keys.into_iter(),
+        };
+
+        (iter, cursor)
+    }
+
+    /// Creates an iterator over every entry whose key starts with the given prefix, in the order in which they are stored.
+    pub fn iter_prefix(&mut self, prefix: impl AsRef<str>) -> Iter<'_, T, S> {
+        let mut keys = self
+            .primary_indexes
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix.as_ref()))
+            .map(|(_, offset)| *offset)
+            .collect::<Vec<_>>();
+
+        keys.sort_unstable();
+
+        Iter {
+            database: self,
+            keys: keys.into_iter(),
+        }
+    }
+
+    /// Returns a view of the collection with the given name, whose keys are stored in the database with the prefix `name/`.
+    ///
+    /// See [`Collection`] for more information.
+    pub fn collection(&mut self, name: impl AsRef<str>) -> Collection<'_, T, S> {
+        Collection::new(self, name)
+    }
+
     /// Creates an iterator over the database, but does not sort the keys.
     ///
     /// This is quicker to create, but will be slower to iterate over since the disk will not be read sequentially.
-    pub fn iter_unordered(&mut self) -> Iter<T, S> {
+    pub fn iter_unordered(&mut self) -> Iter<'_, T, S> {
         let keys = self
             .primary_indexes
             .values()
@@ -418,19 +1733,360 @@ where
         }
     }
 
+    /// Creates an iterator over the database which reads the source sequentially from start to finish.
+    ///
+    /// Rather than reading each entry individually like `iter`, this reads every entry in the source in one buffered pass,
+    ///   skipping any overwritten or deleted ones. For file-based databases, this is much faster for full scans, but it reads
+    ///   more data than `iter` when many entries are dead, so it works best on recently-compacted databases.
+    pub fn iter_sequential(
+        &mut self,
+    ) -> impl Iterator<Item = Result<(String, T), JasonError>> + '_ {
+        let primary_indexes = &self.primary_indexes;
+
+        self.source.iter_raw().filter_map(move |entry| match entry {
+            Ok((k, v, offset)) if primary_indexes.get(&k) == Some(&offset) => {
+                let json = unsafe { String::from_utf8_unchecked(v) };
+
+                Some(
+                    humphrey_json::from_str(json)
+                        .map(|v| (k, v))
+                        .map_err(|_| JasonError::JsonError),
+                )
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Returns every version of the value with the given key which is still recorded in the source, in the order in which
+    ///   they were written, by scanning the source.
+    ///
+    /// Since the source is append-only, overwritten values remain in it until the database is compacted, which removes
+    ///   all but the current version. Deletions are not included, so the versions from before and after a key was
+    ///   deleted and set again are all returned.
+    pub fn history(&mut self, key: impl AsRef<str>) -> Result<Vec<T>, JasonError> {
+        let mut versions = Vec::new();
+        let version = self.source.format_version();
+
+        for entry in self.source.iter_raw() {
+            let (k, v, _) = entry?;
+
+            if k == key.as_ref() && !is_tombstone(version, &v) {
+                let json = unsafe { String::from_utf8_unchecked(v) };
+                versions.push(humphrey_json::from_str(json).map_err(|_| JasonError::JsonError)?);
+            }
+        }
+
+        Ok(versions)
+    }
+
+    /// Returns the keys which have been deleted, in the order in which they were deleted, by scanning the source for keys whose
+    ///   latest entry marks them as deleted.
+    ///
+    /// Keys which were deleted and then set again are not included. Compaction removes all record of deleted keys, so
+    ///   only deletions since the last compaction are returned.
+    pub fn deleted_keys(&mut self) -> Result<Vec<String>, JasonError> {
+        let mut deleted: HashMap<String, u64> = HashMap::new();
+        let version = self.source.format_version();
+
+        for entry in self.source.iter_raw() {
+            let (k, v, offset) = entry?;
+
+            if is_tombstone(version, &v) {
+                deleted.insert(k, offset);
+            } else {
+                deleted.remove(&k);
+            }
+        }
+
+        let mut deleted = deleted.into_iter().collect::<Vec<_>>();
+        deleted.sort_unstable_by_key(|(_, offset)| *offset);
+
+        Ok(deleted.into_iter().map(|(k, _)| k).collect())
+    }
+
+    /// Creates an iterator over the database, ordered by the value of the given indexed field.
+    ///
+    /// Values are ordered `null < bool < number < string < array < object`, and entries with equal values
+    ///   are yielded in the order in which they are stored. Since the iterator is double-ended, it can be
+    ///   reversed to iterate in descending order.
+    ///
+    /// Returns `Err(JasonError::Index)` if the field is not indexed.
+    ///
+    /// ## Example
+    /// ```
+    /// let mut db = Database::new(source)?.with_index("age")?;
+    /// let oldest_ten = db.iter_by("age")?.rev().take(10);
+    /// ```
+    pub fn iter_by(&mut self, field: impl AsRef<str>) -> Result<Iter<'_, T, S>, JasonError> {
+        let index = self
+            .secondary_indexes
+            .get(field.as_ref())
+            .ok_or(JasonError::Index)?;
+
+        let mut values = index.iter().collect::<Vec<_>>();
+        values.sort_unstable_by(|(a, _), (b, _)| ordering::compare(a, b));
+
+        let keys = values
+            .into_iter()
+            .flat_map(|(_, indexes)| indexes.iter().cloned())
+            .collect::<Vec<_>>();
+
+        Ok(Iter {
+            database: self,
+            keys: keys.into_iter(),
+        })
+    }
+
+    /// Reloads the database's indexes from the source, picking up any entries which have been written to it externally,
+    ///   for example by another process appending to the same file. This is typically used with `Database::open_read_only`,
+    ///   since only one writer can have the file open at a time.
+    ///
+    /// **Warning:** readers are not coordinated with the writer, so this is only safe when the external writer
+    ///   has finished writing. If an entry is only partially written when this is called, an error will be returned and the
+    ///   existing indexes will be left unchanged.
+    ///
+    /// If the writer compacts or migrates the database, the file is replaced or rewritten, so the entries this handle has
+    ///   read are no longer where its indexes say they are. For a read-only file source, this is detected and
+    ///   `JasonError::Replaced` is returned, leaving the existing indexes unchanged, and the database must be opened again
+    ///   to read the new file. On platforms other than Unix, only a rewrite in place can be detected, so a replaced file
+    ///   keeps being read as it was when this handle opened it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(source = self.source.kind()))
+    )]
+    pub fn reload(&mut self) -> Result<(), JasonError> {
+        self.source.refresh()?;
+
+        let primary_indexes = self.source.load_indexes()?;
+        let mut secondary_indexes = HashMap::with_capacity(self.secondary_indexes.len());
+
+        for k in self.secondary_indexes.keys() {
+            let indexes = build_index(
+                &mut self.source,
+                &self.computed_indexes,
+                k,
+                &primary_indexes,
+            )?;
+            secondary_indexes.insert(k.clone(), indexes);
+        }
+
+        trace!(
+            tracing::Level::DEBUG,
+            entries = primary_indexes.len(),
+            secondary_indexes = secondary_indexes.len(),
+            size = self.source.size(),
+            "reloaded indexes"
+        );
+
+        for (k, sorted) in self.sorted_indexes.iter_mut() {
+            *sorted = sorted_values(&secondary_indexes[k]);
+        }
+
+        self.primary_indexes = primary_indexes;
+        self.secondary_indexes = secondary_indexes;
+
+        Ok(())
+    }
+
+    /// Ensures that everything written to the database so far is on stable storage, then returns the offset up to which
+    ///   the database is durable.
+    ///
+    /// This flushes the source, which for a file means calling `fsync`, and then flushes every replica, waiting for any
+    ///   pending asynchronous writes to be replicated. After a crash, entries before the returned offset are safe, and
+    ///   anything after it can be discarded with `Source::truncate_to`. For an in-memory database, this simply returns the offset.
+    pub fn checkpoint(&mut self) -> Result<u64, JasonError> {
+        self.source.flush()?;
+
+        let mut result = Ok(());
+
+        for replica in &mut self.replicas {
+            result = result.and(replica.flush());
+        }
+
+        for replica in &mut self.typed_replicas {
+            result = result.and(replica.flush());
+        }
+
+        result?;
+
+        Ok(self.source.size())
+    }
+
+    /// Ensures that everything written to the database so far is on stable storage, including on every replica.
+    ///
+    /// Without calling this, recent writes may still be held in a write buffer or by the operating system, and may be lost
+    ///   if the process or machine crashes. This is equivalent to `checkpoint`, without returning the offset.
+    ///
+    /// If the source's write buffer can't be written out, the buffered writes are lost, so the database must be reloaded
+    ///   with `reload` before it is used again. See `FileSource::with_write_buffer` for details.
+    pub fn flush(&mut self) -> Result<(), JasonError> {
+        self.checkpoint().map(|_| ())
+    }
+
     /// Performs compaction on the database.
+    ///
+    /// Replicas are compacted too, since their offsets are independent of the database's. Replicas which are themselves
+    ///   databases are compacted with `compact`, and other replicas can choose what to do by implementing `Replica::compact`.
+    ///   Every pending write is replicated before an asynchronous replica is compacted, and this waits for it to finish.
     pub fn compact(&mut self) -> Result<(), JasonError> {
-        self.source.compact(&self.primary_indexes)?;
+        self.compact_source(S::compact).map(|_| ())
+    }
+
+    /// Performs compaction on the database, returning a report of how much space was reclaimed.
+    ///
+    /// Counting the entries which are dropped requires reading the whole source before it is compacted, so this is
+    ///   slower than `compact` for large databases.
+    #[must_use = "use `compact` if the report is not needed"]
+    pub fn compact_report(&mut self) -> Result<CompactionReport, JasonError> {
+        let mut entries_before = 0;
+
+        for entry in self.source.iter_raw() {
+            entry?;
+            entries_before += 1;
+        }
+
+        let stats = self.compact_source(S::compact)?;
+
+        Ok(CompactionReport {
+            bytes_before: stats.bytes_before,
+            bytes_after: stats.bytes_after,
+            entries_kept: self.primary_indexes.len(),
+            entries_dropped: entries_before - self.primary_indexes.len(),
+        })
+    }
+
+    /// Estimates how much space compaction would reclaim, without compacting the database.
+    ///
+    /// This only reads the size of the latest entry for each key rather than the entries themselves, so it is much
+    ///   cheaper than compaction, and can be used to decide whether compaction is worthwhile. The estimate is exact
+    ///   for the built-in sources, unless the database is written to before it is compacted.
+    pub fn compaction_estimate(&mut self) -> Result<CompactionEstimate, JasonError> {
+        let mut bytes_live = 0;
+
+        for &offset in self.primary_indexes.values() {
+            bytes_live += self.source.entry_size(offset)?;
+        }
+
+        Ok(CompactionEstimate {
+            bytes_current: self.source.size(),
+            bytes_live,
+        })
+    }
+
+    /// Rebuilds the database from its own log, as a catch-all repair when the indexes or the source might be inconsistent.
+    ///
+    /// The latest entry for each key is found by reading the whole source rather than trusting the loaded indexes. Reading
+    ///   stops at the first entry which can't be read, since the entries after it can't be located, and entries which
+    ///   aren't valid values of the database's type are skipped. The remaining entries are written to a fresh source,
+    ///   which replaces the old one as it does for `compact`, and every index is rebuilt from it. Replicas are compacted,
+    ///   but skipped entries are not deleted from them, so they can still be used to recover the values.
+    ///
+    /// **Warning:** skipped entries and anything after an unreadable entry are permanently removed from the database.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(source = self.source.kind()))
+    )]
+    pub fn rebuild(&mut self) -> Result<(), JasonError> {
+        self.source.refresh()?;
+
+        let mut primary_indexes: HashMap<String, u64> = HashMap::new();
+        let version = self.source.format_version();
+
+        for entry in self.source.iter_raw() {
+            match entry {
+                Ok((key, v, _)) if is_tombstone(version, &v) => {
+                    primary_indexes.remove(&key);
+                }
+                Ok((key, _, offset)) => {
+                    primary_indexes.insert(key, offset);
+                }
+                Err(_) => break,
+            }
+        }
+
+        let source = &mut self.source;
+        primary_indexes.retain(|_, &mut offset| {
+            source
+                .read_entry(offset)
+                .and_then(|(k, v)| parse_entry::<T>(k, v))
+                .is_ok()
+        });
+
+        self.primary_indexes = primary_indexes;
+        self.compact_source(S::compact)?;
+
+        Ok(())
+    }
+
+    /// Compacts the source with the given function, such as `Source::compact`, then compacts the replicas and rebuilds the
+    ///   indexes from the source.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(source = self.source.kind()))
+    )]
+    fn compact_source(
+        &mut self,
+        compact: impl FnOnce(&mut S, &HashMap<String, u64>) -> Result<CompactionStats, JasonError>,
+    ) -> Result<CompactionStats, JasonError> {
+        let stats = self.compact_local(compact)?;
+        self.compact_replicas()?;
+
+        Ok(stats)
+    }
+
+    /// Compacts the source with the given function and rebuilds the indexes from it, without compacting the replicas.
+    fn compact_local(
+        &mut self,
+        compact: impl FnOnce(&mut S, &HashMap<String, u64>) -> Result<CompactionStats, JasonError>,
+    ) -> Result<CompactionStats, JasonError> {
+        let stats = compact(&mut self.source, &self.primary_indexes)?;
         self.primary_indexes = self.source.load_indexes()?;
 
         for (k, v) in self.secondary_indexes.iter_mut() {
-            *v = self.source.index_on(k, &self.primary_indexes)?;
+            *v = build_index(
+                &mut self.source,
+                &self.computed_indexes,
+                k,
+                &self.primary_indexes,
+            )?;
         }
 
-        Ok(())
+        for (k, sorted) in self.sorted_indexes.iter_mut() {
+            *sorted = sorted_values(&self.secondary_indexes[k]);
+        }
+
+        trace!(
+            tracing::Level::INFO,
+            entries = self.primary_indexes.len(),
+            old_size = stats.bytes_before,
+            new_size = stats.bytes_after,
+            "compacted database"
+        );
+
+        Ok(stats)
+    }
+
+    /// Compacts every replica, returning the first error after trying them all.
+    fn compact_replicas(&mut self) -> Result<(), JasonError> {
+        let mut result = Ok(());
+
+        for replica in &mut self.replicas {
+            result = result.and(replica.compact());
+        }
+
+        for replica in &mut self.typed_replicas {
+            result = result.and(replica.compact());
+        }
+
+        result
     }
 
     /// Migrates the database to a new type according to the function.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(source = self.source.kind()))
+    )]
     pub fn migrate<U, F>(mut self, f: F) -> Result<Database<U, S>, JasonError>
     where
         U: IntoJson + FromJson,
@@ -438,10 +2094,152 @@ where
     {
         self.source.migrate(&self.primary_indexes, f)?;
 
+        trace!(
+            tracing::Level::INFO,
+            entries = self.primary_indexes.len(),
+            from = std::any::type_name::<T>(),
+            to = std::any::type_name::<U>(),
+            "migrated database"
+        );
+
         Database::from_source(self.source)
     }
 }
 
+/// Gets the value to index for the given field or computed index from the JSON representation of a value.
+pub(crate) fn index_value(
+    computed_indexes: &HashMap<String, ComputedIndex>,
+    field: &str,
+    json: &Value,
+) -> Value {
+    match computed_indexes.get(field) {
+        Some(f) => f(json),
+        None => indexing::get_value(field, json),
+    }
+}
+
+/// Estimates the memory allocated by a hash map for its entries, not including any memory allocated by the entries themselves.
+///
+/// Each slot in the table takes the size of the entry and one control byte.
+fn map_memory_estimate<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+/// Estimates the memory allocated by a set of offsets.
+///
+/// B-tree nodes hold up to eleven offsets alongside a small header, and are assumed to be full.
+fn set_memory_estimate(set: &BTreeSet<u64>) -> usize {
+    set.len().div_ceil(11) * (11 * std::mem::size_of::<u64>() + 16)
+}
+
+/// Estimates the memory allocated by a JSON value, not including the size of the value itself.
+fn value_memory_estimate(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.capacity(),
+        Value::Array(a) => {
+            a.capacity() * std::mem::size_of::<Value>()
+                + a.iter().map(value_memory_estimate).sum::<usize>()
+        }
+        Value::Object(o) => {
+            o.capacity() * std::mem::size_of::<(String, Value)>()
+                + o.iter()
+                    .map(|(k, v)| k.capacity() + value_memory_estimate(v))
+                    .sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+/// Removes the offset from the bucket for the given value in a secondary index, removing the bucket if it is left empty.
+///
+/// If the index is sorted, the value is also removed from its sorted values along with the bucket.
+///   Returns `false` if the offset was not in the bucket.
+fn unindex(
+    index: &mut HashMap<Value, BTreeSet<u64>>,
+    sorted: Option<&mut BTreeSet<OrderedValue>>,
+    value: &Value,
+    offset: u64,
+) -> bool {
+    match index.get_mut(value) {
+        Some(offsets) => {
+            let removed = offsets.remove(&offset);
+
+            if offsets.is_empty() {
+                index.remove(value);
+
+                if let Some(sorted) = sorted {
+                    sorted.remove(&OrderedValue(value.clone()));
+                }
+            }
+
+            removed
+        }
+        None => false,
+    }
+}
+
+/// Collects the distinct values of a secondary index in order, for use as a sorted index.
+fn sorted_values(index: &HashMap<Value, BTreeSet<u64>>) -> BTreeSet<OrderedValue> {
+    index.keys().cloned().map(OrderedValue).collect()
+}
+
+/// Sets the offset of the given key in the primary indexes, returning the old offset if there was one.
+///
+/// Unlike `HashMap::insert`, this only allocates an owned key if the key is new.
+fn insert_offset(
+    primary_indexes: &mut HashMap<String, u64>,
+    key: &str,
+    offset: u64,
+) -> Option<u64> {
+    match primary_indexes.get_mut(key) {
+        Some(old_offset) => Some(std::mem::replace(old_offset, offset)),
+        None => {
+            primary_indexes.insert(key.to_string(), offset);
+            None
+        }
+    }
+}
+
+/// Parses the key and value of an entry read from the source.
+///
+/// Returns `Err(JasonError::InvalidKey)` if the entry marks a deletion.
+pub(crate) fn parse_entry<T: FromJson>(k: String, v: Vec<u8>) -> Result<(String, T), JasonError> {
+    let json = unsafe { String::from_utf8_unchecked(v) };
+
+    if json.is_empty() {
+        Err(JasonError::InvalidKey)
+    } else {
+        Ok((
+            k,
+            humphrey_json::from_str(json).map_err(|_| JasonError::JsonError)?,
+        ))
+    }
+}
+
+/// Builds the secondary index for the given field or computed index from every entry in the source.
+fn build_index<S: Source>(
+    source: &mut S,
+    computed_indexes: &HashMap<String, ComputedIndex>,
+    field: &str,
+    primary_indexes: &HashMap<String, u64>,
+) -> Result<HashMap<Value, BTreeSet<u64>>, JasonError> {
+    let f = match computed_indexes.get(field) {
+        Some(f) => f,
+        None => return source.index_on(field, primary_indexes),
+    };
+
+    let mut indexes: HashMap<Value, BTreeSet<u64>> = HashMap::new();
+
+    for &offset in primary_indexes.values() {
+        let (k, v) = source.read_entry(offset)?;
+        let value = indexing::parse_entry_value(k, v)?;
+
+        indexes.entry(f(&value)).or_default().insert(offset);
+    }
+
+    Ok(indexes)
+}
+
 /// An iterator over the database.
 pub struct Iter<'a, T, S>
 where
@@ -452,6 +2250,17 @@ where
     pub(crate) keys: IntoIter<u64>,
 }
 
+impl<'a, T, S> Iter<'a, T, S>
+where
+    T: IntoJson + FromJson,
+    S: Source,
+{
+    /// Collects the remaining entries into a map of keys to values, returning the first error encountered.
+    pub fn into_map(self) -> Result<HashMap<String, T>, JasonError> {
+        self.collect()
+    }
+}
+
 impl<'a, T, S> Iterator for Iter<'a, T, S>
 where
     T: IntoJson + FromJson,
@@ -461,7 +2270,7 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         let index = self.keys.next()?;
-        let value = self.database.get_at_index(index);
+        let value = self.database.get_at_offset(index);
 
         Some(value)
     }
@@ -474,7 +2283,7 @@ where
 {
     fn next_back(&mut self) -> Option<Self::Item> {
         let index = self.keys.next_back()?;
-        let value = self.database.get_at_index(index);
+        let value = self.database.get_at_offset(index);
 
         Some(value)
     }
@@ -489,3 +2298,51 @@ where
         self.keys.len()
     }
 }
+
+/// An iterator over the database which only holds a shared reference to it, created with `Database::iter_ref`.
+pub struct IterRef<'a, T, S>
+where
+    T: IntoJson + FromJson + 'static,
+    S: SharedRead,
+{
+    database: &'a Database<T, S>,
+    keys: IntoIter<u64>,
+}
+
+impl<'a, T, S> Iterator for IterRef<'a, T, S>
+where
+    T: IntoJson + FromJson,
+    S: SharedRead,
+{
+    type Item = Result<(String, T), JasonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.keys.next()?;
+        let value = self.database.source.read_entry_at(index);
+
+        Some(value.and_then(|(k, v)| parse_entry(k, v)))
+    }
+}
+
+impl<'a, T, S> DoubleEndedIterator for IterRef<'a, T, S>
+where
+    T: IntoJson + FromJson,
+    S: SharedRead,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let index = self.keys.next_back()?;
+        let value = self.database.source.read_entry_at(index);
+
+        Some(value.and_then(|(k, v)| parse_entry(k, v)))
+    }
+}
+
+impl<'a, T, S> ExactSizeIterator for IterRef<'a, T, S>
+where
+    T: IntoJson + FromJson,
+    S: SharedRead,
+{
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
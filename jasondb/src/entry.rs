@@ -0,0 +1,121 @@
+//! Provides the `Entry` type, for conditionally inserting or modifying a single value.
+
+use crate::database::Database;
+use crate::error::JasonError;
+use crate::sources::Source;
+
+use humphrey_json::prelude::*;
+
+/// Represents a single key in the database which may or may not have a value, created with [`Database::entry`].
+///
+/// This works like the entry API of `HashMap`, but since values are not stored in memory, the methods return owned values
+///   rather than references. The primary index is only looked up once, and the value is only read if it is needed.
+///
+/// ## Example
+/// ```
+/// let mut db: Database<Counter> = Database::new("database.jdb")?;
+///
+/// db.entry("visits")
+///     .and_modify(|counter| counter.count += 1)?
+///     .or_insert(Counter { count: 1 })?;
+/// ```
+pub struct Entry<'a, T, S>
+where
+    T: IntoJson + FromJson + 'static,
+    S: Source,
+{
+    database: &'a mut Database<T, S>,
+    key: String,
+    state: State<T>,
+}
+
+/// Represents what is known about the value of an entry.
+enum State<T> {
+    /// The key has no value.
+    Vacant,
+    /// The key has a value at the given offset which has not been read.
+    Occupied(u64),
+    /// The key has the given value, which has already been read or written.
+    Loaded(T),
+}
+
+impl<'a, T, S> Entry<'a, T, S>
+where
+    T: IntoJson + FromJson + 'static,
+    S: Source,
+{
+    /// Creates an entry for the given key in the database.
+    pub(crate) fn new(database: &'a mut Database<T, S>, key: String) -> Self {
+        let state = match database.primary_indexes.get(&key) {
+            Some(&offset) => State::Occupied(offset),
+            None => State::Vacant,
+        };
+
+        Self {
+            database,
+            key,
+            state,
+        }
+    }
+
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns `true` if the key has a value.
+    pub fn is_occupied(&self) -> bool {
+        !matches!(self.state, State::Vacant)
+    }
+
+    /// Returns the value of the entry, setting it to `default` first if the key has no value.
+    pub fn or_insert(self, default: T) -> Result<T, JasonError> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Returns the value of the entry, setting it to the result of `f` first if the key has no value.
+    pub fn or_insert_with<F>(self, f: F) -> Result<T, JasonError>
+    where
+        F: FnOnce() -> T,
+    {
+        match self.state {
+            State::Vacant => {
+                let value = f();
+                self.database.set(&self.key, &value)?;
+
+                Ok(value)
+            }
+            State::Occupied(offset) => Ok(self.database.get_at_offset(offset)?.1),
+            State::Loaded(value) => Ok(value),
+        }
+    }
+
+    /// Returns the value of the entry, setting it to the default value first if the key has no value.
+    pub fn or_default(self) -> Result<T, JasonError>
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    /// Modifies the value of the entry with `f` and writes it back to the database, if the key has a value.
+    ///
+    /// The entry is returned so that it can be chained with `or_insert`, which will then return the modified value
+    ///   without reading it again.
+    pub fn and_modify<F>(mut self, f: F) -> Result<Self, JasonError>
+    where
+        F: FnOnce(&mut T),
+    {
+        let mut value = match self.state {
+            State::Vacant => return Ok(self),
+            State::Occupied(offset) => self.database.get_at_offset(offset)?.1,
+            State::Loaded(value) => value,
+        };
+
+        f(&mut value);
+        self.database.set(&self.key, &value)?;
+        self.state = State::Loaded(value);
+
+        Ok(self)
+    }
+}
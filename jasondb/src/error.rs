@@ -13,10 +13,37 @@ pub enum JasonError {
     Index,
     /// The key was invalid or not found.
     InvalidKey,
+    /// The key already has a value, and it was not allowed to be overwritten.
+    DuplicateKey,
     /// The JSON value was invalid.
     JsonError,
+    /// The database already exists and contains data.
+    AlreadyExists,
+    /// The database is already open for writing elsewhere.
+    Locked,
+    /// The source is not a JasonDB database or its format version is not supported.
+    BadFormat,
+    /// The source is a database from the JasonDB server, which uses an incompatible tar-based format.
+    IncompatibleFormat,
+    /// The value was larger than the database's maximum value size.
+    ValueTooLarge,
+    /// An entry could not be parsed as JSON while building an index.
+    InvalidEntry {
+        /// The key of the entry which could not be parsed.
+        key: String,
+    },
+    /// The field was not found in any entry.
+    UnknownField,
+    /// The pagination cursor could not be parsed.
+    InvalidCursor,
     /// An error occurred with a replica.
     ReplicaError,
+    /// An error occurred with an asynchronous replica which was given the name by `Database::with_async_replica_named`.
+    NamedReplicaError(String),
+    /// The source's file was replaced or truncated elsewhere, for example by compaction, so it must be reopened.
+    Replaced,
+    /// The operation is not supported by the source.
+    Unsupported,
     /// An unknown error occurred.
     Unknown,
 }
@@ -0,0 +1,95 @@
+//! Provides conversions between JasonDB's JSON values and [`serde_json::Value`].
+//!
+//! This module is only available with the `serde-interop` feature enabled.
+//!
+//! Since neither value type is defined in this crate, conversions go through the [`SerdeValue`] wrapper, which
+//!   converts to and from both. It also implements `IntoJson` and `FromJson`, so it can be used directly
+//!   in `query!` literals and `Database<SerdeValue>` stores and returns arbitrary `serde_json` values.
+//!
+//! ## Example
+//! ```
+//! let mut db: Database<SerdeValue> = Database::new_in_memory();
+//! db.set("alice", SerdeValue(serde_json::json!({ "name": "Alice", "age": 20 })))?;
+//!
+//! let name = SerdeValue(serde_json::json!("Alice"));
+//! let alice: serde_json::Value = db.query(query!(name == name))?.next().unwrap()?.1.into();
+//! ```
+
+use humphrey_json::error::ParseError;
+use humphrey_json::prelude::*;
+use humphrey_json::Value;
+
+/// A wrapper around a [`serde_json::Value`] which can be converted to and from JasonDB's JSON values.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SerdeValue(pub serde_json::Value);
+
+/// Converts a JasonDB JSON value into a [`serde_json::Value`].
+///
+/// Whole numbers which fit in an `i64` are converted to integers. Non-finite numbers, which cannot be represented in
+///   `serde_json`, are converted to `null`.
+pub fn to_serde(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Number(n) => {
+            if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 {
+                serde_json::Value::from(*n as i64)
+            } else {
+                serde_json::Number::from_f64(*n)
+                    .map(serde_json::Value::Number)
+                    .unwrap_or(serde_json::Value::Null)
+            }
+        }
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Array(a) => serde_json::Value::Array(a.iter().map(to_serde).collect()),
+        Value::Object(o) => {
+            serde_json::Value::Object(o.iter().map(|(k, v)| (k.clone(), to_serde(v))).collect())
+        }
+    }
+}
+
+/// Converts a [`serde_json::Value`] into a JasonDB JSON value.
+///
+/// Numbers are converted to `f64`, so integers larger than 2<sup>53</sup> may lose precision.
+pub fn from_serde(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(*b),
+        serde_json::Value::Number(n) => n.as_f64().map(Value::Number).unwrap_or(Value::Null),
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        serde_json::Value::Array(a) => Value::Array(a.iter().map(from_serde).collect()),
+        serde_json::Value::Object(o) => {
+            Value::Object(o.iter().map(|(k, v)| (k.clone(), from_serde(v))).collect())
+        }
+    }
+}
+
+impl From<serde_json::Value> for SerdeValue {
+    fn from(value: serde_json::Value) -> Self {
+        Self(value)
+    }
+}
+
+impl From<SerdeValue> for serde_json::Value {
+    fn from(value: SerdeValue) -> Self {
+        value.0
+    }
+}
+
+impl From<Value> for SerdeValue {
+    fn from(value: Value) -> Self {
+        Self(to_serde(&value))
+    }
+}
+
+impl IntoJson for SerdeValue {
+    fn to_json(&self) -> Value {
+        from_serde(&self.0)
+    }
+}
+
+impl FromJson for SerdeValue {
+    fn from_json(value: &Value) -> Result<Self, ParseError> {
+        Ok(Self(to_serde(value)))
+    }
+}
@@ -0,0 +1,84 @@
+//! Provides the `TryIterator` trait, which adds lazy adapters to iterators of fallible results.
+
+use crate::error::JasonError;
+
+/// Adds adapters to iterators of `Result<V, JasonError>`, such as the results of queries, which operate on the `Ok` values
+///   while passing any errors through unchanged.
+///
+/// This avoids having to either drop errors with `flatten` or `match` on every item. The adapters are lazy, so nothing is
+///   read from the database until the resulting iterator is consumed.
+///
+/// ## Example
+/// ```
+/// let adult_names = db
+///     .query(query!(age >= 18))?
+///     .filter_ok(|(_, person)| person.country == "UK")
+///     .map_ok(|(_, person)| person.name)
+///     .collect::<Result<Vec<_>, JasonError>>()?;
+/// ```
+pub trait TryIterator<V>: Iterator<Item = Result<V, JasonError>> + Sized {
+    /// Transforms every `Ok` value with `f`, leaving errors unchanged.
+    fn map_ok<U, F>(self, f: F) -> MapOk<Self, F>
+    where
+        F: FnMut(V) -> U,
+    {
+        MapOk { iter: self, f }
+    }
+
+    /// Skips every `Ok` value for which `f` returns `false`. Errors are always kept.
+    fn filter_ok<F>(self, f: F) -> FilterOk<Self, F>
+    where
+        F: FnMut(&V) -> bool,
+    {
+        FilterOk { iter: self, f }
+    }
+}
+
+impl<V, I> TryIterator<V> for I where I: Iterator<Item = Result<V, JasonError>> {}
+
+/// An iterator which transforms the `Ok` values of another iterator, created with [`TryIterator::map_ok`].
+pub struct MapOk<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<V, U, I, F> Iterator for MapOk<I, F>
+where
+    I: Iterator<Item = Result<V, JasonError>>,
+    F: FnMut(V) -> U,
+{
+    type Item = Result<U, JasonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|item| item.map(&mut self.f))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// An iterator which skips some of the `Ok` values of another iterator, created with [`TryIterator::filter_ok`].
+pub struct FilterOk<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<V, I, F> Iterator for FilterOk<I, F>
+where
+    I: Iterator<Item = Result<V, JasonError>>,
+    F: FnMut(&V) -> bool,
+{
+    type Item = Result<V, JasonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.find(|item| match item {
+            Ok(value) => (self.f)(value),
+            Err(_) => true,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
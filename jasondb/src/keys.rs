@@ -0,0 +1,47 @@
+//! Provides the encoding used to store binary keys, such as raw hashes, as strings.
+//!
+//! Keys are stored as UTF-8 strings, so arbitrary bytes can't be used as keys directly. Instead, methods such as
+//!   [`Database::set_bytes_key`](crate::Database::set_bytes_key) encode binary keys as lowercase hexadecimal, with two
+//!   characters per byte and no prefix. For example, the key `[0xde, 0xad, 0xbe, 0xef]` is stored as `"deadbeef"`.
+//!   External tools can decode keys with any hex decoder.
+//!
+//! Since there is no prefix, an encoded binary key is indistinguishable from a string key with the same characters,
+//!   so a database should use one kind of key or the other.
+
+/// Encodes a binary key as a lowercase hexadecimal string.
+pub fn encode(key: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut encoded = String::with_capacity(key.len() * 2);
+
+    for &byte in key {
+        encoded.push(DIGITS[(byte >> 4) as usize] as char);
+        encoded.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Decodes a binary key from a hexadecimal string, as encoded by [`encode`].
+///
+/// Uppercase digits are accepted. Returns `None` if the string is not valid hexadecimal.
+pub fn decode(key: &str) -> Option<Vec<u8>> {
+    if !key.len().is_multiple_of(2) {
+        return None;
+    }
+
+    key.as_bytes()
+        .chunks(2)
+        .map(|pair| Some((digit(pair[0])? << 4) | digit(pair[1])?))
+        .collect()
+}
+
+/// Returns the value of a single hexadecimal digit.
+fn digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
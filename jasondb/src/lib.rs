@@ -1,12 +1,29 @@
 //! JasonDB is a blazingly-fast, JSON-based, log-structured database for Rust.
 //! It can be backed with a file or be used fully in-memory, and it is highly resilient and flexible.
+//!
+//! Enabling the `tracing` feature emits [`tracing`](https://docs.rs/tracing) events for compaction, migration, index loading
+//!   and replication, which is useful for diagnosing embedded databases.
+//!
+//! Enabling the `serde-interop` feature adds the [`interop`] module, which converts between JasonDB's JSON values and
+//!   [`serde_json::Value`](https://docs.rs/serde_json).
 
 #![warn(missing_docs)]
 
+mod batch;
+mod collection;
+mod convert;
+mod cursor;
 mod database;
+mod entry;
 pub mod error;
+#[cfg(feature = "serde-interop")]
+pub mod interop;
+mod iter;
+pub mod keys;
 pub mod replica;
+mod schedule;
 pub mod sources;
+mod spilled;
 mod util;
 
 #[macro_use]
@@ -15,4 +32,13 @@ pub mod query;
 #[cfg(test)]
 mod tests;
 
-pub use database::Database;
+pub use batch::{Op, OpResult};
+pub use collection::Collection;
+pub use convert::convert_isam;
+pub use cursor::Cursor;
+pub use database::{ChangeEvent, CompactionEstimate, CompactionReport, Database};
+pub use entry::Entry;
+pub use iter::{FilterOk, MapOk, TryIterator};
+pub use schedule::ScheduledDatabase;
+pub use spilled::SpilledDatabase;
+pub use util::ordering::OrderedValue;
@@ -1,16 +1,19 @@
 //! Provides query construction functionality.
 
-use crate::database::{Database, Iter};
+use crate::database::{index_value, Database, Iter};
 use crate::error::JasonError;
 use crate::sources::Source;
-use crate::util::indexing;
+use crate::util::ordering::OrderedValue;
+use crate::util::{indexing, timestamp};
 
 use humphrey_json::prelude::*;
 pub use humphrey_json::Value;
 
+use std::borrow::Borrow;
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt::Debug;
-use std::ops::{BitAnd, BitOr};
+use std::ops::{BitAnd, BitOr, Bound, RangeBounds};
 
 /// Represents a query to be executed against a database.
 ///
@@ -34,14 +37,116 @@ pub enum Predicate {
     Lt(String, f64),
     /// Equivalent to `key <= value`.
     Lte(String, f64),
+    /// Equivalent to `key > value`, where both sides are strings compared lexicographically by their bytes.
+    GtStr(String, String),
+    /// Equivalent to `key >= value`, where both sides are strings compared lexicographically by their bytes.
+    GteStr(String, String),
+    /// Equivalent to `key < value`, where both sides are strings compared lexicographically by their bytes.
+    LtStr(String, String),
+    /// Equivalent to `key <= value`, where both sides are strings compared lexicographically by their bytes.
+    LteStr(String, String),
+    /// Equivalent to `lower <= key <= upper`, so both bounds are inclusive.
+    Between(String, f64, f64),
+    /// Equivalent to `lower <= key < upper`, so the upper bound is exclusive.
+    Range(String, f64, f64),
+    /// Equivalent to `key < value`, where both sides are parsed as ISO-8601 timestamps.
+    ///
+    /// Does not match if either side is not a valid timestamp.
+    Before(String, String),
+    /// Equivalent to `key > value`, where both sides are parsed as ISO-8601 timestamps.
+    ///
+    /// Does not match if either side is not a valid timestamp.
+    After(String, String),
+    /// Matches when the field is a string containing the given substring.
+    ///
+    /// Does not match if the field is not a string.
+    Contains(String, String),
     /// Equivalent to `key == value`.
     Eq(String, Value),
     /// Equivalent to `key != value`.
     Ne(String, Value),
+    /// Matches when the field is present, even if it is `null`.
+    ///
+    /// Indexes can't distinguish missing fields from `null` ones, so this always checks the values themselves.
+    ///   Values are checked using their `IntoJson` representation, so this only finds missing fields for types which
+    ///   omit them rather than serialising them as `null`, as derived implementations do for `Option` fields.
+    Exists(String),
+    /// Matches when the field is not present. A field which is explicitly `null` is not missing.
+    ///
+    /// This is the opposite of `Exists`, and has the same caveats.
+    Missing(String),
     /// Equivalent to `closure(key)`.
     Closure(String, PredicateClosure),
 }
 
+/// Represents a value which a field can be compared with using `>`, `>=`, `<` and `<=` in the `query!` macro.
+///
+/// This is implemented for numbers, which use the numeric predicates such as `Predicate::Gt`, and for strings, which use
+///   the string predicates such as `Predicate::GtStr`. Each only matches fields of the same type, so comparing a string
+///   field with a number never matches, and neither does comparing a number field with a string.
+pub trait OrderingBound {
+    /// Creates a predicate which matches when the field is greater than this bound.
+    fn gt(self, field: String) -> Predicate;
+    /// Creates a predicate which matches when the field is greater than or equal to this bound.
+    fn gte(self, field: String) -> Predicate;
+    /// Creates a predicate which matches when the field is less than this bound.
+    fn lt(self, field: String) -> Predicate;
+    /// Creates a predicate which matches when the field is less than or equal to this bound.
+    fn lte(self, field: String) -> Predicate;
+}
+
+macro_rules! numeric_bound {
+    ($($t:ty),*) => {
+        $(
+            impl OrderingBound for $t {
+                fn gt(self, field: String) -> Predicate {
+                    Predicate::Gt(field, f64::from(self))
+                }
+
+                fn gte(self, field: String) -> Predicate {
+                    Predicate::Gte(field, f64::from(self))
+                }
+
+                fn lt(self, field: String) -> Predicate {
+                    Predicate::Lt(field, f64::from(self))
+                }
+
+                fn lte(self, field: String) -> Predicate {
+                    Predicate::Lte(field, f64::from(self))
+                }
+            }
+        )*
+    };
+}
+
+numeric_bound!(u8, u16, u32, i8, i16, i32, f32, f64);
+
+macro_rules! string_bound {
+    ($($t:ty),*) => {
+        $(
+            impl OrderingBound for $t {
+                fn gt(self, field: String) -> Predicate {
+                    Predicate::GtStr(field, self.to_string())
+                }
+
+                fn gte(self, field: String) -> Predicate {
+                    Predicate::GteStr(field, self.to_string())
+                }
+
+                fn lt(self, field: String) -> Predicate {
+                    Predicate::LtStr(field, self.to_string())
+                }
+
+                fn lte(self, field: String) -> Predicate {
+                    Predicate::LteStr(field, self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+string_bound!(&str, String, &String);
+
 /// Represents a way of combining predicates. Currently the options are `and` and `or`.
 #[derive(Debug, PartialEq, Eq)]
 pub enum PredicateCombination {
@@ -57,12 +162,91 @@ pub struct PredicateClosure {
     pub closure: Box<dyn Fn(&Value) -> bool>,
 }
 
+/// Represents a query which has been planned for a particular set of secondary indexes, created with [`Query::compile`].
+///
+/// Executing a compiled query skips working out which of its predicates can use an index, which is worthwhile when the
+///   same query is executed many times. If a database is missing an index which the plan relies on, the query is planned
+///   again for that execution, so the results are always correct. However, indexes added after compilation are not used.
+#[derive(Debug, PartialEq)]
+pub struct CompiledQuery {
+    query: Query,
+    plan: Plan,
+}
+
+/// Represents how a compiled query will be executed.
+#[derive(Debug, PartialEq, Eq)]
+enum Plan {
+    /// Use the indexes of the first `n` predicates, then check the rest manually.
+    Optimised(usize),
+    /// Check every predicate manually against every value.
+    Unoptimised,
+    /// Nothing can match, so nothing needs to be checked.
+    Unsatisfiable,
+}
+
 impl Query {
+    /// Creates a query which matches when the given field is a number within the given range.
+    ///
+    /// This follows Rust's range semantics, so `1800..1900` does not match `1900` but `1800..=1900` does.
+    ///   It is used by the `query!` macro for `field in range` predicates.
+    pub fn range<N, R>(field: impl AsRef<str>, range: R) -> Self
+    where
+        N: Copy + Into<f64>,
+        R: RangeBounds<N>,
+    {
+        let field = field.as_ref().to_string();
+
+        let upper = match range.end_bound() {
+            Bound::Included(&upper) => Some((upper.into(), true)),
+            Bound::Excluded(&upper) => Some((upper.into(), false)),
+            Bound::Unbounded => None,
+        };
+
+        let upper_predicate = |field: String| match upper {
+            Some((upper, true)) => Predicate::Lte(field, upper),
+            Some((upper, false)) => Predicate::Lt(field, upper),
+            None => Predicate::Gte(field, f64::NEG_INFINITY),
+        };
+
+        match range.start_bound() {
+            Bound::Included(&lower) => Self::from(match upper {
+                Some((upper, true)) => Predicate::Between(field, lower.into(), upper),
+                Some((upper, false)) => Predicate::Range(field, lower.into(), upper),
+                None => Predicate::Gte(field, lower.into()),
+            }),
+            Bound::Excluded(&lower) => {
+                let query = Self::from(Predicate::Gt(field.clone(), lower.into()));
+
+                match upper {
+                    Some(_) => query & Self::from(upper_predicate(field)),
+                    None => query,
+                }
+            }
+            Bound::Unbounded => Self::from(upper_predicate(field)),
+        }
+    }
+
+    /// Creates a query which matches when the given field is equal to the given value.
+    ///
+    /// This is equivalent to `query!(field == value)`, but the field doesn't have to be known when the code is written,
+    ///   and an existing [`Value`] is used as-is.
+    ///
+    /// ## Example
+    /// ```
+    /// let value = Value::parse(r#"["red", "green"]"#)?;
+    /// let query = Query::eq_value(field_name, value);
+    /// ```
+    pub fn eq_value(field: impl AsRef<str>, value: impl Into<Value>) -> Self {
+        Self::from(Predicate::Eq(field.as_ref().to_string(), value.into()))
+    }
+
     /// Attempts to execute the query against the given database.
     ///
     /// If successful, an iterator over the matching values is returned.
     /// This will automatically optimise the query where possible
     ///   (see issue [#9](https://github.com/w-henderson/JasonDB/issues/9) for optimisation status).
+    ///   Queries which obviously can't match, such as `query!(x > 5) & query!(x < 3)`, return an empty iterator
+    ///   without reading anything.
     pub fn execute<'a, T, S>(
         &self,
         database: &'a mut Database<T, S>,
@@ -71,13 +255,194 @@ impl Query {
         T: IntoJson + FromJson,
         S: Source,
     {
-        if self.is_optimisable(database) {
+        if self.is_unsatisfiable() {
+            Ok(Iter {
+                database,
+                keys: Vec::new().into_iter(),
+            })
+        } else if self.is_optimisable(database) {
             self.execute_optimised(database)
         } else {
             self.execute_unoptimised(database)
         }
     }
 
+    /// Attempts to execute the query against the given database, returning only the keys of the matching values.
+    ///
+    /// The query is optimised in the same way as [`Query::execute`], but matching offsets are resolved to keys using the
+    ///   primary indexes instead of by reading the values. If every predicate is indexed, this avoids reading any values at all.
+    ///   The keys are returned in the order in which they are stored in the database.
+    pub fn execute_keys<T, S>(
+        &self,
+        database: &mut Database<T, S>,
+    ) -> Result<Vec<String>, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        if self.is_unsatisfiable() {
+            return Ok(Vec::new());
+        }
+
+        let offsets = if self.is_optimisable(database) {
+            self.optimised_offsets(database)?
+        } else {
+            self.unoptimised_offsets(database)?
+        }
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+        let mut keys = database
+            .primary_indexes
+            .iter()
+            .filter(|(_, offset)| offsets.contains(offset))
+            .collect::<Vec<_>>();
+
+        keys.sort_unstable_by_key(|(_, offset)| **offset);
+
+        Ok(keys.into_iter().map(|(k, _)| k.clone()).collect())
+    }
+
+    /// Counts the values in the given database which match the query.
+    ///
+    /// The query is optimised in the same way as [`Query::execute`], but the matching offsets are counted rather than
+    ///   read. If every predicate is indexed, the count comes straight from the secondary indexes without reading or
+    ///   deserialising any values. Otherwise, only the values which need to be checked against the remaining predicates are read.
+    pub fn count<T, S>(&self, database: &mut Database<T, S>) -> Result<usize, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        if self.is_unsatisfiable() {
+            Ok(0)
+        } else if self.is_optimisable(database) {
+            Ok(self.optimised_offsets(database)?.len())
+        } else {
+            Ok(self.unoptimised_offsets(database)?.len())
+        }
+    }
+
+    /// Attempts to execute the query against the given database, yielding matching values lazily as they are found.
+    ///
+    /// Unlike [`Query::execute`], which checks every candidate before returning, this only reads and checks values as the
+    ///   iterator is advanced, so taking the first match can stop the scan early. Secondary indexes are still used to find the
+    ///   candidates, and values are yielded in the order in which they are stored.
+    pub fn stream<'a, T, S>(
+        mut self,
+        database: &'a mut Database<T, S>,
+    ) -> Result<impl Iterator<Item = Result<(String, T), JasonError>> + 'a, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        // The candidates are found using the indexed predicates, and the remaining predicates are checked lazily.
+        let (candidates, remaining) = if self.is_unsatisfiable() {
+            (Vec::new(), Vec::new())
+        } else if self.is_optimisable(database) {
+            self.predicates.sort_by_key(|p| !p.is_indexed(database));
+            let indexed = self
+                .predicates
+                .iter()
+                .take_while(|p| p.is_indexed(database))
+                .count();
+
+            let remaining = self.predicates.split_off(indexed);
+            let candidates = self.planned_offsets(database, &self.predicates, &[])?;
+
+            (candidates, remaining)
+        } else {
+            let mut candidates = database
+                .primary_indexes
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            candidates.sort_unstable();
+
+            (candidates, self.predicates)
+        };
+
+        let remaining = Query {
+            predicates: remaining,
+            predicate_combination: self.predicate_combination,
+        };
+        let mut candidates = candidates.into_iter();
+
+        Ok(std::iter::from_fn(move || {
+            for offset in candidates.by_ref() {
+                let entry = database.get_at_offset(offset);
+
+                if remaining.predicates.is_empty() {
+                    return Some(entry);
+                }
+
+                match entry
+                    .and_then(|(k, v)| Ok((remaining.matches_in(database, &v.to_json())?, k, v)))
+                {
+                    Ok((true, k, v)) => return Some(Ok((k, v))),
+                    Ok((false, _, _)) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            None
+        }))
+    }
+
+    /// Plans the query for the given database's secondary indexes so it can be executed repeatedly without replanning.
+    ///
+    /// The compiled query can be executed against any database, but it is only optimised for databases with the same indexes.
+    pub fn compile<T, S>(mut self, database: &Database<T, S>) -> CompiledQuery
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        if self.is_unsatisfiable() {
+            CompiledQuery {
+                query: self,
+                plan: Plan::Unsatisfiable,
+            }
+        } else if self.is_optimisable(database) {
+            self.predicates.sort_by_key(|p| !p.is_indexed(database));
+            let indexed = self
+                .predicates
+                .iter()
+                .take_while(|p| p.is_indexed(database))
+                .count();
+
+            CompiledQuery {
+                query: self,
+                plan: Plan::Optimised(indexed),
+            }
+        } else {
+            CompiledQuery {
+                query: self,
+                plan: Plan::Unoptimised,
+            }
+        }
+    }
+
+    /// Checks whether the query obviously can't match anything, so it doesn't need to be executed.
+    ///
+    /// This is the case when an `And` query requires a field to be within numeric bounds which don't overlap,
+    ///   such as `query!(x > 5) & query!(x < 3)`.
+    fn is_unsatisfiable(&self) -> bool {
+        if self.predicate_combination != PredicateCombination::And {
+            return false;
+        }
+
+        self.predicates.iter().any(|lower| {
+            self.predicates.iter().any(|upper| {
+                lower.key() == upper.key()
+                    && match (lower.lower_bound(), upper.upper_bound()) {
+                        (Some((l, l_inclusive)), Some((u, u_inclusive))) => {
+                            l > u || (l == u && !(l_inclusive && u_inclusive))
+                        }
+                        _ => false,
+                    }
+            })
+        })
+    }
+
     /// Checks whether the query is optimisable on the given database.
     ///
     /// This is used to prevent unnecessary optimisation attempts on unoptimisable queries.
@@ -101,28 +466,90 @@ impl Query {
         T: IntoJson + FromJson,
         S: Source,
     {
-        let mut indexes = Vec::new();
+        let keys = self.optimised_offsets(database)?;
+
+        Ok(Iter {
+            database,
+            keys: keys.into_iter(),
+        })
+    }
 
+    /// Finds the offsets of the values matching the query using the secondary indexes.
+    fn optimised_offsets<T, S>(&self, database: &mut Database<T, S>) -> Result<Vec<u64>, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
         let optimisable_predicates = self
             .predicates
             .iter()
-            .filter(|p| database.secondary_indexes.contains_key(p.key()))
+            .filter(|p| p.is_indexed(database))
             .collect::<Vec<_>>();
 
         let unoptimisable_predicates = self
             .predicates
             .iter()
-            .filter(|p| !database.secondary_indexes.contains_key(p.key()))
+            .filter(|p| !p.is_indexed(database))
             .collect::<Vec<_>>();
 
+        self.planned_offsets(database, &optimisable_predicates, &unoptimisable_predicates)
+    }
+
+    /// Finds the offsets of the values matching the query using the secondary indexes, given which predicates are indexed.
+    ///
+    /// Every predicate in `optimisable_predicates` must be indexed in the database.
+    fn planned_offsets<T, S, P>(
+        &self,
+        database: &mut Database<T, S>,
+        optimisable_predicates: &[P],
+        unoptimisable_predicates: &[P],
+    ) -> Result<Vec<u64>, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+        P: Borrow<Predicate>,
+    {
+        let mut indexes = Vec::new();
+
         // Use each predicate's corresponding index to find matches.
-        for predicate in &optimisable_predicates {
+        for predicate in optimisable_predicates {
+            let predicate = predicate.borrow();
             let index = database.secondary_indexes.get(predicate.key()).unwrap();
+            let matched_before = indexes.len();
+
+            // Sorted indexes only need the values within the range to be checked.
+            match (
+                database.sorted_indexes.get(predicate.key()),
+                predicate.sorted_range(),
+            ) {
+                (Some(sorted), Some((start, end))) => {
+                    let values = match (&start, &end) {
+                        (Bound::Included(start), Bound::Included(end)) if start > end => None,
+                        _ => Some(sorted.range((start, end))),
+                    };
 
-            for (v, i) in index {
-                if predicate.matches_direct(v)? {
-                    indexes.push(i.iter().peekable());
+                    for value in values.into_iter().flatten() {
+                        if let Some(i) = index.get(&value.0) {
+                            if predicate.matches_direct(&value.0)? {
+                                indexes.push(i.iter().peekable());
+                            }
+                        }
+                    }
                 }
+                _ => {
+                    for (v, i) in index {
+                        if predicate.matches_direct(v)? {
+                            indexes.push(i.iter().peekable());
+                        }
+                    }
+                }
+            }
+
+            // If an indexed predicate matches nothing, neither can the whole `And` query, so the rest can be skipped.
+            if self.predicate_combination == PredicateCombination::And
+                && indexes.len() == matched_before
+            {
+                return Ok(Vec::new());
             }
         }
 
@@ -178,10 +605,7 @@ impl Query {
         if unoptimisable_predicates.is_empty() {
             // If there are no unoptimisable predicates, we don't need to check any more conditions and we can return now.
 
-            Ok(Iter {
-                database,
-                keys: combined_indexes.into_iter(),
-            })
+            Ok(combined_indexes)
         } else {
             // If there are some unoptimisable predicates, we check them manually but use the existing indexes instead of every index.
             // This is quicker than iterating over the whole database, but can only be applied when the combination is `And`.
@@ -189,10 +613,12 @@ impl Query {
             let mut filtered_indexes = Vec::with_capacity(combined_indexes.len());
 
             'outer: for index in combined_indexes {
-                let (_, v) = database.get_at_index(index)?;
+                let (_, v) = database.get_at_offset(index)?;
 
-                for predicate in &unoptimisable_predicates {
-                    if !predicate.matches(&v.to_json())? {
+                let json = v.to_json();
+
+                for predicate in unoptimisable_predicates {
+                    if !predicate.borrow().matches_in(database, &json)? {
                         continue 'outer;
                     }
                 }
@@ -200,10 +626,7 @@ impl Query {
                 filtered_indexes.push(index);
             }
 
-            Ok(Iter {
-                database,
-                keys: filtered_indexes.into_iter(),
-            })
+            Ok(filtered_indexes)
         }
     }
 
@@ -212,6 +635,23 @@ impl Query {
         &self,
         database: &'a mut Database<T, S>,
     ) -> Result<Iter<'a, T, S>, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        let keys = self.unoptimised_offsets(database)?;
+
+        Ok(Iter {
+            database,
+            keys: keys.into_iter(),
+        })
+    }
+
+    /// Finds the offsets of the values matching the query by checking every value in the database.
+    fn unoptimised_offsets<T, S>(
+        &self,
+        database: &mut Database<T, S>,
+    ) -> Result<Vec<u64>, JasonError>
     where
         T: IntoJson + FromJson,
         S: Source,
@@ -224,17 +664,14 @@ impl Query {
             .collect::<Vec<_>>();
 
         for key in &keys {
-            let (_, v) = database.get_at_index(*key)?;
+            let (_, v) = database.get_at_offset(*key)?;
 
-            if self.matches(&v.to_json())? {
+            if self.matches_in(database, &v.to_json())? {
                 indexes.push(*key);
             }
         }
 
-        Ok(Iter {
-            database,
-            keys: indexes.into_iter(),
-        })
+        Ok(indexes)
     }
 
     /// Checks whether the query matches the given value.
@@ -258,6 +695,78 @@ impl Query {
             }
         }
     }
+
+    /// Checks whether the query matches the given value in the given database, taking its computed indexes into account.
+    pub(crate) fn matches_in<T, S>(
+        &self,
+        database: &Database<T, S>,
+        json: &Value,
+    ) -> Result<bool, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        if database.computed_indexes.is_empty() {
+            return self.matches(json);
+        }
+
+        match self.predicate_combination {
+            PredicateCombination::And => {
+                for predicate in &self.predicates {
+                    if !predicate.matches_in(database, json)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            PredicateCombination::Or => {
+                for predicate in &self.predicates {
+                    if predicate.matches_in(database, json)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl CompiledQuery {
+    /// Attempts to execute the compiled query against the given database.
+    ///
+    /// If successful, an iterator over the matching values is returned.
+    pub fn execute<'a, T, S>(
+        &self,
+        database: &'a mut Database<T, S>,
+    ) -> Result<Iter<'a, T, S>, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        let keys = match self.plan {
+            Plan::Optimised(n) => {
+                let (indexed, unindexed) = self.query.predicates.split_at(n);
+
+                if indexed.iter().all(|p| p.is_indexed(database)) {
+                    self.query.planned_offsets(database, indexed, unindexed)?
+                } else {
+                    return self.query.execute(database);
+                }
+            }
+            Plan::Unoptimised => self.query.unoptimised_offsets(database)?,
+            Plan::Unsatisfiable => Vec::new(),
+        };
+
+        Ok(Iter {
+            database,
+            keys: keys.into_iter(),
+        })
+    }
+
+    /// Returns the query which was compiled.
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
 }
 
 impl Predicate {
@@ -267,27 +776,70 @@ impl Predicate {
         T: IntoJson + FromJson,
         S: Source,
     {
-        database.secondary_indexes.contains_key(self.key())
+        !matches!(self, Self::Exists(_) | Self::Missing(_))
+            && database.secondary_indexes.contains_key(self.key())
     }
 
     /// Checks whether the predicate matches the given value.
     pub(crate) fn matches(&self, json: &Value) -> Result<bool, JasonError> {
         match self {
             Self::Gt(index, right) => {
-                let left = indexing::get_number(index, json)?;
-                Ok(left > *right)
+                let left = indexing::get_value(index, json);
+                Ok(left.as_number().is_some_and(|left| left > *right))
+            }
+            Self::GtStr(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(left.as_str().is_some_and(|left| left > right.as_str()))
             }
             Self::Gte(index, right) => {
-                let left = indexing::get_number(index, json)?;
-                Ok(left >= *right)
+                let left = indexing::get_value(index, json);
+                Ok(left.as_number().is_some_and(|left| left >= *right))
+            }
+            Self::GteStr(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(left.as_str().is_some_and(|left| left >= right.as_str()))
             }
             Self::Lt(index, right) => {
-                let left = indexing::get_number(index, json)?;
-                Ok(left < *right)
+                let left = indexing::get_value(index, json);
+                Ok(left.as_number().is_some_and(|left| left < *right))
+            }
+            Self::LtStr(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(left.as_str().is_some_and(|left| left < right.as_str()))
             }
             Self::Lte(index, right) => {
-                let left = indexing::get_number(index, json)?;
-                Ok(left <= *right)
+                let left = indexing::get_value(index, json);
+                Ok(left.as_number().is_some_and(|left| left <= *right))
+            }
+            Self::LteStr(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(left.as_str().is_some_and(|left| left <= right.as_str()))
+            }
+            Self::Between(index, lower, upper) => {
+                let left = indexing::get_value(index, json);
+                Ok(left
+                    .as_number()
+                    .is_some_and(|left| *lower <= left && left <= *upper))
+            }
+            Self::Range(index, lower, upper) => {
+                let left = indexing::get_value(index, json);
+                Ok(left
+                    .as_number()
+                    .is_some_and(|left| *lower <= left && left < *upper))
+            }
+            Self::Before(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(compare_timestamps(&left, right) == Some(Ordering::Less))
+            }
+            Self::After(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(compare_timestamps(&left, right) == Some(Ordering::Greater))
+            }
+            Self::Contains(index, right) => {
+                let left = indexing::get_value(index, json);
+                Ok(left
+                    .as_str()
+                    .is_some_and(|left| left.contains(right.as_str())))
             }
             Self::Eq(index, right) => {
                 let left = indexing::get_value(index, json);
@@ -297,6 +849,8 @@ impl Predicate {
                 let left = indexing::get_value(index, json);
                 Ok(left != *right)
             }
+            Self::Exists(index) => Ok(indexing::get_value_opt(index, json).is_some()),
+            Self::Missing(index) => Ok(indexing::get_value_opt(index, json).is_none()),
             Self::Closure(index, closure) => {
                 let left = indexing::get_value(index, json);
                 Ok((closure.closure)(&left))
@@ -304,46 +858,142 @@ impl Predicate {
         }
     }
 
+    /// Checks whether the predicate matches the given value in the given database, taking its computed indexes into account.
+    pub(crate) fn matches_in<T, S>(
+        &self,
+        database: &Database<T, S>,
+        json: &Value,
+    ) -> Result<bool, JasonError>
+    where
+        T: IntoJson + FromJson,
+        S: Source,
+    {
+        let presence = matches!(self, Self::Exists(_) | Self::Missing(_));
+
+        if !presence && database.computed_indexes.contains_key(self.key()) {
+            self.matches_direct(&index_value(&database.computed_indexes, self.key(), json))
+        } else {
+            self.matches(json)
+        }
+    }
+
     /// Checks whether the predicate directly matches the given value.
     /// This bypasses the index and checks for equality with the value itself.
+    ///
+    /// Since a value on its own can't be missing, `Exists` and `Missing` treat `null` as missing here.
     pub(crate) fn matches_direct(&self, json: &Value) -> Result<bool, JasonError> {
         match self {
-            Self::Gt(_, right) => {
-                let left = json.as_number().ok_or(JasonError::JsonError)?;
-                Ok(left > *right)
-            }
-            Self::Gte(_, right) => {
-                let left = json.as_number().ok_or(JasonError::JsonError)?;
-                Ok(left >= *right)
-            }
-            Self::Lt(_, right) => {
-                let left = json.as_number().ok_or(JasonError::JsonError)?;
-                Ok(left < *right)
-            }
-            Self::Lte(_, right) => {
-                let left = json.as_number().ok_or(JasonError::JsonError)?;
-                Ok(left <= *right)
-            }
+            Self::Gt(_, right) => Ok(json.as_number().is_some_and(|left| left > *right)),
+            Self::GtStr(_, right) => Ok(json.as_str().is_some_and(|left| left > right.as_str())),
+            Self::Gte(_, right) => Ok(json.as_number().is_some_and(|left| left >= *right)),
+            Self::GteStr(_, right) => Ok(json.as_str().is_some_and(|left| left >= right.as_str())),
+            Self::Lt(_, right) => Ok(json.as_number().is_some_and(|left| left < *right)),
+            Self::LtStr(_, right) => Ok(json.as_str().is_some_and(|left| left < right.as_str())),
+            Self::Lte(_, right) => Ok(json.as_number().is_some_and(|left| left <= *right)),
+            Self::LteStr(_, right) => Ok(json.as_str().is_some_and(|left| left <= right.as_str())),
+            Self::Between(_, lower, upper) => Ok(json
+                .as_number()
+                .is_some_and(|left| *lower <= left && left <= *upper)),
+            Self::Range(_, lower, upper) => Ok(json
+                .as_number()
+                .is_some_and(|left| *lower <= left && left < *upper)),
+            Self::Before(_, right) => Ok(compare_timestamps(json, right) == Some(Ordering::Less)),
+            Self::After(_, right) => Ok(compare_timestamps(json, right) == Some(Ordering::Greater)),
+            Self::Contains(_, right) => Ok(json
+                .as_str()
+                .is_some_and(|left| left.contains(right.as_str()))),
             Self::Eq(_, right) => Ok(*json == *right),
             Self::Ne(_, right) => Ok(*json != *right),
+            Self::Exists(_) => Ok(*json != Value::Null),
+            Self::Missing(_) => Ok(*json == Value::Null),
             Self::Closure(_, closure) => Ok((closure.closure)(json)),
         }
     }
 
+    /// Returns the range of values in a sorted index which could match the predicate, if it is an ordering predicate.
+    ///
+    /// Both ends of the range are inclusive, and a bound of zero includes both `-0.0` and `0.0`, which are distinct in a
+    ///   sorted index. The range can therefore be slightly wider than the values which match, so each must still be checked.
+    fn sorted_range(&self) -> Option<(Bound<OrderedValue>, Bound<OrderedValue>)> {
+        // Numbers are ordered after `true` and before the empty string, which is the smallest string.
+        let numbers_start = || Bound::Excluded(OrderedValue(Value::Bool(true)));
+        let numbers_end = || Bound::Excluded(OrderedValue(Value::String(String::new())));
+        let strings_start = || Bound::Included(OrderedValue(Value::String(String::new())));
+        let strings_end = || Bound::Excluded(OrderedValue(Value::Array(Vec::new())));
+
+        let lower =
+            |n: f64| Bound::Included(OrderedValue(Value::Number(if n == 0.0 { -0.0 } else { n })));
+        let upper =
+            |n: f64| Bound::Included(OrderedValue(Value::Number(if n == 0.0 { 0.0 } else { n })));
+        let string = |s: &String| Bound::Included(OrderedValue(Value::String(s.clone())));
+
+        match self {
+            Self::Gt(_, n) | Self::Gte(_, n) => Some((lower(*n), numbers_end())),
+            Self::Lt(_, n) | Self::Lte(_, n) => Some((numbers_start(), upper(*n))),
+            Self::Between(_, l, u) | Self::Range(_, l, u) => Some((lower(*l), upper(*u))),
+            Self::GtStr(_, s) | Self::GteStr(_, s) => Some((string(s), strings_end())),
+            Self::LtStr(_, s) | Self::LteStr(_, s) => Some((strings_start(), string(s))),
+            _ => None,
+        }
+    }
+
+    /// Returns the lower bound which the predicate places on a numeric field, and whether it is inclusive.
+    fn lower_bound(&self) -> Option<(f64, bool)> {
+        match self {
+            Self::Gt(_, lower) => Some((*lower, false)),
+            Self::Gte(_, lower) => Some((*lower, true)),
+            Self::Between(_, lower, _) => Some((*lower, true)),
+            Self::Range(_, lower, _) => Some((*lower, true)),
+            _ => None,
+        }
+    }
+
+    /// Returns the upper bound which the predicate places on a numeric field, and whether it is inclusive.
+    fn upper_bound(&self) -> Option<(f64, bool)> {
+        match self {
+            Self::Lt(_, upper) => Some((*upper, false)),
+            Self::Lte(_, upper) => Some((*upper, true)),
+            Self::Between(_, _, upper) => Some((*upper, true)),
+            Self::Range(_, _, upper) => Some((*upper, false)),
+            _ => None,
+        }
+    }
+
     /// Returns the key of the predicate.
     pub(crate) fn key(&self) -> &str {
         match self {
             Self::Gt(key, _) => key,
+            Self::GtStr(key, _) => key,
             Self::Gte(key, _) => key,
+            Self::GteStr(key, _) => key,
             Self::Lt(key, _) => key,
+            Self::LtStr(key, _) => key,
             Self::Lte(key, _) => key,
+            Self::LteStr(key, _) => key,
+            Self::Between(key, _, _) => key,
+            Self::Range(key, _, _) => key,
+            Self::Before(key, _) => key,
+            Self::After(key, _) => key,
+            Self::Contains(key, _) => key,
             Self::Eq(key, _) => key,
             Self::Ne(key, _) => key,
+            Self::Exists(key) => key,
+            Self::Missing(key) => key,
             Self::Closure(key, _) => key,
         }
     }
 }
 
+/// Compares a JSON value with a timestamp string as instants in time.
+///
+/// Returns `None` if either side is not a valid timestamp.
+fn compare_timestamps(left: &Value, right: &str) -> Option<Ordering> {
+    let left = timestamp::parse(left.as_str()?)?;
+    let right = timestamp::parse(right)?;
+
+    Some(left.cmp(&right))
+}
+
 impl From<Predicate> for Query {
     fn from(predicate: Predicate) -> Self {
         Self {
@@ -400,8 +1050,49 @@ impl PartialEq for PredicateClosure {
 /// query!(coordinates.lat > 0.0) // `lat` field of `coordinates` > 0.0, e.g. above equator
 /// query!(country == "UK") // `country` field == "UK"
 /// query!(price < 10) | query!(discounted) // `price` field < 10 or `discounted` field == true
+/// query!(year in 1800..=1900) // `year` field >= 1800 and <= 1900
+/// query!(year in 1800..1900) // `year` field >= 1800 and < 1900
 /// ```
 ///
+/// Strings can be compared with the same operators, and are ordered lexicographically by their bytes. Ordering
+///   comparisons only match fields of the same type as the value, so a string field is never greater or less than a
+///   number, and fields which are missing or `null` never match.
+///
+/// ```
+/// query!(name >= "M") // `name` field is alphabetically at or after "M"
+/// ```
+///
+/// Ranges are written with Rust's range syntax and have the same semantics, so `a..b` excludes `b` and `a..=b` includes it.
+///   Either bound can be omitted, as in `..b`, `..=b` and `a..`.
+///
+/// ISO-8601 timestamps stored as strings can be compared as instants in time with `before` and `after`.
+///   This accounts for UTC offsets and differing precision, and timestamps which cannot be parsed never match.
+///
+/// ```
+/// query!(created_at before "2023-01-01") // `created_at` field is earlier than midnight UTC on 1st January 2023
+/// query!(created_at after "2023-01-01T12:00:00+01:00") // `created_at` field is later than 11am UTC on that day
+/// ```
+///
+/// String fields can be searched for a substring with `~`. This is case-sensitive, and fields which aren't strings never
+///   match. A substring can't be looked up in a secondary index directly, so an index on the field is used by checking
+///   every distinct indexed value, which avoids reading the values themselves but is slower than an equality lookup.
+///
+/// ```
+/// query!(name ~ "Bach") // `name` field contains "Bach"
+/// ```
+///
+/// A field which is explicitly `null` is distinguished from one which is not present at all with `exists` and `missing`.
+///   Comparing with `null` matches both.
+///
+/// ```
+/// query!(middle_name exists) // `middle_name` field is present, even if it is `null`
+/// query!(middle_name missing) // `middle_name` field is not present
+/// query!(middle_name == null) // `middle_name` field is `null` or not present
+/// ```
+///
+/// The value being compared with can be any expression which converts into a [`Value`], including an existing `Value`.
+///   To compare a field whose name is only known at runtime, use [`Query::eq_value`].
+///
 /// You'll notice that queries are combined using bitwise operators outside of the macro.
 /// This is because the macro is currently not able to parse `&&` and `||`, but this will hopefully change in the future.
 ///
@@ -420,30 +1111,67 @@ impl PartialEq for PredicateClosure {
 #[macro_export]
 macro_rules! query {
     ($($field:ident).+ > $value:expr) => {
-        $crate::query::Query::from($crate::query::Predicate::Gt(
+        $crate::query::Query::from($crate::query::OrderingBound::gt(
+            $value,
             stringify!($($field).+).to_string(),
-            f64::from($value),
         ))
     };
 
     ($($field:ident).+ >= $value:expr) => {
-        $crate::query::Query::from($crate::query::Predicate::Gte(
+        $crate::query::Query::from($crate::query::OrderingBound::gte(
+            $value,
             stringify!($($field).+).to_string(),
-            f64::from($value),
         ))
     };
 
     ($($field:ident).+ < $value:expr) => {
-        $crate::query::Query::from($crate::query::Predicate::Lt(
+        $crate::query::Query::from($crate::query::OrderingBound::lt(
+            $value,
             stringify!($($field).+).to_string(),
-            f64::from($value),
         ))
     };
 
     ($($field:ident).+ <= $value:expr) => {
-        $crate::query::Query::from($crate::query::Predicate::Lte(
+        $crate::query::Query::from($crate::query::OrderingBound::lte(
+            $value,
+            stringify!($($field).+).to_string(),
+        ))
+    };
+
+    ($($field:ident).+ in $range:expr) => {
+        $crate::query::Query::range(stringify!($($field).+), $range)
+    };
+
+    ($($field:ident).+ before $value:expr) => {
+        $crate::query::Query::from($crate::query::Predicate::Before(
+            stringify!($($field).+).to_string(),
+            ($value).to_string(),
+        ))
+    };
+
+    ($($field:ident).+ after $value:expr) => {
+        $crate::query::Query::from($crate::query::Predicate::After(
+            stringify!($($field).+).to_string(),
+            ($value).to_string(),
+        ))
+    };
+
+    ($($field:ident).+ ~ $value:expr) => {
+        $crate::query::Query::from($crate::query::Predicate::Contains(
+            stringify!($($field).+).to_string(),
+            ($value).to_string(),
+        ))
+    };
+
+    ($($field:ident).+ exists) => {
+        $crate::query::Query::from($crate::query::Predicate::Exists(
+            stringify!($($field).+).to_string(),
+        ))
+    };
+
+    ($($field:ident).+ missing) => {
+        $crate::query::Query::from($crate::query::Predicate::Missing(
             stringify!($($field).+).to_string(),
-            f64::from($value),
         ))
     };
 
@@ -2,6 +2,7 @@
 
 use crate::error::JasonError;
 use crate::sources::Source;
+use crate::util::trace;
 use crate::Database;
 
 use humphrey_json::prelude::*;
@@ -18,6 +19,54 @@ pub trait Replica<T>: Send + 'static {
     ///
     /// The value is passed as the JSON representation of the value.
     fn set(&mut self, key: &str, value: &str) -> Result<(), JasonError>;
+
+    /// Replicate the deletion of the key to the replica.
+    ///
+    /// By default, this sets the value to `null`, so replicas which cannot distinguish deletions from `null` values
+    ///   do not need to implement it.
+    fn delete(&mut self, key: &str) -> Result<(), JasonError> {
+        self.set(key, "null")
+    }
+
+    /// Ensures that every change replicated so far has been written to stable storage.
+    ///
+    /// By default, this does nothing, which is appropriate for replicas which write changes durably as they receive them.
+    fn flush(&mut self) -> Result<(), JasonError> {
+        Ok(())
+    }
+
+    /// Compacts the replica, which is called whenever the database it replicates is compacted.
+    ///
+    /// By default, this does nothing, which is appropriate for replicas which don't keep a log of every change.
+    fn compact(&mut self) -> Result<(), JasonError> {
+        Ok(())
+    }
+}
+
+/// Represents a replica of a database which receives the typed values rather than their JSON representations.
+///
+/// This is intended for in-process replicas which store values as `T`, so they can use the value which has
+///   already been constructed instead of parsing it again. For replicas which only need the JSON, use [`Replica`].
+pub trait TypedReplica<T>: Send + 'static {
+    /// Replicate the change to the replica.
+    fn set_typed(&mut self, key: &str, value: &T) -> Result<(), JasonError>;
+
+    /// Replicate the deletion of the key to the replica.
+    fn delete_typed(&mut self, key: &str) -> Result<(), JasonError>;
+
+    /// Ensures that every change replicated so far has been written to stable storage.
+    ///
+    /// By default, this does nothing, which is appropriate for replicas which write changes durably as they receive them.
+    fn flush(&mut self) -> Result<(), JasonError> {
+        Ok(())
+    }
+
+    /// Compacts the replica, which is called whenever the database it replicates is compacted.
+    ///
+    /// By default, this does nothing, which is appropriate for replicas which don't keep a log of every change.
+    fn compact(&mut self) -> Result<(), JasonError> {
+        Ok(())
+    }
 }
 
 /// Manages replication to a replica.
@@ -31,6 +80,8 @@ pub(crate) enum Replicator<T> {
         thread: Option<JoinHandle<()>>,
         /// A sender to send messages to the thread.
         sender: Sender<ReplicationMessage>,
+        /// The name of the replica, which is included in any errors it causes.
+        name: Option<String>,
     },
 }
 
@@ -38,6 +89,12 @@ pub(crate) enum Replicator<T> {
 pub(crate) enum ReplicationMessage {
     /// Indicates that the thread should replicate this write.
     Replicate(String, String),
+    /// Indicates that the thread should replicate this deletion.
+    Delete(String),
+    /// Indicates that the thread should flush the replica and send the result back once every previous message is handled.
+    Flush(Sender<Result<(), JasonError>>),
+    /// Indicates that the thread should compact the replica and send the result back once every previous message is handled.
+    Compact(Sender<Result<(), JasonError>>),
     /// Indicates that the thread should shut down.
     Shutdown,
 }
@@ -54,22 +111,48 @@ where
         Self::Sync(Box::new(replica))
     }
 
-    /// Creates a new asynchronous replicator.
-    pub fn new_async<R>(mut replica: R) -> Self
+    /// Creates a new asynchronous replicator, optionally with a name to identify the replica in errors.
+    ///
+    /// If a write to the replica fails, the thread stops, so every subsequent write to or flush of the replicator
+    ///   returns `JasonError::NamedReplicaError` with the replica's name, or `JasonError::ReplicaError` if it has none.
+    ///   Other replicas are unaffected.
+    pub fn new_async<R>(mut replica: R, name: Option<String>) -> Self
     where
         R: Replica<T>,
     {
         let (tx, rx): (Sender<ReplicationMessage>, Receiver<ReplicationMessage>) = channel();
+        let thread_name = name.clone();
 
         let handle = spawn(move || {
+            let error = || replica_error(&thread_name);
+
             for msg in rx {
-                match msg {
+                let result = match msg {
                     ReplicationMessage::Replicate(key, value) => {
-                        replica.set(&key, &value).unwrap();
+                        trace!(tracing::Level::TRACE, key, "replicating write");
+                        replica.set(&key, &value)
+                    }
+                    ReplicationMessage::Delete(key) => {
+                        trace!(tracing::Level::TRACE, key, "replicating deletion");
+                        replica.delete(&key)
+                    }
+                    ReplicationMessage::Flush(result) => {
+                        result.send(replica.flush().map_err(|_| error())).ok();
+                        Ok(())
+                    }
+                    ReplicationMessage::Compact(result) => {
+                        trace!(tracing::Level::DEBUG, "compacting replica");
+                        result.send(replica.compact().map_err(|_| error())).ok();
+                        Ok(())
                     }
                     ReplicationMessage::Shutdown => {
                         break;
                     }
+                };
+
+                if result.is_err() {
+                    trace!(tracing::Level::ERROR, name = ?thread_name, "replica failed");
+                    break;
                 }
             }
         });
@@ -77,33 +160,101 @@ where
         Self::Async {
             thread: Some(handle),
             sender: tx,
+            name,
         }
     }
 
     /// Sets the key to the given value in the replica.
     pub fn set(&mut self, key: &str, value: &str) -> Result<(), JasonError> {
         match self {
-            Self::Sync(replica) => replica.set(key, value),
-            Self::Async { sender, .. } => {
+            Self::Sync(replica) => {
+                trace!(tracing::Level::TRACE, key, "replicating write");
+                replica.set(key, value)
+            }
+            Self::Async { sender, name, .. } => {
                 let msg = ReplicationMessage::Replicate(key.to_string(), value.to_string());
 
-                sender.send(msg).map_err(|_| JasonError::ReplicaError)?;
+                sender.send(msg).map_err(|_| replica_error(name))?;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Deletes the key from the replica.
+    pub fn delete(&mut self, key: &str) -> Result<(), JasonError> {
+        match self {
+            Self::Sync(replica) => {
+                trace!(tracing::Level::TRACE, key, "replicating deletion");
+                replica.delete(key)
+            }
+            Self::Async { sender, name, .. } => {
+                let msg = ReplicationMessage::Delete(key.to_string());
+
+                sender.send(msg).map_err(|_| replica_error(name))?;
 
                 Ok(())
             }
         }
     }
+
+    /// Flushes the replica, waiting for any pending asynchronous writes to be replicated first.
+    pub fn flush(&mut self) -> Result<(), JasonError> {
+        trace!(tracing::Level::DEBUG, "flushing replica");
+
+        match self {
+            Self::Sync(replica) => replica.flush(),
+            Self::Async { sender, name, .. } => wait_for(sender, name, ReplicationMessage::Flush),
+        }
+    }
+
+    /// Compacts the replica, waiting for any pending asynchronous writes to be replicated first.
+    pub fn compact(&mut self) -> Result<(), JasonError> {
+        match self {
+            Self::Sync(replica) => {
+                trace!(tracing::Level::DEBUG, "compacting replica");
+                replica.compact()
+            }
+            Self::Async { sender, name, .. } => wait_for(sender, name, ReplicationMessage::Compact),
+        }
+    }
+}
+
+/// Returns the error for a failure of the asynchronous replica with the given name.
+fn replica_error(name: &Option<String>) -> JasonError {
+    match name {
+        Some(name) => JasonError::NamedReplicaError(name.clone()),
+        None => JasonError::ReplicaError,
+    }
+}
+
+/// Sends the message to an asynchronous replica's thread, then waits for the result to be sent back.
+fn wait_for<F>(
+    sender: &Sender<ReplicationMessage>,
+    name: &Option<String>,
+    message: F,
+) -> Result<(), JasonError>
+where
+    F: FnOnce(Sender<Result<(), JasonError>>) -> ReplicationMessage,
+{
+    let error = || replica_error(name);
+    let (tx, rx) = channel();
+
+    sender.send(message(tx)).map_err(|_| error())?;
+
+    rx.recv().map_err(|_| error())?
 }
 
 impl<T> Drop for Replicator<T> {
     fn drop(&mut self) {
         match self {
             Self::Sync(_) => (),
-            Self::Async { thread, sender } => {
-                sender.send(ReplicationMessage::Shutdown).unwrap();
+            Self::Async { thread, sender, .. } => {
+                // The thread will have already stopped if the replica failed.
+                sender.send(ReplicationMessage::Shutdown).ok();
 
                 if let Some(thread) = thread.take() {
-                    thread.join().unwrap();
+                    thread.join().ok();
                 }
             }
         }
@@ -118,4 +269,38 @@ where
     fn set(&mut self, key: &str, value: &str) -> Result<(), JasonError> {
         self.set_raw(key, value.as_bytes())
     }
+
+    fn delete(&mut self, key: &str) -> Result<(), JasonError> {
+        self.delete_raw(key)
+    }
+
+    fn flush(&mut self) -> Result<(), JasonError> {
+        self.checkpoint().map(|_| ())
+    }
+
+    fn compact(&mut self) -> Result<(), JasonError> {
+        Database::compact(self)
+    }
+}
+
+impl<T, S> TypedReplica<T> for Database<T, S>
+where
+    T: IntoJson + FromJson + Send + 'static,
+    S: Source + Send + 'static,
+{
+    fn set_typed(&mut self, key: &str, value: &T) -> Result<(), JasonError> {
+        self.set(key, value)
+    }
+
+    fn delete_typed(&mut self, key: &str) -> Result<(), JasonError> {
+        self.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<(), JasonError> {
+        self.checkpoint().map(|_| ())
+    }
+
+    fn compact(&mut self) -> Result<(), JasonError> {
+        Database::compact(self)
+    }
 }
@@ -0,0 +1,119 @@
+//! Provides the `ScheduledDatabase` type, which compacts a database periodically on a background thread.
+
+use crate::database::Database;
+use crate::sources::Source;
+use crate::util::trace;
+
+use humphrey_json::prelude::*;
+
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+/// A database which is compacted periodically on a background thread, created with
+///   [`Database::with_background_compaction`].
+///
+/// Since compaction needs exclusive access, the database is held in an `Arc<Mutex<_>>` which is shared with the
+///   background thread. It can be used directly with `lock`, or shared with other threads with `shared`. The background
+///   thread is stopped when this is dropped or when `stop` is called.
+///
+/// ## Example
+/// ```
+/// let db = Database::new("database.jdb")?.with_background_compaction(Duration::from_secs(3600));
+///
+/// db.lock().set("key", value)?;
+/// ```
+pub struct ScheduledDatabase<T, S>
+where
+    T: IntoJson + FromJson + Send + 'static,
+    S: Source + Send + 'static,
+{
+    database: Arc<Mutex<Database<T, S>>>,
+    sender: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T, S> ScheduledDatabase<T, S>
+where
+    T: IntoJson + FromJson + Send + 'static,
+    S: Source + Send + 'static,
+{
+    /// Starts a background thread which compacts the database every `interval`.
+    pub(crate) fn new(database: Database<T, S>, interval: Duration) -> Self {
+        let database = Arc::new(Mutex::new(database));
+        let thread_database = database.clone();
+        let (sender, rx) = channel();
+
+        // The thread stops when it is signalled, or when the sender is dropped.
+        let thread = spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                let mut database = match thread_database.lock() {
+                    Ok(database) => database,
+                    Err(_) => break,
+                };
+
+                // Databases with nothing to reclaim are not rewritten.
+                let result = database.compaction_estimate().and_then(|estimate| {
+                    match estimate.bytes_reclaimable() {
+                        0 => Ok(()),
+                        _ => database.compact(),
+                    }
+                });
+
+                if result.is_err() {
+                    trace!(tracing::Level::ERROR, "scheduled compaction failed");
+                }
+            }
+        });
+
+        Self {
+            database,
+            sender,
+            thread: Some(thread),
+        }
+    }
+
+    /// Locks the database for use, waiting for any compaction in progress to finish.
+    ///
+    /// ## Panics
+    /// This function will panic if another thread panicked while the database was locked.
+    pub fn lock(&self) -> MutexGuard<'_, Database<T, S>> {
+        self.database.lock().unwrap()
+    }
+
+    /// Returns a shared reference to the database, which can be sent to other threads.
+    ///
+    /// The background thread keeps compacting the database until this is dropped or stopped, even if the returned
+    ///   reference is still in use.
+    pub fn shared(&self) -> Arc<Mutex<Database<T, S>>> {
+        self.database.clone()
+    }
+
+    /// Stops the background thread, waiting for any compaction in progress to finish, and returns the database.
+    ///
+    /// If there are no other shared references, the database can be unwrapped with `Arc::try_unwrap`.
+    pub fn stop(mut self) -> Arc<Mutex<Database<T, S>>> {
+        self.stop_thread();
+        self.database.clone()
+    }
+
+    /// Signals the background thread to stop and waits for it.
+    fn stop_thread(&mut self) {
+        self.sender.send(()).ok();
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl<T, S> Drop for ScheduledDatabase<T, S>
+where
+    T: IntoJson + FromJson + Send + 'static,
+    S: Source + Send + 'static,
+{
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
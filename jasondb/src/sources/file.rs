@@ -1,17 +1,106 @@
 use crate::error::JasonError;
-use crate::sources::{InMemory, Source};
+use crate::sources::{is_tombstone, CompactionStats, InMemory, SharedRead, Source};
 use crate::util::{indexing, quiet_assert};
 
 use humphrey_json::prelude::*;
 use humphrey_json::Value;
 
 use std::collections::{BTreeSet, HashMap};
-use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::thread::{spawn, JoinHandle};
+
+/// Specifies how [`FileSource::compact_with`] builds the compacted database before it replaces the original.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactStrategy {
+    /// Writes the compacted data to a temporary file alongside the database, then renames it over the original (default).
+    ///
+    /// This never holds more than one entry in memory, but it requires permission to create files in the database's directory.
+    #[default]
+    TempFile,
+    /// Builds the compacted data in memory, then truncates the original file and writes it back in one go.
+    ///
+    /// This avoids creating any other files, which is useful on read-only or memory-only filesystems, but the whole compacted
+    ///   database must fit in memory. If the final write fails, the database file may be left incomplete.
+    InMemory,
+}
+
+/// The magic bytes at the start of every JasonDB file with a header.
+const MAGIC: &[u8; 8] = b"JASONDB\0";
+
+/// The current version of the file format, which is written in the header of new files.
+///
+/// Version 0 refers to files from before the header was introduced, which are still supported.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// The length of the header in bytes, which consists of the magic bytes followed by the format version.
+const HEADER_LEN: u64 = 16;
+
+/// The length of a tar header block, which is how databases from the JasonDB server start.
+const TAR_BLOCK_LEN: u64 = 512;
+
+/// Represents a compaction of a file-based database source which is running on a background thread.
+///
+/// Created with `FileSource::start_compaction` or `Database::start_compaction`.
+pub struct BackgroundCompaction {
+    thread: JoinHandle<Result<u64, JasonError>>,
+    temp_path: PathBuf,
+    snapshot_len: u64,
+    generation: u64,
+}
+
+impl BackgroundCompaction {
+    /// Returns `true` if the background thread has finished copying live entries, so finishing the compaction won't block
+    ///   for long.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+/// A replacement for a file-based database source which is being written to a temporary file one entry at a time.
+///
+/// Created with `FileSource::start_rewrite`.
+pub(crate) struct Rewrite {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    len: u64,
+}
+
+impl Rewrite {
+    /// Writes an entry to the replacement, returning its offset.
+    pub(crate) fn write_entry(&mut self, k: &str, v: &[u8]) -> Result<u64, JasonError> {
+        let offset = self.len;
+
+        let parts: [&[u8]; 4] = [
+            &(k.len() as u64).to_le_bytes(),
+            k.as_bytes(),
+            &(v.len() as u64).to_le_bytes(),
+            v,
+        ];
+
+        for part in parts {
+            self.writer.write_all(part).map_err(|_| JasonError::Io)?;
+        }
+
+        self.len += (k.len() + v.len() + 16) as u64;
+
+        Ok(offset)
+    }
+}
 
 /// Represents a file-based database source.
 ///
+/// New files start with a header identifying them as JasonDB files and specifying the format version, which is checked when
+///   they are opened. Files without a header are treated as version 0 and are upgraded when they are compacted or migrated.
+///   Version 0 files mark deletions with `null` values, so until they are upgraded, writing a `null` value to one returns
+///   `JasonError::BadFormat` rather than deleting the key.
+///   Offsets exposed by the source are relative to the end of the header, so they are the same as in an equivalent
+///   in-memory source.
+///
+/// Writes go straight to the file unless a write buffer is enabled with `FileSource::with_write_buffer`, but they are
+///   only durable once the source is flushed, unless every write is synchronised with `FileSource::with_sync`.
+///
 /// ## Example
 /// ```
 /// let source = FileSource::new("database.jdb");
@@ -21,11 +110,26 @@ pub struct FileSource {
     pub(crate) file: File,
     pub(crate) path: PathBuf,
     pub(crate) len: u64,
+    pub(crate) version: u64,
+    pub(crate) writable: bool,
+    pub(crate) generation: u64,
+    pub(crate) buffer: Vec<u8>,
+    pub(crate) buffer_capacity: usize,
+    pub(crate) sync: bool,
 }
 
 impl FileSource {
     /// Opens the file-based database source from the given path, or creates an empty one if it doesn't exist.
     ///
+    /// If the file is empty, a header is written to it. Otherwise, its header is validated, and `JasonError::BadFormat` is
+    ///   returned if it was written by a newer version of JasonDB. Databases from the JasonDB server are tar archives, which
+    ///   share the `.jdb` extension but not the format, so `JasonError::IncompatibleFormat` is returned for them instead
+    ///   of reading them as garbage.
+    ///
+    /// The file is locked for writing until the source is dropped, and `JasonError::Locked` is returned if another source
+    ///   already has it open for writing, even in the same process. The lock is advisory, so other programs can still modify the file.
+    ///   To read a database which is open elsewhere, use `FileSource::open_read_only`.
+    ///
     /// To create an empty database and throw an error if it already exists, use `FileSource::create`.
     /// To open an existing database and throw an error if it doesn't exist, use `FileSource::open`.
     pub fn new(path: impl AsRef<Path>) -> Result<Self, JasonError> {
@@ -36,15 +140,57 @@ impl FileSource {
             .open(&path)
             .map_err(|_| JasonError::Io)?;
 
-        let meta = file.metadata().map_err(|_| JasonError::Io)?;
-        let len = meta.len();
+        Self::from_file(file, path, true)
+    }
+
+    /// Opens an existing file-based database source at the given path without locking it, so it can be read while it is
+    ///   open for writing elsewhere.
+    ///
+    /// Any attempt to write to the source will return `JasonError::Io`. Writes made elsewhere can be picked up by refreshing the source,
+    ///   which returns `JasonError::Replaced` if the file has been compacted or migrated elsewhere since it was opened.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self, JasonError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(&path)
+            .map_err(|_| JasonError::Io)?;
+
+        Self::from_file(file, path, false)
+    }
+
+    /// Creates the source from the given open file, locking it and writing a header to it if it is writable.
+    fn from_file(
+        mut file: File,
+        path: impl AsRef<Path>,
+        writable: bool,
+    ) -> Result<Self, JasonError> {
+        if writable {
+            lock(&file)?;
+        }
 
+        let meta = file.metadata().map_err(|_| JasonError::Io)?;
         quiet_assert(meta.is_file(), JasonError::Io)?;
 
+        // An empty file opened as read-only will have a header written by whichever source is writing to it.
+        let version = if meta.len() == 0 {
+            if writable {
+                write_header(&mut file)?;
+            }
+
+            FORMAT_VERSION
+        } else {
+            read_header(&mut file, meta.len())?
+        };
+
         Ok(Self {
             file,
             path: path.as_ref().to_path_buf(),
-            len,
+            len: meta.len().saturating_sub(header_len(version)),
+            version,
+            writable,
+            generation: 0,
+            buffer: Vec::new(),
+            buffer_capacity: 0,
+            sync: false,
         })
     }
 
@@ -56,6 +202,16 @@ impl FileSource {
         Self::new(path)
     }
 
+    /// Creates a new empty file-based database source at the given path, or opens it if it exists but contains no entries.
+    ///
+    /// If the file already contains entries, `JasonError::AlreadyExists` will be thrown.
+    pub fn create_exclusive(path: impl AsRef<Path>) -> Result<Self, JasonError> {
+        let source = Self::new(path)?;
+        quiet_assert(source.len == 0, JasonError::AlreadyExists)?;
+
+        Ok(source)
+    }
+
     /// Opens an existing file-based database source at the given path.
     ///
     /// If the file doesn't exist, an error will be thrown.
@@ -64,6 +220,67 @@ impl FileSource {
         Self::new(path)
     }
 
+    /// Enables the write buffer, so that new entries are collected in memory and written to the file together once
+    ///   `capacity` bytes have built up, rather than with several writes each.
+    ///
+    /// Since reads and writes share the same file handle, the buffer is written out before anything is read from the file,
+    ///   as well as when the source is flushed, compacted, migrated, truncated, refreshed or dropped. Buffered entries
+    ///   are lost if the process exits without dropping the source, so call `Source::flush` at points which must be durable.
+    ///
+    /// If writing out the buffer fails, every buffered entry is discarded and the error is returned by whichever operation
+    ///   wrote it out. The offsets of the discarded entries may already have been returned by `Source::write_entry`, so
+    ///   a database using the source must be reloaded with `Database::reload` before it is used again.
+    ///
+    /// A capacity of zero disables the buffer.
+    ///
+    /// ## Example
+    /// ```
+    /// let source = FileSource::new("database.jdb")?.with_write_buffer(64 * 1024);
+    /// let mut db: Database<String> = Database::from_source(source)?;
+    /// ```
+    pub fn with_write_buffer(mut self, capacity: usize) -> Self {
+        self.buffer_capacity = capacity;
+        self.buffer.reserve(capacity);
+
+        self
+    }
+
+    /// Enables or disables synchronising every write to stable storage before it returns.
+    ///
+    /// When enabled, each new entry is written to the file and then `fsync`ed, so that it survives a crash of the process
+    ///   or the machine as soon as the write returns. Any write buffer is written out with every entry. This makes writes
+    ///   much slower, so it is disabled by default, in which case writes are only durable once `Source::flush` is called.
+    ///
+    /// ## Example
+    /// ```
+    /// let source = FileSource::new("ledger.jdb")?.with_sync(true);
+    /// let mut db: Database<Transaction> = Database::from_source(source)?;
+    /// ```
+    pub fn with_sync(mut self, enabled: bool) -> Self {
+        self.sync = enabled;
+        self
+    }
+
+    /// Writes any buffered entries to the file.
+    fn flush_buffer(&mut self) -> Result<(), JasonError> {
+        if !self.buffer.is_empty() {
+            let result = self.file.write_all(&self.buffer);
+
+            // If the write fails, the buffered entries can't be trusted to be anywhere in the file, so they are discarded
+            //   along with their part of the length, and any part of them which was written is cut off so that later
+            //   entries are written where the length says they are.
+            if result.is_err() {
+                self.len -= self.buffer.len() as u64;
+                self.file.set_len(self.data_start() + self.len).ok();
+            }
+
+            self.buffer.clear();
+            result.map_err(|_| JasonError::Io)?;
+        }
+
+        Ok(())
+    }
+
     /// Converts the file source into an in-memory source by copying the contents of the file into memory.
     ///
     /// **Warning:** changes made to the new in-memory source will not be reflected in the original file source. If you're looking
@@ -76,21 +293,315 @@ impl FileSource {
     ///     .with_replica(Database::open("database.jdb")?);   // Replicate subsequent writes back to the file
     /// ```
     pub fn into_memory(mut self) -> Result<InMemory, JasonError> {
+        self.flush_buffer()?;
+
         let mut buf: Vec<u8> = Vec::with_capacity(self.len as usize);
 
-        self.file.rewind().map_err(|_| JasonError::Io)?;
+        self.file
+            .seek(SeekFrom::Start(self.data_start()))
+            .map_err(|_| JasonError::Io)?;
         self.file
             .read_to_end(&mut buf)
             .map_err(|_| JasonError::Io)?;
 
-        Ok(InMemory { data: buf })
+        Ok(InMemory {
+            data: buf,
+            capacity: None,
+            version: self.version,
+        })
+    }
+
+    /// Returns the offset in the file at which the entries start.
+    fn data_start(&self) -> u64 {
+        header_len(self.version)
+    }
+
+    /// Returns `true` if the file at the source's path is no longer the open file, or if the open file's header no longer
+    ///   matches its cached format version, which happens when the file is compacted or migrated by another source.
+    ///
+    /// Whether the path refers to a different file can only be detected on Unix.
+    fn is_replaced(&self, file_len: u64) -> Result<bool, JasonError> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let open = self.file.metadata().map_err(|_| JasonError::Io)?;
+
+            match fs::metadata(&self.path) {
+                Ok(current) if current.dev() == open.dev() && current.ino() == open.ino() => (),
+                _ => return Ok(true),
+            }
+        }
+
+        if file_len == 0 {
+            return Ok(false);
+        }
+
+        let mut header = vec![0u8; file_len.min(HEADER_LEN) as usize];
+        read_exact_at(&self.file, &mut header, 0)?;
+
+        Ok(parse_header(&header)? != self.version)
+    }
+
+    /// Compacts the database using the given strategy, removing all deleted entries to save space. Returns the size of the
+    ///   database before and after, excluding the header.
+    ///
+    /// `Source::compact` uses [`CompactStrategy::TempFile`].
+    pub fn compact_with(
+        &mut self,
+        indexes: &HashMap<String, u64>,
+        strategy: CompactStrategy,
+    ) -> Result<CompactionStats, JasonError> {
+        self.flush_buffer()?;
+
+        let bytes_before = self.len;
+
+        match strategy {
+            CompactStrategy::TempFile => self.compact_to_temp_file(indexes)?,
+            CompactStrategy::InMemory => self.compact_in_memory(indexes)?,
+        }
+
+        Ok(CompactionStats {
+            bytes_before,
+            bytes_after: self.len,
+        })
+    }
+
+    /// Compacts the database by writing the live entries to a temporary file and then renaming it over the original.
+    fn compact_to_temp_file(&mut self, indexes: &HashMap<String, u64>) -> Result<(), JasonError> {
+        let mut rewrite = self.start_rewrite()?;
+        rewrite.len = self.copy_entries(indexes, &mut rewrite.writer)?;
+
+        self.finish_rewrite(rewrite)
+    }
+
+    /// Starts writing a replacement for the database to a temporary file, which the caller fills with entries, typically
+    ///   read from this source.
+    ///
+    /// The replacement only takes effect once it is passed to `FileSource::finish_rewrite`.
+    pub(crate) fn start_rewrite(&mut self) -> Result<Rewrite, JasonError> {
+        quiet_assert(self.writable, JasonError::Io)?;
+        self.flush_buffer()?;
+
+        let path = self.path.with_extension("jdbtmp");
+        if path.exists() {
+            fs::remove_file(&path).map_err(|_| JasonError::Io)?;
+        }
+
+        let mut new_file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .map_err(|_| JasonError::Io)?;
+        write_header(&mut new_file)?;
+
+        Ok(Rewrite {
+            writer: BufWriter::new(new_file),
+            path,
+            len: 0,
+        })
+    }
+
+    /// Replaces the file with the replacement started with `FileSource::start_rewrite`. Any offsets read from the source
+    ///   before are invalidated.
+    pub(crate) fn finish_rewrite(&mut self, rewrite: Rewrite) -> Result<(), JasonError> {
+        let new_file = rewrite.writer.into_inner().map_err(|_| JasonError::Io)?;
+        drop(new_file);
+
+        self.replace_with(&rewrite.path, rewrite.len)
+    }
+
+    /// Replaces the file with the complete database at the given temporary path, which has the given length excluding its header.
+    fn replace_with(&mut self, temp_path: &Path, new_len: u64) -> Result<(), JasonError> {
+        fs::rename(&self.path, self.path.with_extension("jdbold")).map_err(|_| JasonError::Io)?;
+        fs::rename(temp_path, &self.path).map_err(|_| JasonError::Io)?;
+
+        let new_file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|_| JasonError::Io)?;
+        lock(&new_file)?;
+
+        let _old_file = std::mem::replace(&mut self.file, new_file);
+        self.len = new_len;
+        self.version = FORMAT_VERSION;
+        self.generation += 1;
+
+        fs::remove_file(self.path.with_extension("jdbold")).map_err(|_| JasonError::Io)?;
+
+        Ok(())
+    }
+
+    /// Starts compacting the database on a background thread, copying the live entries at the given offsets to a
+    ///   temporary file without blocking reads or writes.
+    ///
+    /// The compaction only takes effect once it is passed to `FileSource::finish_compaction`, which copies any entries written
+    ///   in the meantime and then replaces the file. If the returned value is dropped instead, the compaction is abandoned.
+    ///   Entries still in the write buffer are treated as if they were written after the compaction started.
+    pub fn start_compaction(
+        &self,
+        indexes: &HashMap<String, u64>,
+    ) -> Result<BackgroundCompaction, JasonError> {
+        quiet_assert(self.writable, JasonError::Io)?;
+
+        let temp_path = self.path.with_extension("jdbcompact");
+        if temp_path.exists() {
+            fs::remove_file(&temp_path).map_err(|_| JasonError::Io)?;
+        }
+
+        let mut new_file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&temp_path)
+            .map_err(|_| JasonError::Io)?;
+        write_header(&mut new_file)?;
+
+        // The background thread reads through its own handle so that it doesn't share a file position with this one.
+        //   Entries before the current length are never modified by writes, so it doesn't need any synchronisation.
+        //   Buffered entries aren't in the file yet, so they are left for `finish_compaction` to copy.
+        let snapshot_len = self.len - self.buffer.len() as u64;
+        let mut snapshot = FileSource {
+            file: File::open(&self.path).map_err(|_| JasonError::Io)?,
+            path: self.path.clone(),
+            len: snapshot_len,
+            version: self.version,
+            writable: false,
+            generation: self.generation,
+            buffer: Vec::new(),
+            buffer_capacity: 0,
+            sync: false,
+        };
+        let indexes = indexes
+            .iter()
+            .filter(|(_, &offset)| offset < snapshot_len)
+            .map(|(k, &offset)| (k.clone(), offset))
+            .collect::<HashMap<_, _>>();
+
+        let thread = spawn(move || snapshot.copy_entries(&indexes, &mut new_file));
+
+        Ok(BackgroundCompaction {
+            thread,
+            temp_path,
+            snapshot_len,
+            generation: self.generation,
+        })
+    }
+
+    /// Finishes a compaction started with `FileSource::start_compaction`, waiting for the background thread if necessary.
+    ///
+    /// Entries written since the compaction started are appended to the compacted data, then the file is replaced with it.
+    ///   Any indexes loaded from the source must be reloaded afterwards. Returns `Err(JasonError::Index)` if the source has been
+    ///   compacted, migrated or truncated since the compaction started, in which case the compaction is abandoned.
+    pub fn finish_compaction(
+        &mut self,
+        compaction: BackgroundCompaction,
+    ) -> Result<(), JasonError> {
+        let result = compaction.thread.join().map_err(|_| JasonError::Unknown);
+
+        if compaction.generation != self.generation || self.len < compaction.snapshot_len {
+            fs::remove_file(&compaction.temp_path).ok();
+            return Err(JasonError::Index);
+        }
+
+        let mut new_len = match result {
+            Ok(Ok(new_len)) => new_len,
+            Ok(Err(e)) | Err(e) => {
+                fs::remove_file(&compaction.temp_path).ok();
+                return Err(e);
+            }
+        };
+
+        let mut new_file = OpenOptions::new()
+            .append(true)
+            .open(&compaction.temp_path)
+            .map_err(|_| JasonError::Io)?;
+
+        let version = self.version;
+
+        for entry in self.iter_raw_from(compaction.snapshot_len) {
+            let (k, mut v, _) = entry?;
+
+            // Deletions are rewritten in the current format, since the compacted file has a header.
+            if is_tombstone(version, &v) {
+                v.clear();
+            }
+
+            new_file
+                .write_all(&k.len().to_le_bytes())
+                .map_err(|_| JasonError::Io)?;
+            new_file
+                .write_all(k.as_bytes())
+                .map_err(|_| JasonError::Io)?;
+            new_file
+                .write_all(&v.len().to_le_bytes())
+                .map_err(|_| JasonError::Io)?;
+            new_file.write_all(&v).map_err(|_| JasonError::Io)?;
+
+            new_len += (k.len() + v.len() + 16) as u64;
+        }
+
+        drop(new_file);
+
+        self.replace_with(&compaction.temp_path, new_len)
+    }
+
+    /// Compacts the database by copying the live entries into a buffer and then rewriting the original file with it.
+    fn compact_in_memory(&mut self, indexes: &HashMap<String, u64>) -> Result<(), JasonError> {
+        quiet_assert(self.writable, JasonError::Io)?;
+
+        let mut buf: Vec<u8> = Vec::new();
+        let new_len = self.copy_entries(indexes, &mut buf)?;
+
+        self.file.set_len(0).map_err(|_| JasonError::Io)?;
+        self.len = 0;
+        write_header(&mut self.file)?;
+        self.version = FORMAT_VERSION;
+        self.file.write_all(&buf).map_err(|_| JasonError::Io)?;
+        self.len = new_len;
+        self.generation += 1;
+
+        Ok(())
+    }
+
+    /// Copies the entries at the given offsets to the writer, skipping any which mark deletions. Returns the number of bytes written.
+    fn copy_entries(
+        &mut self,
+        indexes: &HashMap<String, u64>,
+        writer: &mut impl Write,
+    ) -> Result<u64, JasonError> {
+        let mut new_len: u64 = 0;
+
+        for &start_index in indexes.values() {
+            let v_index = start_index + self.load_size(start_index)? + 8;
+            let end_index = v_index + self.load_size(v_index)? + 8;
+
+            let mut buf: Vec<u8> = vec![0; (end_index - start_index) as usize];
+            self.file
+                .seek(SeekFrom::Start(self.data_start() + start_index))
+                .map_err(|_| JasonError::Index)?;
+            self.file.read_exact(&mut buf).map_err(|_| JasonError::Io)?;
+
+            if is_tombstone(self.version, &buf[(v_index - start_index + 8) as usize..]) {
+                continue;
+            }
+
+            writer.write_all(&buf).map_err(|_| JasonError::Io)?;
+            new_len += buf.len() as u64;
+        }
+
+        Ok(new_len)
     }
 
     /// Loads the size of a database entry from the given offset.
     fn load_size(&mut self, offset: u64) -> Result<u64, JasonError> {
+        let end = offset.checked_add(8).ok_or(JasonError::Index)?;
+        quiet_assert(end <= self.len, JasonError::Index)?;
+        self.flush_buffer()?;
+
         let mut size_buf = [0u8; 8];
         self.file
-            .seek(SeekFrom::Start(offset))
+            .seek(SeekFrom::Start(self.data_start() + offset))
             .map_err(|_| JasonError::Index)?;
         self.file
             .read_exact(&mut size_buf)
@@ -102,9 +613,11 @@ impl FileSource {
     /// Loads an arbitrary value from the data at the given offset.
     fn load_value(&mut self, offset: u64) -> Result<(Vec<u8>, u64), JasonError> {
         let size = self.load_size(offset)?;
+        quiet_assert(size <= self.len - offset - 8, JasonError::Index)?;
+
         let mut data: Vec<u8> = vec![0; size as usize];
         self.file
-            .seek(SeekFrom::Start(offset + 8))
+            .seek(SeekFrom::Start(self.data_start() + offset + 8))
             .map_err(|_| JasonError::Index)?;
         self.file
             .read_exact(&mut data)
@@ -112,6 +625,51 @@ impl FileSource {
 
         Ok((data, offset + 8 + size))
     }
+
+    /// Fills the buffer with the data at the given offset without moving the file's position, reading from the write buffer
+    ///   if the data hasn't been written to the file yet.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<(), JasonError> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or(JasonError::Index)?;
+        quiet_assert(end <= self.len, JasonError::Index)?;
+
+        // The buffer is always written out in full, so an entry is either entirely in the file or entirely in the buffer.
+        let flushed_len = self.len - self.buffer.len() as u64;
+
+        if offset >= flushed_len {
+            let start = (offset - flushed_len) as usize;
+            buf.copy_from_slice(&self.buffer[start..start + buf.len()]);
+
+            Ok(())
+        } else {
+            quiet_assert(end <= flushed_len, JasonError::Index)?;
+            read_exact_at(&self.file, buf, self.data_start() + offset)
+        }
+    }
+
+    /// Loads an arbitrary value from the data at the given offset using positioned reads.
+    fn load_value_at(&self, offset: u64) -> Result<(Vec<u8>, u64), JasonError> {
+        let mut size_buf = [0u8; 8];
+        self.read_at(&mut size_buf, offset)?;
+
+        let size = u64::from_le_bytes(size_buf);
+        quiet_assert(size <= self.len - offset - 8, JasonError::Index)?;
+
+        let mut data: Vec<u8> = vec![0; size as usize];
+        self.read_at(&mut data, offset + 8)?;
+
+        Ok((data, offset + 8 + size))
+    }
+}
+
+impl SharedRead for FileSource {
+    fn read_entry_at(&self, offset: u64) -> Result<(String, Vec<u8>), JasonError> {
+        let (k, v_index) = self.load_value_at(offset)?;
+        let (v, _) = self.load_value_at(v_index)?;
+
+        Ok((unsafe { String::from_utf8_unchecked(k) }, v))
+    }
 }
 
 impl Source for FileSource {
@@ -127,39 +685,171 @@ impl Source for FileSource {
         let v = v.as_ref();
         let size = k.len() + v.len() + 16;
 
+        quiet_assert(
+            v.is_empty() || !is_tombstone(self.version, v),
+            JasonError::BadFormat,
+        )?;
+
+        if self.buffer_capacity > 0 {
+            // Writes to a read-only file would otherwise only fail when the buffer is flushed.
+            quiet_assert(self.writable, JasonError::Io)?;
+
+            self.buffer.extend_from_slice(&k.len().to_le_bytes());
+            self.buffer.extend_from_slice(k.as_bytes());
+            self.buffer.extend_from_slice(&v.len().to_le_bytes());
+            self.buffer.extend_from_slice(v);
+            self.len += size as u64;
+
+            if self.buffer.len() >= self.buffer_capacity || self.sync {
+                self.flush_buffer()?;
+            }
+        } else {
+            self.file
+                .write_all(&k.len().to_le_bytes())
+                .map_err(|_| JasonError::Io)?;
+            self.file
+                .write_all(k.as_bytes())
+                .map_err(|_| JasonError::Io)?;
+            self.file
+                .write_all(&v.len().to_le_bytes())
+                .map_err(|_| JasonError::Io)?;
+            self.file.write_all(v).map_err(|_| JasonError::Io)?;
+
+            self.len += size as u64;
+        }
+
+        if self.sync {
+            self.file.sync_data().map_err(|_| JasonError::Io)?;
+        }
+
+        Ok(self.len - size as u64)
+    }
+
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn size(&mut self) -> u64 {
+        self.len
+    }
+
+    /// Returns the format version of the file, which is 0 if the file has no header.
+    fn format_version(&self) -> u64 {
+        self.version
+    }
+
+    fn entry_size(&mut self, offset: u64) -> Result<u64, JasonError> {
+        let key_size = self.load_size(offset)?;
+        let value_size = self.load_size(offset + 8 + key_size)?;
+
+        Ok(key_size + value_size + 16)
+    }
+
+    fn truncate_to(&mut self, offset: u64) -> Result<(), JasonError> {
+        quiet_assert(offset <= self.len, JasonError::Index)?;
+        self.flush_buffer()?;
+
         self.file
-            .write_all(&k.len().to_le_bytes())
-            .map_err(|_| JasonError::Io)?;
-        self.file
-            .write_all(k.as_bytes())
-            .map_err(|_| JasonError::Io)?;
-        self.file
-            .write_all(&v.len().to_le_bytes())
+            .set_len(self.data_start() + offset)
             .map_err(|_| JasonError::Io)?;
-        self.file.write_all(v).map_err(|_| JasonError::Io)?;
+        self.len = offset;
+        self.generation += 1;
+
+        Ok(())
+    }
 
-        self.len += size as u64;
+    fn refresh(&mut self) -> Result<(), JasonError> {
+        self.flush_buffer()?;
 
-        Ok(self.len - size as u64)
+        let file_len = self.file.metadata().map_err(|_| JasonError::Io)?.len();
+        let len = file_len.saturating_sub(self.data_start());
+
+        // Writable sources hold the lock, so only read-only sources can have their file compacted or migrated by another source.
+        if !self.writable {
+            quiet_assert(
+                len >= self.len && !self.is_replaced(file_len)?,
+                JasonError::Replaced,
+            )?;
+        }
+
+        self.len = len;
+
+        Ok(())
+    }
+
+    fn iter_raw_from(
+        &mut self,
+        offset: u64,
+    ) -> impl Iterator<Item = Result<(String, Vec<u8>, u64), JasonError>> + '_ {
+        let mut flushed = Some(self.flush_buffer());
+        let len = self.len;
+        let data_start = self.data_start();
+        let mut reader = BufReader::new(&self.file);
+        let mut offset = offset;
+        let mut started = false;
+
+        std::iter::from_fn(move || {
+            if offset >= len {
+                return None;
+            }
+
+            if let Some(Err(e)) = flushed.take() {
+                offset = len;
+                return Some(Err(e));
+            }
+
+            if !started {
+                started = true;
+
+                if reader.seek(SeekFrom::Start(data_start + offset)).is_err() {
+                    offset = len;
+                    return Some(Err(JasonError::Index));
+                }
+            }
+
+            let entry = read_sized(&mut reader, len - offset).and_then(|k| {
+                let v = read_sized(&mut reader, len - offset - 8 - k.len() as u64)?;
+
+                Ok((k, v))
+            });
+
+            match entry {
+                Ok((k, v)) => {
+                    let entry_offset = offset;
+                    offset += (k.len() + v.len() + 16) as u64;
+
+                    Some(Ok((
+                        unsafe { String::from_utf8_unchecked(k) },
+                        v,
+                        entry_offset,
+                    )))
+                }
+                Err(e) => {
+                    offset = len;
+
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    fn flush(&mut self) -> Result<(), JasonError> {
+        self.flush_buffer()?;
+        self.file.sync_data().map_err(|_| JasonError::Io)
     }
 
     fn load_indexes(&mut self) -> Result<HashMap<String, u64>, JasonError> {
         let mut indexes: HashMap<String, u64> = HashMap::new();
-        let mut offset = 0;
-
-        while offset < self.len {
-            let (k, v_index) = self.load_value(offset)?;
-            let (v, new_offset) = self.load_value(v_index)?;
+        let version = self.version;
 
-            let key = unsafe { String::from_utf8_unchecked(k) };
+        for entry in self.iter_raw() {
+            let (key, v, offset) = entry?;
 
-            if v == b"null" {
+            if is_tombstone(version, &v) {
                 indexes.remove(&key);
             } else {
                 indexes.insert(key, offset);
             }
-
-            offset = new_offset;
         }
 
         Ok(indexes)
@@ -173,64 +863,18 @@ impl Source for FileSource {
         let mut indexes: HashMap<Value, BTreeSet<u64>> = HashMap::new();
 
         for i in primary_indexes.values() {
-            let (_, v) = self.read_entry(*i)?;
-            let json = unsafe { String::from_utf8_unchecked(v) };
-            let value = Value::parse(json).map_err(|_| JasonError::JsonError)?;
+            let (key, v) = self.read_entry(*i)?;
+            let value = indexing::parse_entry_value(key, v)?;
             let indexed_value = indexing::get_value(k.as_ref(), &value);
 
-            indexes
-                .entry(indexed_value)
-                .or_insert_with(BTreeSet::new)
-                .insert(*i);
+            indexes.entry(indexed_value).or_default().insert(*i);
         }
 
         Ok(indexes)
     }
 
-    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<(), JasonError> {
-        let temp_path = self.path.with_extension("jdbtmp");
-        if temp_path.exists() {
-            fs::remove_file(&temp_path).map_err(|_| JasonError::Io)?;
-        }
-
-        let mut new_file = OpenOptions::new()
-            .create_new(true)
-            .append(true)
-            .open(&temp_path)
-            .map_err(|_| JasonError::Io)?;
-        let mut new_len: u64 = 0;
-
-        for &start_index in indexes.values() {
-            let v_index = start_index + self.load_size(start_index)? + 8;
-            let end_index = v_index + self.load_size(v_index)? + 8;
-
-            let mut buf: Vec<u8> = vec![0; (end_index - start_index) as usize];
-            self.file
-                .seek(SeekFrom::Start(start_index))
-                .map_err(|_| JasonError::Index)?;
-            self.file.read_exact(&mut buf).map_err(|_| JasonError::Io)?;
-
-            new_file.write_all(&buf).map_err(|_| JasonError::Io)?;
-            new_len += buf.len() as u64;
-        }
-
-        drop(new_file);
-
-        fs::rename(&self.path, self.path.with_extension("jdbold")).map_err(|_| JasonError::Io)?;
-        fs::rename(&temp_path, &self.path).map_err(|_| JasonError::Io)?;
-
-        let new_file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(&self.path)
-            .map_err(|_| JasonError::Io)?;
-
-        let _old_file = std::mem::replace(&mut self.file, new_file);
-        self.len = new_len;
-
-        fs::remove_file(self.path.with_extension("jdbold")).map_err(|_| JasonError::Io)?;
-
-        Ok(())
+    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<CompactionStats, JasonError> {
+        self.compact_with(indexes, CompactStrategy::TempFile)
     }
 
     fn migrate<Old, New, F>(
@@ -243,6 +887,9 @@ impl Source for FileSource {
         New: IntoJson + FromJson,
         F: Fn(Old) -> New,
     {
+        quiet_assert(self.writable, JasonError::Io)?;
+        self.flush_buffer()?;
+
         let temp_path = self.path.with_extension("jdbtmp");
         if temp_path.exists() {
             fs::remove_file(&temp_path).map_err(|_| JasonError::Io)?;
@@ -252,6 +899,11 @@ impl Source for FileSource {
 
         for &start_index in indexes.values() {
             let (k, v) = self.read_entry(start_index)?;
+
+            if is_tombstone(self.version, &v) {
+                continue;
+            }
+
             let value_string = unsafe { String::from_utf8_unchecked(v) };
 
             let old: Old =
@@ -266,20 +918,166 @@ impl Source for FileSource {
 
         drop(new_file);
 
-        fs::rename(&self.path, self.path.with_extension("jdbold")).map_err(|_| JasonError::Io)?;
-        fs::rename(&temp_path, &self.path).map_err(|_| JasonError::Io)?;
+        self.replace_with(&temp_path, new_len)
+    }
+}
 
-        let new_file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(&self.path)
-            .map_err(|_| JasonError::Io)?;
+impl Drop for FileSource {
+    fn drop(&mut self) {
+        self.flush_buffer().ok();
+    }
+}
 
-        let _old_file = std::mem::replace(&mut self.file, new_file);
-        self.len = new_len;
+/// Takes an exclusive advisory lock on the file, which is released when it is closed.
+fn lock(file: &File) -> Result<(), JasonError> {
+    file.try_lock().map_err(|e| match e {
+        TryLockError::WouldBlock => JasonError::Locked,
+        TryLockError::Error(_) => JasonError::Io,
+    })
+}
 
-        fs::remove_file(self.path.with_extension("jdbold")).map_err(|_| JasonError::Io)?;
+/// Fills the buffer with the data at the given position in the file, without moving the file's position.
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], position: u64) -> Result<(), JasonError> {
+    use std::os::unix::fs::FileExt;
 
-        Ok(())
+    file.read_exact_at(buf, position)
+        .map_err(|_| JasonError::Io)
+}
+
+/// Fills the buffer with the data at the given position in the file.
+///
+/// On Windows, this moves the file's position, but every other read seeks before reading and writes always append,
+///   so this doesn't interfere with them.
+#[cfg(windows)]
+pub(crate) fn read_exact_at(
+    file: &File,
+    mut buf: &mut [u8],
+    mut position: u64,
+) -> Result<(), JasonError> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, position) {
+            Ok(0) | Err(_) => return Err(JasonError::Io),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                position += n as u64;
+            }
+        }
     }
+
+    Ok(())
+}
+
+/// Returns the length of the header for the given format version.
+pub(crate) fn header_len(version: u64) -> u64 {
+    if version == 0 {
+        0
+    } else {
+        HEADER_LEN
+    }
+}
+
+/// Returns the header for the current format version.
+pub(crate) fn header() -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+    header
+}
+
+/// Writes a header with the current format version to the end of the file, which should be empty.
+fn write_header(file: &mut File) -> Result<(), JasonError> {
+    file.write_all(&header()).map_err(|_| JasonError::Io)?;
+
+    Ok(())
+}
+
+/// Reads the header from the start of the file with the given length, returning the format version.
+///
+/// Files which don't start with the magic bytes are assumed to be headerless version 0 files, unless they are tar archives.
+fn read_header(file: &mut File, len: u64) -> Result<u64, JasonError> {
+    // Enough is read to recognise a tar header as well as our own.
+    let mut header = vec![0u8; len.min(TAR_BLOCK_LEN) as usize];
+    file.rewind().map_err(|_| JasonError::Io)?;
+    file.read_exact(&mut header).map_err(|_| JasonError::Io)?;
+
+    parse_header(&header)
+}
+
+/// Parses the header at the start of the given data, returning the format version.
+///
+/// Data which doesn't start with the magic bytes is assumed to be headerless version 0 data. If it starts with a tar
+///   header instead, `JasonError::IncompatibleFormat` is returned.
+pub(crate) fn parse_header(data: &[u8]) -> Result<u64, JasonError> {
+    if data.len() < HEADER_LEN as usize || &data[..8] != MAGIC {
+        quiet_assert(!is_tar_header(data), JasonError::IncompatibleFormat)?;
+        return Ok(0);
+    }
+
+    let version = u64::from_le_bytes(data[8..16].try_into().unwrap());
+    quiet_assert(
+        version != 0 && version <= FORMAT_VERSION,
+        JasonError::BadFormat,
+    )?;
+
+    Ok(version)
+}
+
+/// Returns `true` if the data starts with a tar header, as used by databases from the JasonDB server.
+///
+/// This checks the header's checksum, which is stored in octal as the sum of the bytes of the header with the checksum field
+///   itself counted as spaces. This is recognised by both old-style and USTAR archives, and it is vanishingly unlikely
+///   to match a log-structured database by chance.
+pub(crate) fn is_tar_header(data: &[u8]) -> bool {
+    if data.len() < TAR_BLOCK_LEN as usize {
+        return false;
+    }
+
+    let header = &data[..TAR_BLOCK_LEN as usize];
+    let field = &header[148..156];
+
+    let digits = field
+        .iter()
+        .copied()
+        .skip_while(|&b| b == b' ')
+        .take_while(|&b| b != 0 && b != b' ')
+        .collect::<Vec<u8>>();
+
+    let checksum = match std::str::from_utf8(&digits)
+        .ok()
+        .and_then(|digits| u64::from_str_radix(digits, 8).ok())
+    {
+        Some(checksum) => checksum,
+        None => return false,
+    };
+
+    let sum = header[..148]
+        .iter()
+        .chain(header[156..].iter())
+        .map(|&b| b as u64)
+        .sum::<u64>()
+        + 8 * b' ' as u64;
+
+    sum == checksum
+}
+
+/// Reads a length-prefixed value from the reader, checking that it is no longer than the given number of bytes.
+fn read_sized(reader: &mut impl Read, max_len: u64) -> Result<Vec<u8>, JasonError> {
+    let mut size_buf = [0u8; 8];
+    reader
+        .read_exact(&mut size_buf)
+        .map_err(|_| JasonError::Io)?;
+
+    let size = u64::from_le_bytes(size_buf);
+    quiet_assert(
+        size.checked_add(8).is_some_and(|len| len <= max_len),
+        JasonError::Index,
+    )?;
+
+    let mut data: Vec<u8> = vec![0; size as usize];
+    reader.read_exact(&mut data).map_err(|_| JasonError::Io)?;
+
+    Ok(data)
 }
@@ -0,0 +1,415 @@
+//! Provides the `IndexFile` type, a sorted primary index which is stored on disk rather than in memory.
+
+use crate::error::JasonError;
+use crate::sources::file::read_exact_at;
+use crate::util::quiet_assert;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// The magic bytes at the start of every index file.
+const MAGIC: &[u8; 8] = b"JASONIDX";
+
+/// The version of the index file format.
+const VERSION: u64 = 1;
+
+/// The length of the header, which consists of the magic bytes, the version, the length and checksum of the data
+///   covered by the index, and the number of keys.
+const HEADER_LEN: u64 = 40;
+
+/// The maximum number of keys which are sorted in memory at a time by an `IndexSorter`.
+const RUN_LEN: usize = 64 * 1024;
+
+/// The number of bytes which are read from a sorted run at a time while the runs are merged.
+const RUN_BUFFER_LEN: usize = 8 * 1024;
+
+/// The offset recorded in a sorted run for a key which has been deleted.
+const DELETED: u64 = u64::MAX;
+
+/// A sorted primary index stored in a file, which maps keys to offsets by binary search without loading them into memory.
+///
+/// The file starts with a header, followed by the records in order of their keys, followed by a table of the positions of
+///   the records. Each record is the length of the key, the key and the offset, with the lengths and offset as
+///   little-endian `u64`s like in database files. The index only covers the data before `data_len`, and records the
+///   checksum of that data so that an index which no longer matches its database can be recognised.
+pub(crate) struct IndexFile {
+    file: File,
+    table_start: u64,
+    pub(crate) data_len: u64,
+    pub(crate) checksum: u64,
+    pub(crate) len: u64,
+}
+
+impl IndexFile {
+    /// Opens the index file at the path. Returns `None` if it doesn't exist or is not a valid index file.
+    pub(crate) fn open(path: &Path) -> Result<Option<Self>, JasonError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(path).map_err(|_| JasonError::Io)?;
+        let file_len = file.metadata().map_err(|_| JasonError::Io)?.len();
+
+        if file_len < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; HEADER_LEN as usize];
+        read_exact_at(&file, &mut header, 0)?;
+
+        let field = |i: usize| u64::from_le_bytes(header[i..i + 8].try_into().unwrap());
+        let len = field(32);
+
+        if &header[..8] != MAGIC || field(8) != VERSION || len > (file_len - HEADER_LEN) / 8 {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            file,
+            table_start: file_len - len * 8,
+            data_len: field(16),
+            checksum: field(24),
+            len,
+        }))
+    }
+
+    /// Looks up the offset of the given key by binary search.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<u64>, JasonError> {
+        let (mut low, mut high) = (0, self.len);
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (k, offset) = self.record(middle)?;
+
+            match k.as_str().cmp(key) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Ok(Some(offset)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Iterates over every key and offset in the index, in order of their keys.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Result<(String, u64), JasonError>> + '_ {
+        (0..self.len).map(|i| self.record(i))
+    }
+
+    /// Reads the key and offset of the record at the given position in the table.
+    fn record(&self, i: u64) -> Result<(String, u64), JasonError> {
+        let position = self.read_u64(self.table_start + i * 8)?;
+        let key_len = self.read_u64(position)?;
+        quiet_assert(
+            key_len <= self.table_start.saturating_sub(position + 16),
+            JasonError::Index,
+        )?;
+
+        let mut key = vec![0; key_len as usize];
+        read_exact_at(&self.file, &mut key, position + 8)?;
+        let offset = self.read_u64(position + 8 + key_len)?;
+
+        Ok((
+            String::from_utf8(key).map_err(|_| JasonError::Index)?,
+            offset,
+        ))
+    }
+
+    /// Reads a little-endian `u64` from the given position in the file.
+    fn read_u64(&self, position: u64) -> Result<u64, JasonError> {
+        let mut buf = [0u8; 8];
+        read_exact_at(&self.file, &mut buf, position)?;
+
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+/// Writes an index file from keys given in ascending order, without holding them in memory.
+///
+/// Records are written to a temporary file as they are given, and their positions to a second temporary file, which is
+///   appended to the first when the index is finished. The finished index then replaces any existing index file.
+pub(crate) struct IndexWriter {
+    path: PathBuf,
+    records: BufWriter<File>,
+    table: BufWriter<File>,
+    position: u64,
+    len: u64,
+}
+
+impl IndexWriter {
+    /// Starts writing an index file which will be stored at the given path once it is finished.
+    pub(crate) fn new(path: &Path) -> Result<Self, JasonError> {
+        let mut records = BufWriter::new(temp_file(&path.with_extension("jdbindextmp"))?);
+
+        // The header is written once the number of keys is known.
+        records
+            .write_all(&[0; HEADER_LEN as usize])
+            .map_err(|_| JasonError::Io)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            records,
+            table: BufWriter::new(temp_file(&path.with_extension("jdbindextable"))?),
+            position: HEADER_LEN,
+            len: 0,
+        })
+    }
+
+    /// Adds the key and its offset to the index. Keys must be added in ascending order, and only once each.
+    pub(crate) fn push(&mut self, key: &str, offset: u64) -> Result<(), JasonError> {
+        self.table
+            .write_all(&self.position.to_le_bytes())
+            .map_err(|_| JasonError::Io)?;
+        write_record(&mut self.records, key, offset)?;
+
+        self.position += key.len() as u64 + 16;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Finishes the index file, which covers the data before `data_len` whose checksum is `checksum`, and replaces any
+    ///   existing index file with it.
+    pub(crate) fn finish(mut self, data_len: u64, checksum: u64) -> Result<IndexFile, JasonError> {
+        let mut table = self.table.into_inner().map_err(|_| JasonError::Io)?;
+        table.rewind().map_err(|_| JasonError::Io)?;
+        io::copy(&mut table, &mut self.records).map_err(|_| JasonError::Io)?;
+        drop(table);
+        fs::remove_file(self.path.with_extension("jdbindextable")).map_err(|_| JasonError::Io)?;
+
+        let mut header = MAGIC.to_vec();
+        for field in [VERSION, data_len, checksum, self.len] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+
+        let mut file = self.records.into_inner().map_err(|_| JasonError::Io)?;
+        file.rewind().map_err(|_| JasonError::Io)?;
+        file.write_all(&header).map_err(|_| JasonError::Io)?;
+        file.sync_data().map_err(|_| JasonError::Io)?;
+        drop(file);
+
+        fs::rename(self.path.with_extension("jdbindextmp"), &self.path)
+            .map_err(|_| JasonError::Io)?;
+
+        IndexFile::open(&self.path)?.ok_or(JasonError::Io)
+    }
+}
+
+/// Builds an index file from keys given in any order, such as the entries of a database in the order in which they were
+///   written, holding at most `run_len` keys in memory at a time.
+///
+/// Keys are collected into runs, which are sorted and written to a temporary file. When the index is finished, the runs are
+///   merged into an `IndexWriter`. If a key is given more than once, the last time it was given wins.
+pub(crate) struct IndexSorter {
+    path: PathBuf,
+    run: Vec<(String, u64)>,
+    pub(crate) run_len: usize,
+    runs: BufWriter<File>,
+    run_ends: Vec<u64>,
+    runs_len: u64,
+}
+
+impl IndexSorter {
+    /// Starts building an index file which will be stored at the given path once it is finished.
+    pub(crate) fn new(path: &Path) -> Result<Self, JasonError> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            run: Vec::new(),
+            run_len: RUN_LEN,
+            runs: BufWriter::new(temp_file(&path.with_extension("jdbindexruns"))?),
+            run_ends: Vec::new(),
+            runs_len: 0,
+        })
+    }
+
+    /// Sets the offset of the key, replacing any offset it was given before.
+    pub(crate) fn insert(&mut self, key: String, offset: u64) -> Result<(), JasonError> {
+        self.run.push((key, offset));
+
+        if self.run.len() >= self.run_len {
+            self.write_run()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the key from the index, if it was given before.
+    pub(crate) fn remove(&mut self, key: String) -> Result<(), JasonError> {
+        self.insert(key, DELETED)
+    }
+
+    /// Finishes the index file, which covers the data before `data_len` whose checksum is `checksum`, and replaces any
+    ///   existing index file with it.
+    pub(crate) fn finish(mut self, data_len: u64, checksum: u64) -> Result<IndexFile, JasonError> {
+        if !self.run.is_empty() {
+            self.write_run()?;
+        }
+
+        let runs_file = self.runs.into_inner().map_err(|_| JasonError::Io)?;
+        let mut runs = Vec::with_capacity(self.run_ends.len());
+        let mut start = 0;
+
+        for &end in &self.run_ends {
+            runs.push(Run {
+                file: &runs_file,
+                position: start,
+                end,
+                buffer: Vec::new(),
+                start: 0,
+            });
+            start = end;
+        }
+
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some((key, offset)) = run.next()? {
+                heap.push(Reverse((key, i, offset)));
+            }
+        }
+
+        let mut writer = IndexWriter::new(&self.path)?;
+
+        while let Some(Reverse((key, mut i, mut offset))) = heap.pop() {
+            // Equal keys come out in the order of their runs, so the last one is the latest.
+            loop {
+                if let Some(record) = runs[i].next()? {
+                    heap.push(Reverse((record.0, i, record.1)));
+                }
+
+                match heap.peek() {
+                    Some(Reverse((next, _, _))) if *next == key => {
+                        let Reverse((_, j, next_offset)) = heap.pop().unwrap();
+                        i = j;
+                        offset = next_offset;
+                    }
+                    _ => break,
+                }
+            }
+
+            if offset != DELETED {
+                writer.push(&key, offset)?;
+            }
+        }
+
+        drop(runs);
+        drop(runs_file);
+        fs::remove_file(self.path.with_extension("jdbindexruns")).map_err(|_| JasonError::Io)?;
+
+        writer.finish(data_len, checksum)
+    }
+
+    /// Sorts the keys collected so far and writes them to the temporary file as a run.
+    fn write_run(&mut self) -> Result<(), JasonError> {
+        // The sort is stable, so reversing the run first puts the latest offset of each key first, which `dedup_by` keeps.
+        self.run.reverse();
+        self.run.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.run.dedup_by(|(a, _), (b, _)| a == b);
+
+        for (key, offset) in self.run.drain(..) {
+            write_record(&mut self.runs, &key, offset)?;
+            self.runs_len += key.len() as u64 + 16;
+        }
+
+        self.run_ends.push(self.runs_len);
+
+        Ok(())
+    }
+}
+
+/// Reads the records of one sorted run from the temporary file of an `IndexSorter`, a buffer at a time.
+struct Run<'a> {
+    file: &'a File,
+    position: u64,
+    end: u64,
+    buffer: Vec<u8>,
+    start: usize,
+}
+
+impl Run<'_> {
+    /// Reads the next key and offset from the run, or returns `None` if there are none left.
+    fn next(&mut self) -> Result<Option<(String, u64)>, JasonError> {
+        if self.start == self.buffer.len() && self.position == self.end {
+            return Ok(None);
+        }
+
+        let key_len = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        let key = String::from_utf8(self.take(key_len as usize)?.to_vec())
+            .map_err(|_| JasonError::Index)?;
+        let offset = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+
+        Ok(Some((key, offset)))
+    }
+
+    /// Takes the next `n` bytes of the run, reading more from the file if they aren't all in the buffer.
+    fn take(&mut self, n: usize) -> Result<&[u8], JasonError> {
+        if self.buffer.len() - self.start < n {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+
+            let len =
+                ((n - self.buffer.len()).max(RUN_BUFFER_LEN) as u64).min(self.end - self.position);
+            quiet_assert(self.buffer.len() + len as usize >= n, JasonError::Index)?;
+
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + len as usize, 0);
+            read_exact_at(self.file, &mut self.buffer[filled..], self.position)?;
+            self.position += len;
+        }
+
+        self.start += n;
+
+        Ok(&self.buffer[self.start - n..self.start])
+    }
+}
+
+/// Writes a record of the key and offset, as stored in index files and sorted runs.
+fn write_record(writer: &mut impl Write, key: &str, offset: u64) -> Result<(), JasonError> {
+    writer
+        .write_all(&(key.len() as u64).to_le_bytes())
+        .map_err(|_| JasonError::Io)?;
+    writer
+        .write_all(key.as_bytes())
+        .map_err(|_| JasonError::Io)?;
+    writer
+        .write_all(&offset.to_le_bytes())
+        .map_err(|_| JasonError::Io)?;
+
+    Ok(())
+}
+
+/// Creates an empty temporary file at the path which can be both written and read, replacing any left by an earlier attempt.
+fn temp_file(path: &Path) -> Result<File, JasonError> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|_| JasonError::Io)
+}
+
+/// Updates the checksum of a database's data with the next entry, using 64-bit FNV-1a over its raw bytes.
+///
+/// The checksum of no data is `CHECKSUM_START`.
+pub(crate) fn update_checksum(mut checksum: u64, k: &str, v: &[u8]) -> u64 {
+    let parts: [&[u8]; 4] = [
+        &(k.len() as u64).to_le_bytes(),
+        k.as_bytes(),
+        &(v.len() as u64).to_le_bytes(),
+        v,
+    ];
+
+    for byte in parts.into_iter().flatten() {
+        checksum ^= *byte as u64;
+        checksum = checksum.wrapping_mul(0x100000001b3);
+    }
+
+    checksum
+}
+
+/// The initial value of the checksum, which is the FNV-1a offset basis.
+pub(crate) const CHECKSUM_START: u64 = 0xcbf29ce484222325;
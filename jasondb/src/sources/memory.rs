@@ -1,5 +1,7 @@
 use crate::error::JasonError;
-use crate::sources::{FileSource, Source};
+use crate::sources::{
+    file, is_tombstone, CompactionStats, FileSource, SharedRead, Source, FORMAT_VERSION,
+};
 use crate::util::{indexing, quiet_assert};
 
 use humphrey_json::prelude::*;
@@ -16,9 +18,10 @@ use std::path::Path;
 /// let source = InMemory::new();
 /// let mut db: Database<String, InMemory> = Database::new(source)?;
 /// ```
-#[derive(Default)]
 pub struct InMemory {
     pub(crate) data: Vec<u8>,
+    pub(crate) capacity: Option<u64>,
+    pub(crate) version: u64,
 }
 
 impl InMemory {
@@ -26,13 +29,104 @@ impl InMemory {
     ///
     /// Does not allocate until the first entry is written as it is backed with a `Vec`.
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            data: Vec::new(),
+            capacity: None,
+            version: FORMAT_VERSION,
+        }
+    }
+
+    /// Creates an in-memory database from the given bytes, such as the contents of a database file embedded with `include_bytes!`.
+    ///
+    /// If the bytes start with a file header, it is removed. Otherwise, they are treated as version 0 data, like a file
+    ///   without a header. The bytes are not otherwise checked, so if they might not be a valid database, use
+    ///   `InMemory::from_bytes_checked` instead.
+    pub fn from_bytes(mut data: Vec<u8>) -> Self {
+        let version = file::parse_header(&data).unwrap_or(0);
+        data.drain(..file::header_len(version) as usize);
+
+        Self {
+            data,
+            capacity: None,
+            version,
+        }
+    }
+
+    /// Limits the size of the database to the given number of bytes, so that it can be used as a bounded cache.
+    ///
+    /// Whenever a write takes the database over its capacity, overwritten and deleted entries are compacted away, then
+    ///   the values which were written least recently are evicted until the rest fit. Evicted values are deleted from the
+    ///   indexes and replicas, and observers are notified as they are for `Database::delete`. Writing a value whose
+    ///   entry is larger than the capacity on its own returns `Err(JasonError::ValueTooLarge)`.
+    ///
+    /// Since this compacts the whole database, a full database does work proportional to its size on every write.
+    ///
+    /// ## Example
+    /// ```
+    /// let source = InMemory::new().with_capacity_bytes(1024 * 1024);
+    /// let mut cache: Database<Response, InMemory> = Database::from_source(source)?;
+    /// ```
+    pub fn with_capacity_bytes(mut self, capacity: u64) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Creates an in-memory database from the given bytes, checking that they form a valid database.
+    ///
+    /// Returns `Err(JasonError::BadFormat)` if the bytes have a header with an unsupported format version,
+    ///   `Err(JasonError::IncompatibleFormat)` if they are a database from the JasonDB server, or another error if
+    ///   any entry cannot be read.
+    pub fn from_bytes_checked(data: Vec<u8>) -> Result<Self, JasonError> {
+        file::parse_header(&data)?;
+
+        let mut source = Self::from_bytes(data);
+        source.load_indexes()?;
+
+        Ok(source)
+    }
+
+    /// Returns the raw entries of the database without a header.
+    ///
+    /// Since bytes without a header are loaded as version 0 data, use `InMemory::into_bytes` to get bytes which can be
+    ///   loaded again with `InMemory::from_bytes`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes the database and returns its raw bytes, which can be loaded again with `InMemory::from_bytes`.
+    ///
+    /// The bytes start with a file header unless the database holds version 0 data.
+    pub fn into_bytes(self) -> Vec<u8> {
+        if self.version == 0 {
+            self.data
+        } else {
+            self.to_bytes()
+        }
+    }
+
+    /// Returns a copy of the raw bytes of the database, as returned by `InMemory::into_bytes`.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = if self.version == 0 {
+            Vec::new()
+        } else {
+            file::header()
+        };
+        bytes.extend_from_slice(&self.data);
+
+        bytes
     }
 
     /// Writes the in-memory database to a new file at the given path.
+    ///
+    /// Version 0 data is written without a header, so that its deletions are still recognised.
     pub fn into_file(self, path: impl AsRef<Path>) -> Result<FileSource, JasonError> {
         let mut file = FileSource::create(path)?;
 
+        if self.version == 0 {
+            file.file.set_len(0).map_err(|_| JasonError::Io)?;
+            file.version = 0;
+        }
+
         file.file
             .write_all(&self.data)
             .map_err(|_| JasonError::Io)?;
@@ -42,8 +136,23 @@ impl InMemory {
     }
 }
 
-impl Source for InMemory {
-    fn read_entry(&mut self, offset: u64) -> Result<(String, Vec<u8>), JasonError> {
+impl Default for InMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<&[u8]> for InMemory {
+    type Error = JasonError;
+
+    /// Creates an in-memory database from a copy of the given bytes, checking that they form a valid database.
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes_checked(data.to_vec())
+    }
+}
+
+impl SharedRead for InMemory {
+    fn read_entry_at(&self, offset: u64) -> Result<(String, Vec<u8>), JasonError> {
         let (k, v_index) = load_value(&self.data, offset)?;
         let (v, _) = load_value(&self.data, v_index as u64)?;
 
@@ -52,12 +161,23 @@ impl Source for InMemory {
             v.to_vec(),
         ))
     }
+}
+
+impl Source for InMemory {
+    fn read_entry(&mut self, offset: u64) -> Result<(String, Vec<u8>), JasonError> {
+        self.read_entry_at(offset)
+    }
 
     fn write_entry(&mut self, k: impl AsRef<str>, v: impl AsRef<[u8]>) -> Result<u64, JasonError> {
         let k = k.as_ref();
         let v = v.as_ref();
         let size = k.len() + v.len() + 16;
 
+        quiet_assert(
+            v.is_empty() || !is_tombstone(self.version, v),
+            JasonError::BadFormat,
+        )?;
+
         self.data.reserve(size);
         self.data.extend_from_slice(&k.len().to_le_bytes());
         self.data.extend_from_slice(k.as_bytes());
@@ -67,23 +187,96 @@ impl Source for InMemory {
         Ok((self.data.len() - size) as u64)
     }
 
+    fn kind(&self) -> &'static str {
+        "memory"
+    }
+
+    fn size(&mut self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn format_version(&self) -> u64 {
+        self.version
+    }
+
+    fn capacity(&self) -> Option<u64> {
+        self.capacity
+    }
+
+    fn entry_size(&mut self, offset: u64) -> Result<u64, JasonError> {
+        let (_, v_index) = load_value(&self.data, offset)?;
+        let (_, end) = load_value(&self.data, v_index as u64)?;
+
+        Ok(end as u64 - offset)
+    }
+
+    fn truncate_to(&mut self, offset: u64) -> Result<(), JasonError> {
+        let offset: usize = offset.try_into().map_err(|_| JasonError::Index)?;
+        quiet_assert(offset <= self.data.len(), JasonError::Index)?;
+
+        self.data.truncate(offset);
+
+        Ok(())
+    }
+
+    fn remove_entry(&mut self, offset: u64) -> Result<Option<u64>, JasonError> {
+        let (_, v_index) = load_value(&self.data, offset)?;
+        let (_, end_index) = load_value(&self.data, v_index as u64)?;
+
+        self.data.drain(offset as usize..end_index);
+
+        Ok(Some(end_index as u64 - offset))
+    }
+
+    fn iter_raw_from(
+        &mut self,
+        offset: u64,
+    ) -> impl Iterator<Item = Result<(String, Vec<u8>, u64), JasonError>> + '_ {
+        let mut offset = offset as usize;
+
+        std::iter::from_fn(move || {
+            if offset >= self.data.len() {
+                return None;
+            }
+
+            let entry = load_value(&self.data, offset as u64).and_then(|(k, v_index)| {
+                let (v, new_offset) = load_value(&self.data, v_index as u64)?;
+
+                Ok((k, v, new_offset))
+            });
+
+            match entry {
+                Ok((k, v, new_offset)) => {
+                    let entry_offset = offset;
+                    offset = new_offset;
+
+                    Some(Ok((
+                        unsafe { String::from_utf8_unchecked(k.to_vec()) },
+                        v.to_vec(),
+                        entry_offset as u64,
+                    )))
+                }
+                Err(e) => {
+                    offset = self.data.len();
+
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
     fn load_indexes(&mut self) -> Result<HashMap<String, u64>, JasonError> {
         let mut indexes: HashMap<String, u64> = HashMap::new();
-        let mut offset = 0;
+        let version = self.version;
 
-        while offset < self.data.len() {
-            let (k, v_index) = load_value(&self.data, offset as u64)?;
-            let (v, new_offset) = load_value(&self.data, v_index as u64)?;
+        for entry in self.iter_raw() {
+            let (key, v, offset) = entry?;
 
-            let key = unsafe { String::from_utf8_unchecked(k.to_vec()) };
-
-            if v == b"null" {
+            if is_tombstone(version, &v) {
                 indexes.remove(&key);
             } else {
-                indexes.insert(key, offset as u64);
+                indexes.insert(key, offset);
             }
-
-            offset = new_offset;
         }
 
         Ok(indexes)
@@ -97,34 +290,43 @@ impl Source for InMemory {
         let mut indexes: HashMap<Value, BTreeSet<u64>> = HashMap::new();
 
         for i in primary_indexes.values() {
-            let (_, v) = self.read_entry(*i)?;
-            let json = unsafe { String::from_utf8_unchecked(v) };
-            let value = Value::parse(json).map_err(|_| JasonError::JsonError)?;
+            let (key, v) = self.read_entry(*i)?;
+            let value = indexing::parse_entry_value(key, v)?;
             let indexed_value = indexing::get_value(k.as_ref(), &value);
 
-            indexes
-                .entry(indexed_value)
-                .or_insert_with(BTreeSet::new)
-                .insert(*i);
+            indexes.entry(indexed_value).or_default().insert(*i);
         }
 
         Ok(indexes)
     }
 
-    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<(), JasonError> {
+    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<CompactionStats, JasonError> {
+        let bytes_before = self.size();
         let mut new_data = Vec::new();
 
-        for &start_index in indexes.values() {
+        // Entries are kept in the order in which they were written, so that bounded sources evict the oldest first.
+        let mut offsets = indexes.values().copied().collect::<Vec<_>>();
+        offsets.sort_unstable();
+
+        for start_index in offsets {
             let start_index: usize = start_index.try_into().map_err(|_| JasonError::Index)?;
             let (_, v_index) = load_value(&self.data, start_index as u64)?;
-            let (_, end_index) = load_value(&self.data, v_index as u64)?;
+            let (v, end_index) = load_value(&self.data, v_index as u64)?;
+
+            if is_tombstone(self.version, v) {
+                continue;
+            }
 
             new_data.extend_from_slice(&self.data[start_index..end_index]);
         }
 
         self.data = new_data;
+        self.version = FORMAT_VERSION;
 
-        Ok(())
+        Ok(CompactionStats {
+            bytes_before,
+            bytes_after: self.size(),
+        })
     }
 
     fn migrate<Old, New, F>(
@@ -139,8 +341,17 @@ impl Source for InMemory {
     {
         let mut new_data = InMemory::new();
 
-        for &start_index in indexes.values() {
+        // As in `compact`, entries are kept in the order in which they were written.
+        let mut offsets = indexes.values().copied().collect::<Vec<_>>();
+        offsets.sort_unstable();
+
+        for start_index in offsets {
             let (k, v) = self.read_entry(start_index)?;
+
+            if is_tombstone(self.version, &v) {
+                continue;
+            }
+
             let value_string = unsafe { String::from_utf8_unchecked(v) };
 
             let old: Old =
@@ -151,7 +362,10 @@ impl Source for InMemory {
             new_data.write_entry(k, new_bytes)?;
         }
 
-        *self = new_data;
+        *self = InMemory {
+            capacity: self.capacity,
+            ..new_data
+        };
 
         Ok(())
     }
@@ -161,7 +375,8 @@ impl Source for InMemory {
 fn load_value(data: &[u8], offset: u64) -> Result<(&[u8], usize), JasonError> {
     let offset: usize = offset.try_into().map_err(|_| JasonError::Index)?;
 
-    quiet_assert(offset + 8 <= data.len(), JasonError::Index)?;
+    let end = offset.checked_add(8).ok_or(JasonError::Index)?;
+    quiet_assert(end <= data.len(), JasonError::Index)?;
     let size: usize = u64::from_le_bytes(
         data[offset..offset + 8]
             .try_into()
@@ -169,7 +384,7 @@ fn load_value(data: &[u8], offset: u64) -> Result<(&[u8], usize), JasonError> {
     )
     .try_into()
     .map_err(|_| JasonError::Index)?;
-    quiet_assert(offset + 8 + size <= data.len(), JasonError::Index)?;
+    quiet_assert(size <= data.len() - offset - 8, JasonError::Index)?;
     let data = &data[offset + 8..offset + 8 + size];
 
     Ok((data, offset + 8 + size))
@@ -1,18 +1,53 @@
 //! Provides backend sources for the database as well as the extensible `Source` trait.
 
 mod file;
+pub(crate) mod index_file;
 mod memory;
 
-pub use file::FileSource;
+pub(crate) use file::is_tar_header;
+pub use file::{BackgroundCompaction, CompactStrategy, FileSource, FORMAT_VERSION};
 pub use memory::InMemory;
 
 use crate::error::JasonError;
+use crate::util::indexing;
 
 use humphrey_json::prelude::*;
 use humphrey_json::Value;
 
 use std::collections::{BTreeSet, HashMap};
 
+/// The sizes of a source before and after it was compacted, as returned by [`Source::compact`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// The size of the source in bytes before compaction.
+    pub bytes_before: u64,
+    /// The size of the source in bytes after compaction.
+    pub bytes_after: u64,
+}
+
+impl CompactionStats {
+    /// Returns the number of bytes which were reclaimed by compaction.
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// Returns `true` if the value marks its key as deleted in data of the given format version.
+///
+/// Deletions are marked with empty values, but version 0 data marked them with `null`, which is only a value in later versions.
+pub(crate) fn is_tombstone(version: u64, value: &[u8]) -> bool {
+    value.is_empty() || (version == 0 && value == b"null")
+}
+
+/// Represents a source which can read entries through a shared reference, so it can be read from without exclusive access.
+///
+/// This is implemented for both built-in sources. [`FileSource`] uses positioned reads, which don't move the file's
+///   position, so they don't interfere with the reads and writes which go through `Source`.
+pub trait SharedRead: Source {
+    /// Reads an entry from the source at the given offset without modifying the source. Returns its key and value.
+    fn read_entry_at(&self, offset: u64) -> Result<(String, Vec<u8>), JasonError>;
+}
+
 /// Represents a backend source for the database.
 ///
 /// This handles the database's low-level storage API. It is currently implemented for:
@@ -25,18 +60,171 @@ pub trait Source {
     /// Writes an entry to the source with the given key and value. Returns the offset of the new entry.
     fn write_entry(&mut self, k: impl AsRef<str>, v: impl AsRef<[u8]>) -> Result<u64, JasonError>;
 
+    /// Returns a short name for the kind of source, such as `"file"` or `"memory"`, for use in diagnostics.
+    ///
+    /// By default, this returns `"custom"`.
+    fn kind(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Returns the size of the source in bytes, which is also the offset at which the next entry will be written.
+    ///
+    /// By default, this reads every entry from the start until one can't be read, assuming that each entry follows the
+    ///   one before it and takes up the length of its key and value plus 16 bytes, as in the built-in sources.
+    ///   Since this is slow, sources should override it if they can.
+    fn size(&mut self) -> u64 {
+        let mut offset = 0;
+
+        while let Ok((k, v)) = self.read_entry(offset) {
+            offset += k.len() as u64 + v.len() as u64 + 16;
+        }
+
+        offset
+    }
+
+    /// Returns the format version of the source's entries, which determines how deletions are marked.
+    ///
+    /// Version 0 data, from before the file header was introduced, marks deletions with `null` values as well as
+    ///   empty ones. By default, this returns `FORMAT_VERSION`.
+    fn format_version(&self) -> u64 {
+        FORMAT_VERSION
+    }
+
+    /// Returns the maximum size of the source in bytes, if it is bounded.
+    ///
+    /// Databases evict their least recently written values to keep bounded sources within their capacity.
+    ///   By default, this returns `None`.
+    fn capacity(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the size in bytes of the entry at the given offset, including the lengths of its key and value.
+    ///
+    /// By default, this reads the whole entry, but sources can override it to only read the lengths.
+    fn entry_size(&mut self, offset: u64) -> Result<u64, JasonError> {
+        let (k, v) = self.read_entry(offset)?;
+
+        Ok(k.len() as u64 + v.len() as u64 + 16)
+    }
+
+    /// Iterates over every entry in the source in the order in which they are stored, including overwritten and deleted
+    ///   entries. Yields the key, value and offset of each entry.
+    ///
+    /// If an entry cannot be read, the error is yielded and iteration stops.
+    fn iter_raw(
+        &mut self,
+    ) -> impl Iterator<Item = Result<(String, Vec<u8>, u64), JasonError>> + '_ {
+        self.iter_raw_from(0)
+    }
+
+    /// Iterates over every entry in the source in the order in which they are stored, starting from the entry at the given offset.
+    ///
+    /// The offset must be the start of an entry or the size of the source, otherwise the entries yielded will be invalid.
+    ///
+    /// By default, this reads each entry with `Source::read_entry`, making the same assumption about their layout as `Source::size`.
+    fn iter_raw_from(
+        &mut self,
+        offset: u64,
+    ) -> impl Iterator<Item = Result<(String, Vec<u8>, u64), JasonError>> + '_ {
+        let len = self.size();
+        let mut offset = offset;
+
+        std::iter::from_fn(move || {
+            if offset >= len {
+                return None;
+            }
+
+            match self.read_entry(offset) {
+                Ok((k, v)) => {
+                    let entry_offset = offset;
+                    offset += k.len() as u64 + v.len() as u64 + 16;
+
+                    Some(Ok((k, v, entry_offset)))
+                }
+                Err(e) => {
+                    offset = len;
+
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Discards every entry written at or after the given offset, which must be the start of an entry or the size of the source.
+    ///
+    /// This is intended for rolling back failed writes and removing corrupt data from the end of the source.
+    ///   Any indexes loaded from the source must be reloaded afterwards.
+    ///
+    /// By default, this returns `Err(JasonError::Unsupported)` to indicate that entries cannot be discarded.
+    fn truncate_to(&mut self, offset: u64) -> Result<(), JasonError> {
+        let _ = offset;
+        Err(JasonError::Unsupported)
+    }
+
+    /// Physically removes the entry at the given offset, moving every later entry back to fill the space, and returns the
+    ///   number of bytes removed. The offsets of all later entries are reduced by this amount.
+    ///
+    /// By default, this does nothing and returns `None` to indicate that entries cannot be removed, which is appropriate
+    ///   for append-only sources.
+    fn remove_entry(&mut self, offset: u64) -> Result<Option<u64>, JasonError> {
+        let _ = offset;
+        Ok(None)
+    }
+
+    /// Refreshes any cached information about the underlying storage, such as its size, in case it has been
+    ///   modified externally. By default, this does nothing.
+    fn refresh(&mut self) -> Result<(), JasonError> {
+        Ok(())
+    }
+
+    /// Ensures that every entry written so far has been written to stable storage. By default, this does nothing.
+    fn flush(&mut self) -> Result<(), JasonError> {
+        Ok(())
+    }
+
     /// Loads indexes from the source. Returns a map of keys to offsets.
     fn load_indexes(&mut self) -> Result<HashMap<String, u64>, JasonError>;
 
     /// Loads secondary indexes from the source. Returns a map of keys to offsets.
+    ///
+    /// If any entry is not valid JSON, `JasonError::InvalidEntry` should be returned with its key.
     fn index_on(
         &mut self,
         k: impl AsRef<str>,
         indexes: &HashMap<String, u64>,
     ) -> Result<HashMap<Value, BTreeSet<u64>>, JasonError>;
 
-    /// Compacts the database, removing all deleted entries to save space.
-    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<(), JasonError>;
+    /// Loads secondary indexes on several fields from the source in a single pass. Returns a map of fields to indexes.
+    ///
+    /// By default, this reads and parses each entry once, then indexes every field from the parsed value.
+    fn index_on_many<K>(
+        &mut self,
+        fields: &[K],
+        indexes: &HashMap<String, u64>,
+    ) -> Result<HashMap<String, HashMap<Value, BTreeSet<u64>>>, JasonError>
+    where
+        K: AsRef<str>,
+    {
+        let mut secondary_indexes: HashMap<String, HashMap<Value, BTreeSet<u64>>> = fields
+            .iter()
+            .map(|field| (field.as_ref().to_string(), HashMap::new()))
+            .collect();
+
+        for &i in indexes.values() {
+            let (k, v) = self.read_entry(i)?;
+            let value = indexing::parse_entry_value(k, v)?;
+
+            for (field, index) in &mut secondary_indexes {
+                let indexed_value = indexing::get_value(field, &value);
+                index.entry(indexed_value).or_default().insert(i);
+            }
+        }
+
+        Ok(secondary_indexes)
+    }
+
+    /// Compacts the database, removing all deleted entries to save space. Returns the size of the source before and after.
+    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<CompactionStats, JasonError>;
 
     /// Migrates the source from one datatype to another.
     fn migrate<Old, New, F>(
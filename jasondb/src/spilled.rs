@@ -0,0 +1,346 @@
+//! Provides the `SpilledDatabase` type, which keeps most of its primary index on disk rather than in memory.
+
+use crate::database::parse_entry;
+use crate::error::JasonError;
+use crate::sources::index_file::{
+    update_checksum, IndexFile, IndexSorter, IndexWriter, CHECKSUM_START,
+};
+use crate::sources::{is_tombstone, CompactionStats, FileSource, Source};
+use crate::util::quiet_assert;
+
+use humphrey_json::prelude::*;
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// The number of keys looked up from the index file which are cached in memory by default.
+const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// A file-based database whose primary index is mostly stored in a sorted index file next to the database, for databases
+///   with too many keys to index in memory.
+///
+/// The index file has the extension `.jdbindex`, and it is regenerated whenever the database is compacted. Keys written
+///   since then are indexed in memory, as are the most recently looked up keys from the index file, so reads of hot
+///   keys don't touch the index file. Other reads do a binary search of the index file, which takes a few small reads
+///   for each step. Since keys written since the last compaction are kept in memory, the database should be compacted
+///   periodically to keep memory use down.
+///
+/// This only supports reading and writing values by key. For queries, secondary indexes, replicas and iteration, use
+///   [`Database`](crate::Database), which can open the same file. When the database is opened, the index file is
+///   checked against the data, so if the file was modified elsewhere in a way which invalidates it, such as by
+///   compaction, it is regenerated.
+///
+/// Apart from the cache, only the keys written since the last compaction are held in memory. Compacting copies the entries
+///   in order of their keys, merging those keys into the ones in the index file and writing the new index file as it
+///   goes. Regenerating the index file sorts the keys in runs of up to 65,536, which are written to a temporary file
+///   next to the database and then merged, so at most one run of keys is in memory at a time.
+///
+/// ## Example
+/// ```
+/// let mut db: SpilledDatabase<User> = SpilledDatabase::new("users.jdb")?;
+///
+/// db.set("alice", alice)?;
+/// db.compact()?; // Moves every key to the index file
+///
+/// let alice = db.get("alice")?; // Looked up from the index file
+/// ```
+pub struct SpilledDatabase<T>
+where
+    T: IntoJson + FromJson,
+{
+    source: FileSource,
+    index: IndexFile,
+    index_path: PathBuf,
+    pub(crate) hot: HashMap<String, Option<u64>>,
+    pub(crate) cache: HashMap<String, u64>,
+    cache_order: VecDeque<String>,
+    cache_capacity: usize,
+    len: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T> SpilledDatabase<T>
+where
+    T: IntoJson + FromJson,
+{
+    /// Opens the database at the given path, or creates an empty one if it doesn't exist.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, JasonError> {
+        Self::from_source(FileSource::new(path)?)
+    }
+
+    /// Opens an existing database at the given path.
+    ///
+    /// If the file doesn't exist, an error will be thrown.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JasonError> {
+        Self::from_source(FileSource::open(path)?)
+    }
+
+    /// Opens the database from the given file source, such as one with a write buffer.
+    ///
+    /// The index file is read if it matches the data, and regenerated otherwise. Keys written after it was generated
+    ///   are then indexed in memory. Checking the index file reads every entry once, but they are not kept in memory.
+    pub fn from_source(mut source: FileSource) -> Result<Self, JasonError> {
+        let index_path = source.path.with_extension("jdbindex");
+
+        let index = match IndexFile::open(&index_path)? {
+            Some(index) if checksum(&mut source, index.data_len)? == Some(index.checksum) => index,
+            _ => build_index(&mut source, &index_path)?,
+        };
+
+        let mut hot: HashMap<String, Option<u64>> = HashMap::new();
+        let mut len = index.len as usize;
+        let version = source.format_version();
+
+        for entry in source.iter_raw_from(index.data_len) {
+            let (k, v, offset) = entry?;
+
+            let existed = match hot.get(&k) {
+                Some(offset) => offset.is_some(),
+                None => index.get(&k)?.is_some(),
+            };
+
+            if is_tombstone(version, &v) {
+                if existed {
+                    len -= 1;
+                }
+
+                match index.get(&k)? {
+                    Some(_) => hot.insert(k, None),
+                    None => hot.remove(&k),
+                };
+            } else {
+                if !existed {
+                    len += 1;
+                }
+
+                hot.insert(k, Some(offset));
+            }
+        }
+
+        Ok(Self {
+            source,
+            index,
+            index_path,
+            hot,
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            len,
+            marker: PhantomData,
+        })
+    }
+
+    /// Sets the number of keys looked up from the index file which are cached in memory, which is 1024 by default.
+    ///
+    /// When the cache is full, the key which was cached first is evicted. A capacity of zero disables the cache.
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = capacity;
+        self.evict(0);
+
+        self
+    }
+
+    /// Returns the number of keys in the database.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the database contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the database contains the given key.
+    pub fn contains_key(&mut self, key: impl AsRef<str>) -> Result<bool, JasonError> {
+        Ok(self.offset_of(key.as_ref())?.is_some())
+    }
+
+    /// Gets the value with the given key.
+    ///
+    /// Returns `Err(JasonError::InvalidKey)` if the key is not found, or another error if the source or index file fails.
+    pub fn get(&mut self, key: impl AsRef<str>) -> Result<T, JasonError> {
+        let offset = self
+            .offset_of(key.as_ref())?
+            .ok_or(JasonError::InvalidKey)?;
+
+        let (k, v) = self.source.read_entry(offset)?;
+        quiet_assert(k == key.as_ref(), JasonError::Index)?;
+
+        Ok(parse_entry(k, v)?.1)
+    }
+
+    /// Sets the value with the given key to the given value, which is indexed in memory until the next compaction.
+    pub fn set(&mut self, key: impl AsRef<str>, value: impl Borrow<T>) -> Result<(), JasonError> {
+        let key = key.as_ref();
+        let existed = self.offset_of(key)?.is_some();
+
+        let json = value.borrow().to_json().serialize();
+        let offset = self.source.write_entry(key, json)?;
+
+        self.hot.insert(key.to_string(), Some(offset));
+        self.cache.remove(key);
+
+        if !existed {
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the value with the given key.
+    ///
+    /// Returns `Err(JasonError::InvalidKey)` if the key is not found. Deletions of keys in the index file are recorded in
+    ///   memory until the next compaction.
+    pub fn delete(&mut self, key: impl AsRef<str>) -> Result<(), JasonError> {
+        let key = key.as_ref();
+        quiet_assert(self.offset_of(key)?.is_some(), JasonError::InvalidKey)?;
+
+        self.source.write_entry(key, b"")?;
+
+        match self.index.get(key)? {
+            Some(_) => self.hot.insert(key.to_string(), None),
+            None => self.hot.remove(key),
+        };
+        self.cache.remove(key);
+        self.len -= 1;
+
+        Ok(())
+    }
+
+    /// Ensures that every write so far is on stable storage.
+    pub fn flush(&mut self) -> Result<(), JasonError> {
+        self.source.flush()
+    }
+
+    /// Compacts the database, writing the live entries to a new file in order of their keys along with a new index file
+    ///   which covers every key, then clears the keys indexed in memory. Returns the size of the database before and after.
+    pub fn compact(&mut self) -> Result<CompactionStats, JasonError> {
+        let bytes_before = self.source.size();
+        let mut rewrite = self.source.start_rewrite()?;
+        let mut index = IndexWriter::new(&self.index_path)?;
+        let mut checksum = CHECKSUM_START;
+
+        let mut copy = |key: &str, offset: u64| -> Result<(), JasonError> {
+            let (k, v) = self.source.read_entry(offset)?;
+            quiet_assert(k == key, JasonError::Index)?;
+
+            index.push(&k, rewrite.write_entry(&k, &v)?)?;
+            checksum = update_checksum(checksum, &k, &v);
+
+            Ok(())
+        };
+
+        let mut hot = self
+            .hot
+            .iter()
+            .filter_map(|(k, offset)| Some((k.as_str(), (*offset)?)))
+            .collect::<Vec<_>>();
+        hot.sort_unstable();
+        let mut hot = hot.into_iter().peekable();
+
+        // Keys written since the last compaction replace their entries in the index file, so they are merged into it.
+        for entry in self.index.iter() {
+            let (k, offset) = entry?;
+
+            while let Some((hot_k, hot_offset)) = hot.next_if(|(hot_k, _)| *hot_k < k.as_str()) {
+                copy(hot_k, hot_offset)?;
+            }
+
+            if !self.hot.contains_key(&k) {
+                copy(&k, offset)?;
+            }
+        }
+
+        for (k, offset) in hot {
+            copy(k, offset)?;
+        }
+
+        self.source.finish_rewrite(rewrite)?;
+        let bytes_after = self.source.size();
+
+        self.index = index.finish(bytes_after, checksum)?;
+        self.hot.clear();
+        self.cache.clear();
+        self.cache_order.clear();
+        self.len = self.index.len as usize;
+
+        Ok(CompactionStats {
+            bytes_before,
+            bytes_after,
+        })
+    }
+
+    /// Finds the offset of the value with the given key, caching it if it is read from the index file.
+    fn offset_of(&mut self, key: &str) -> Result<Option<u64>, JasonError> {
+        if let Some(&offset) = self.hot.get(key) {
+            return Ok(offset);
+        }
+
+        if let Some(&offset) = self.cache.get(key) {
+            return Ok(Some(offset));
+        }
+
+        let offset = self.index.get(key)?;
+
+        if let Some(offset) = offset {
+            if self.cache_capacity > 0 {
+                self.evict(1);
+                self.cache.insert(key.to_string(), offset);
+                self.cache_order.push_back(key.to_string());
+            }
+        }
+
+        Ok(offset)
+    }
+
+    /// Evicts the keys which were cached first until there is room for the given number of keys.
+    fn evict(&mut self, space: usize) {
+        while self.cache.len() + space > self.cache_capacity {
+            match self.cache_order.pop_front() {
+                Some(key) => self.cache.remove(&key),
+                None => break,
+            };
+        }
+    }
+}
+
+/// Builds an index file of every key in the source, replacing the existing one.
+fn build_index(source: &mut FileSource, path: &Path) -> Result<IndexFile, JasonError> {
+    let mut sorter = IndexSorter::new(path)?;
+    let mut checksum = CHECKSUM_START;
+    let version = source.format_version();
+
+    for entry in source.iter_raw() {
+        let (k, v, offset) = entry?;
+        checksum = update_checksum(checksum, &k, &v);
+
+        if is_tombstone(version, &v) {
+            sorter.remove(k)?;
+        } else {
+            sorter.insert(k, offset)?;
+        }
+    }
+
+    let data_len = source.size();
+    sorter.finish(data_len, checksum)
+}
+
+/// Calculates the checksum of the entries before the given offset. Returns `None` if the offset is not the end of an entry.
+fn checksum(source: &mut FileSource, data_len: u64) -> Result<Option<u64>, JasonError> {
+    let mut checksum = CHECKSUM_START;
+    let mut end = 0;
+
+    for entry in source.iter_raw() {
+        if end >= data_len {
+            break;
+        }
+
+        let (k, v, offset) = entry?;
+        checksum = update_checksum(checksum, &k, &v);
+        end = offset + k.len() as u64 + v.len() as u64 + 16;
+    }
+
+    Ok((end == data_len).then_some(checksum))
+}
@@ -0,0 +1,41 @@
+use crate::error::JasonError;
+use crate::sources::InMemory;
+use crate::{Op, OpResult};
+
+use crate::tests::mock::{composers_db, Person};
+
+#[test]
+fn partial_success() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+    let elgar = Person::new("Edward Elgar", 1857);
+
+    let results = database.batch(vec![
+        Op::Get("bach".to_string()),
+        Op::Delete("elgar".to_string()),
+        Op::Set("elgar".to_string(), elgar.clone()),
+        Op::Get("elgar".to_string()),
+        Op::Delete("mozart".to_string()),
+        Op::Get("mozart".to_string()),
+    ]);
+
+    assert_eq!(
+        results,
+        vec![
+            Ok(OpResult::Get(Person::new("Johann Sebastian Bach", 1685))),
+            Err(JasonError::InvalidKey),
+            Ok(OpResult::Set),
+            Ok(OpResult::Get(elgar)),
+            Ok(OpResult::Delete),
+            Err(JasonError::InvalidKey),
+        ]
+    );
+
+    assert_eq!(database.len(), 6);
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1857))?,
+        vec!["elgar"]
+    );
+    assert!(database.batch(Vec::new()).is_empty());
+
+    Ok(())
+}
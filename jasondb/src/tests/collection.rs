@@ -0,0 +1,55 @@
+use crate::error::JasonError;
+use crate::sources::InMemory;
+use crate::Database;
+
+use crate::tests::mock::Person;
+
+#[test]
+fn collections() -> Result<(), JasonError> {
+    let mut database: Database<Person, InMemory> =
+        Database::new_in_memory().with_index(field!(year_of_birth))?;
+
+    let bach = Person::new("Johann Sebastian Bach", 1685);
+    let elizabeth = Person::new("Elizabeth II", 1926);
+    let george = Person::new("George VI", 1895);
+
+    database.collection("composers").set("bach", &bach)?;
+    database
+        .collection("monarchs")
+        .set("elizabeth", &elizabeth)?;
+    database.collection("monarchs").set("george", &george)?;
+    database.set("monarchs", &bach)?;
+
+    assert_eq!(database.len(), 4);
+    assert_eq!(database.get("composers/bach"), Ok(bach.clone()));
+
+    let mut monarchs = database.collection("monarchs");
+
+    assert_eq!(monarchs.name(), "monarchs");
+    assert_eq!(monarchs.len(), 2);
+    assert_eq!(monarchs.keys(), vec!["elizabeth", "george"]);
+    assert_eq!(monarchs.get("elizabeth"), Ok(elizabeth.clone()));
+    assert_eq!(monarchs.get("bach"), Err(JasonError::InvalidKey));
+
+    let all = monarchs.iter().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        all,
+        vec![
+            ("elizabeth".to_string(), elizabeth),
+            ("george".to_string(), george.clone())
+        ]
+    );
+
+    let before_1900 = monarchs
+        .query(query!(year_of_birth < 1900))?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(before_1900, vec![("george".to_string(), george)]);
+
+    monarchs.clear()?;
+
+    assert!(monarchs.is_empty());
+    assert_eq!(database.len(), 2);
+    assert_eq!(database.collection("composers").get("bach"), Ok(bach));
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::error::JasonError;
+use crate::{convert_isam, Database};
+
+use crate::tests::mock::{tar_archive, Person};
+
+use std::fs;
+
+#[test]
+fn convert() -> Result<(), JasonError> {
+    let archive = tar_archive(&[
+        (
+            "composers/bach.json",
+            br#"{ "name": "Johann Sebastian Bach", "year_of_birth": 1685 }"#,
+        ),
+        (
+            "composers/mozart.json",
+            br#"{"name":"Wolfgang Amadeus Mozart","year_of_birth":1756}"#,
+        ),
+        (
+            "performers/mozart.json",
+            br#"{"name":"Leopold Mozart","year_of_birth":1719}"#,
+        ),
+        (
+            "composers/bach.json",
+            br#"{"name":"Johann Sebastian Bach","year_of_birth":1686}"#,
+        ),
+    ]);
+    fs::write("test_convert_legacy.jdb", archive).unwrap();
+
+    convert_isam("test_convert_legacy.jdb", "test_db_convert.jdb")?;
+
+    let mut database: Database<Person> = Database::open("test_db_convert.jdb")?;
+    assert_eq!(database.len(), 3);
+    assert_eq!(database.collection("composers").len(), 2);
+    assert_eq!(database.get("composers/bach")?.year_of_birth, 1686);
+    assert_eq!(
+        database.collection("performers").get("mozart")?.name,
+        "Leopold Mozart"
+    );
+    drop(database);
+
+    // The new file must not already exist.
+    assert_eq!(
+        convert_isam("test_convert_legacy.jdb", "test_db_convert.jdb"),
+        Err(JasonError::Io)
+    );
+    assert_eq!(Database::<Person>::open("test_db_convert.jdb")?.len(), 3);
+
+    fs::remove_file("test_convert_legacy.jdb").unwrap();
+    fs::remove_file("test_db_convert.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn convert_relative_paths() -> Result<(), JasonError> {
+    // Archives created with `tar -cf legacy.jdb .` store paths relative to the current directory.
+    let archive = tar_archive(&[
+        (
+            "./composers/bach.json",
+            br#"{"name":"Johann Sebastian Bach","year_of_birth":1685}"#,
+        ),
+        (
+            "./composers/mozart.json",
+            br#"{"name":"Wolfgang Amadeus Mozart","year_of_birth":1756}"#,
+        ),
+    ]);
+    fs::write("test_convert_relative_legacy.jdb", archive).unwrap();
+
+    convert_isam(
+        "test_convert_relative_legacy.jdb",
+        "test_db_convert_relative.jdb",
+    )?;
+
+    let mut database: Database<Person> = Database::open("test_db_convert_relative.jdb")?;
+    assert_eq!(database.collection("composers").len(), 2);
+    assert_eq!(
+        database.collection("composers").get("bach")?.year_of_birth,
+        1685
+    );
+    drop(database);
+
+    fs::remove_file("test_convert_relative_legacy.jdb").unwrap();
+    fs::remove_file("test_db_convert_relative.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn convert_invalid() {
+    fs::write("test_convert_invalid.jdb", b"not an archive").unwrap();
+    assert_eq!(
+        convert_isam("test_convert_invalid.jdb", "test_db_convert_invalid.jdb"),
+        Err(JasonError::BadFormat)
+    );
+
+    fs::write(
+        "test_convert_invalid.jdb",
+        tar_archive(&[("users/alice.json", b"{\"name\":")]),
+    )
+    .unwrap();
+    assert_eq!(
+        convert_isam("test_convert_invalid.jdb", "test_db_convert_invalid.jdb"),
+        Err(JasonError::JsonError)
+    );
+    assert!(!fs::exists("test_db_convert_invalid.jdb").unwrap());
+
+    let mut archive = tar_archive(&[("users/alice.json", b"{}")]);
+    archive.truncate(513);
+    fs::write("test_convert_invalid.jdb", archive).unwrap();
+    assert_eq!(
+        convert_isam("test_convert_invalid.jdb", "test_db_convert_invalid.jdb"),
+        Err(JasonError::BadFormat)
+    );
+
+    fs::remove_file("test_convert_invalid.jdb").unwrap();
+}
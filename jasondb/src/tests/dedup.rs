@@ -0,0 +1,102 @@
+use crate::error::JasonError;
+use crate::query::Query;
+use crate::sources::InMemory;
+use crate::Database;
+
+use crate::tests::mock::{composers_db, Person};
+
+use std::collections::HashSet;
+
+/// Queries whose predicates can match the same entry more than once.
+fn overlapping_queries() -> Vec<Query> {
+    vec![
+        query!(year_of_birth == 1685) | query!(year_of_birth == 1685),
+        query!(year_of_birth == 1685) & query!(year_of_birth == 1685),
+        query!(year_of_birth in 1800..=1900) | query!(year_of_birth in 1830..=1850),
+        query!(year_of_birth in 1800..=1900) & query!(year_of_birth in 1830..=1850),
+        query!(year_of_birth > 1700) | query!(year_of_birth < 1900),
+        query!(year_of_birth < 1800) | query!(name == "Johann Sebastian Bach"),
+        query!(name == "Johannes Brahms")
+            | query!(year_of_birth == 1833)
+            | query!(year_of_birth >= 1833),
+        query!(year_of_birth >= 1833) & query!(name != "Johannes Brahms"),
+        query!(year_of_birth != 0) | query!(name != ""),
+    ]
+}
+
+/// Returns the composers database with the given fields indexed.
+///
+/// Some entries are overwritten and deleted so the indexes have been updated in place.
+fn database(indexes: &[&str]) -> Result<Database<Person, InMemory>, JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    for index in indexes {
+        database = database.with_index(index)?;
+    }
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("brahms", Person::new("Johannes Brahms", 1833))?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    database.set("elgar", Person::new("Edward Elgar", 1857))?;
+    database.delete("elgar")?;
+
+    Ok(database)
+}
+
+fn assert_unique(keys: Vec<String>) {
+    let unique = keys.iter().collect::<HashSet<_>>();
+    assert_eq!(unique.len(), keys.len(), "duplicate keys in {:?}", keys);
+}
+
+#[test]
+fn no_duplicates() -> Result<(), JasonError> {
+    let configurations: &[&[&str]] = &[
+        &[],
+        &["year_of_birth"],
+        &["name"],
+        &["name", "year_of_birth"],
+    ];
+
+    for indexes in configurations {
+        let mut database = database(indexes)?;
+
+        for (i, query) in overlapping_queries().into_iter().enumerate() {
+            let expected = database.query_keys(query)?;
+            assert_unique(expected.clone());
+
+            let query = overlapping_queries().swap_remove(i);
+            let keys = database
+                .query(query)?
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_unique(keys.clone());
+            assert_eq!(keys.len(), expected.len());
+
+            let query = overlapping_queries().swap_remove(i);
+            let keys = query
+                .execute_unoptimised(&mut database)?
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_unique(keys.clone());
+            assert_eq!(keys.len(), expected.len());
+
+            let query = overlapping_queries().swap_remove(i).compile(&database);
+            let keys = database
+                .query_compiled(&query)?
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_unique(keys.clone());
+            assert_eq!(keys.len(), expected.len());
+
+            let query = overlapping_queries().swap_remove(i);
+            let keys = database
+                .stream_query(query)?
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_unique(keys.clone());
+            assert_eq!(keys.len(), expected.len());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use crate::error::JasonError;
+use crate::sources::{InMemory, Source};
+use crate::Database;
+
+use crate::tests::mock::{composers_db, Person};
+
+#[test]
+fn or_insert() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    let size = database.source.size();
+
+    let bach = database
+        .entry("bach")
+        .or_insert(Person::new("Johann Christian Bach", 1735))?;
+
+    // Occupied entries are not overwritten.
+    assert_eq!(bach, Person::new("Johann Sebastian Bach", 1685));
+    assert_eq!(database.source.size(), size);
+
+    let elgar = database
+        .entry("elgar")
+        .or_insert_with(|| Person::new("Edward Elgar", 1857))?;
+
+    assert_eq!(elgar, Person::new("Edward Elgar", 1857));
+    assert_eq!(database.get("elgar")?, elgar);
+    assert_eq!(database.len(), 7);
+
+    Ok(())
+}
+
+#[test]
+fn and_modify() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    let bach = database
+        .entry("bach")
+        .and_modify(|person| person.year_of_birth = 1735)?
+        .or_insert(Person::new("Johann Sebastian Bach", 1685))?;
+
+    assert_eq!(bach.year_of_birth, 1735);
+    assert_eq!(database.get("bach")?.year_of_birth, 1735);
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1735))?,
+        vec!["bach"]
+    );
+
+    let entry = database
+        .entry("elgar")
+        .and_modify(|person| person.year_of_birth = 0)?;
+
+    assert!(!entry.is_occupied());
+    assert_eq!(entry.key(), "elgar");
+
+    let elgar = entry.or_insert(Person::new("Edward Elgar", 1857))?;
+
+    assert_eq!(elgar.year_of_birth, 1857);
+    assert_eq!(database.get("elgar")?.year_of_birth, 1857);
+
+    Ok(())
+}
+
+#[test]
+fn or_default() -> Result<(), JasonError> {
+    let mut database: Database<String, InMemory> = Database::new_in_memory();
+
+    assert_eq!(database.entry("empty").or_default()?, "");
+    assert!(database.entry("empty").is_occupied());
+
+    Ok(())
+}
@@ -1,10 +1,11 @@
 use crate::error::JasonError;
-use crate::sources::FileSource;
+use crate::sources::{CompactStrategy, FileSource, Source};
 use crate::Database;
 
 use crate::tests::mock::{composers_db, AgedPerson, Person};
 
 use std::fs;
+use std::io::Write;
 
 #[test]
 fn basic() -> Result<(), JasonError> {
@@ -30,6 +31,8 @@ fn basic() -> Result<(), JasonError> {
 
     let old_len = database.source.len;
 
+    drop(database);
+
     let mut database: Database<Person> = Database::open("test_db_basic.jdb")?.with_compaction()?;
     assert_eq!(database.iter().count(), 3);
     assert!(database.source.len < old_len);
@@ -50,6 +53,8 @@ fn delete() -> Result<(), JasonError> {
     assert_eq!(database.iter().count(), 0);
     assert!(database.source.len > 0);
 
+    drop(database);
+
     let mut database: Database<Person> = Database::new("test_db_delete.jdb")?.with_compaction()?;
     assert_eq!(database.iter().count(), 0);
     assert_eq!(database.source.len, 0);
@@ -59,6 +64,331 @@ fn delete() -> Result<(), JasonError> {
     Ok(())
 }
 
+#[test]
+fn remove() -> Result<(), JasonError> {
+    let mut database: Database<Person> = Database::new("test_db_remove.jdb")?;
+
+    database.set("queen_elizabeth_ii", Person::new("Elizabeth II", 1926))?;
+    let len = database.source.len;
+
+    // File sources cannot remove entries, so this appends an empty entry like `delete`.
+    database.remove("queen_elizabeth_ii")?;
+
+    assert_eq!(database.iter().count(), 0);
+    assert!(database.source.len > len);
+
+    drop(database);
+
+    let database: Database<Person> = Database::open("test_db_remove.jdb")?;
+    assert!(database.is_empty());
+
+    fs::remove_file("test_db_remove.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn reload() -> Result<(), JasonError> {
+    let mut external_database: Database<Person> = Database::create("test_db_reload.jdb")?;
+    let mut database: Database<Person> =
+        Database::open_read_only("test_db_reload.jdb")?.with_index("year_of_birth")?;
+
+    external_database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.reload()?;
+
+    assert_eq!(database.len(), 1);
+
+    external_database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    external_database.delete("bach")?;
+
+    assert_eq!(database.len(), 1);
+
+    database.reload()?;
+
+    assert_eq!(database.len(), 1);
+    assert!(database.get("bach").is_err());
+    assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+    assert_eq!(database.query(query!(year_of_birth == 1756))?.count(), 1);
+    assert_eq!(database.query(query!(year_of_birth == 1685))?.count(), 0);
+
+    fs::remove_file("test_db_reload.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn reload_after_compaction() -> Result<(), JasonError> {
+    for (path, strategy) in [
+        ("test_db_reload_temp_file.jdb", CompactStrategy::TempFile),
+        ("test_db_reload_in_memory.jdb", CompactStrategy::InMemory),
+    ] {
+        let mut external_database: Database<Person> = Database::create(path)?;
+        external_database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+        external_database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+
+        let mut database: Database<Person> = Database::open_read_only(path)?;
+
+        external_database.delete("bach")?;
+        external_database.compact_with(strategy)?;
+
+        assert_eq!(database.reload(), Err(JasonError::Replaced));
+        assert_eq!(database.len(), 2);
+
+        let mut database: Database<Person> = Database::open_read_only(path)?;
+        assert_eq!(database.len(), 1);
+        assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+
+        drop(external_database);
+        fs::remove_file(path).unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn locking() -> Result<(), JasonError> {
+    let mut database: Database<Person> = Database::create("test_db_locking.jdb")?;
+
+    assert!(matches!(
+        Database::<Person>::open("test_db_locking.jdb"),
+        Err(JasonError::Locked)
+    ));
+
+    let mut reader: Database<Person> = Database::open_read_only("test_db_locking.jdb")?;
+    let second_reader: Database<Person> = Database::open_read_only("test_db_locking.jdb")?;
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.compact()?;
+
+    assert!(reader
+        .set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))
+        .is_err());
+    assert!(reader.compact().is_err());
+    assert!(second_reader.is_empty());
+
+    // Compaction replaces the file, which must still be locked afterwards.
+    assert!(matches!(
+        Database::<Person>::open("test_db_locking.jdb"),
+        Err(JasonError::Locked)
+    ));
+
+    drop(database);
+
+    let mut database: Database<Person> = Database::open("test_db_locking.jdb")?;
+    assert_eq!(database.get("bach")?.year_of_birth, 1685);
+
+    fs::remove_file("test_db_locking.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn create_exclusive() -> Result<(), JasonError> {
+    fs::File::create("test_db_create_exclusive.jdb").unwrap();
+
+    let database: Database<Person> = Database::create_exclusive("test_db_create_exclusive.jdb")?;
+    drop(database);
+
+    let mut database: Database<Person> =
+        Database::create_exclusive("test_db_create_exclusive.jdb")?;
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    drop(database);
+
+    assert!(matches!(
+        Database::<Person>::create_exclusive("test_db_create_exclusive.jdb"),
+        Err(JasonError::AlreadyExists)
+    ));
+
+    fs::remove_file("test_db_create_exclusive.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn background_compaction() -> Result<(), JasonError> {
+    let mut database = composers_db(FileSource::create("test_db_background_compaction.jdb")?)?
+        .with_index(field!(year_of_birth))?;
+
+    database.set("bach", Person::new("J. S. Bach", 1685))?;
+    database.delete("mozart")?;
+
+    let compaction = database.start_compaction()?;
+
+    // Writes made while the compaction is running must not be lost.
+    database.set("bruckner", Person::new("Anton Bruckner", 1824))?;
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.delete("brahms")?;
+    assert_eq!(database.get("tchaikovsky")?.year_of_birth, 1840);
+
+    let old_len = database.source.len;
+    database.finish_compaction(compaction)?;
+    assert!(database.source.len < old_len);
+
+    let check = |database: &mut Database<Person>| -> Result<(), JasonError> {
+        assert_eq!(database.len(), 5);
+        assert_eq!(database.get("bach")?.name, "Johann Sebastian Bach");
+        assert_eq!(database.get("bruckner")?.year_of_birth, 1824);
+        assert!(database.get("mozart").is_err());
+        assert!(database.get("brahms").is_err());
+        assert_eq!(database.query(query!(year_of_birth < 1830))?.count(), 2);
+
+        Ok(())
+    };
+
+    check(&mut database)?;
+    drop(database);
+
+    let mut database: Database<Person> =
+        Database::open("test_db_background_compaction.jdb")?.with_index("year_of_birth")?;
+    check(&mut database)?;
+
+    // A compaction which has been overtaken by another one must be abandoned.
+    let compaction = database.start_compaction()?;
+    database.compact()?;
+    assert_eq!(
+        database.finish_compaction(compaction),
+        Err(JasonError::Index)
+    );
+    check(&mut database)?;
+
+    assert!(!fs::exists("test_db_background_compaction.jdbcompact").unwrap());
+    fs::remove_file("test_db_background_compaction.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn compaction_estimate() -> Result<(), JasonError> {
+    let source = FileSource::create("test_db_compaction_estimate.jdb")?.with_write_buffer(1024);
+    let mut database = composers_db(source)?;
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    database.delete("brahms")?;
+
+    let estimate = database.compaction_estimate()?;
+    database.compact()?;
+
+    assert!(estimate.bytes_reclaimable() > 0);
+    assert_eq!(estimate.bytes_live, database.source.size());
+    assert_eq!(database.compaction_estimate()?.bytes_reclaimable(), 0);
+
+    fs::remove_file("test_db_compaction_estimate.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn rebuild() -> Result<(), JasonError> {
+    let mut database = composers_db(FileSource::create("test_db_rebuild.jdb")?)?;
+
+    // Simulate a partially written entry appended by another writer.
+    fs::OpenOptions::new()
+        .append(true)
+        .open("test_db_rebuild.jdb")
+        .and_then(|mut file| file.write_all(&[0xff; 12]))
+        .unwrap();
+
+    database.rebuild()?;
+    assert_eq!(database.len(), 6);
+    drop(database);
+
+    let mut database: Database<Person> = Database::open("test_db_rebuild.jdb")?;
+    assert_eq!(database.len(), 6);
+    assert_eq!(
+        database.get("bach")?,
+        Person::new("Johann Sebastian Bach", 1685)
+    );
+
+    fs::remove_file("test_db_rebuild.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn flush() -> Result<(), JasonError> {
+    let source = FileSource::create("test_db_flush.jdb")?.with_write_buffer(1024 * 1024);
+    let mut database = composers_db(source)?;
+    database.set("elgar", Person::new("Edward Elgar", 1857))?;
+
+    // The entries are still in the write buffer, so another reader can't see them yet.
+    let mut reader = FileSource::open_read_only("test_db_flush.jdb")?;
+    assert!(reader.load_indexes()?.is_empty());
+
+    database.flush()?;
+
+    let mut reader: Database<Person> = Database::open_read_only("test_db_flush.jdb")?;
+    assert_eq!(reader.len(), 7);
+    assert_eq!(reader.get("elgar")?, Person::new("Edward Elgar", 1857));
+
+    drop(database);
+    fs::remove_file("test_db_flush.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn sync() -> Result<(), JasonError> {
+    let source = FileSource::create("test_db_sync.jdb")?.with_write_buffer(1024 * 1024);
+    let mut database: Database<Person> = Database::from_source(source)?.with_sync();
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("elgar", Person::new("Edward Elgar", 1857))?;
+    database.delete("bach")?;
+
+    // Every write is in the file as soon as it returns, even with a write buffer.
+    let mut reader: Database<Person> = Database::open_read_only("test_db_sync.jdb")?;
+    assert_eq!(reader.len(), 1);
+    assert_eq!(reader.get("elgar")?, Person::new("Edward Elgar", 1857));
+    assert!(reader.get("bach").is_err());
+
+    drop(database);
+    fs::remove_file("test_db_sync.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn write_buffer() -> Result<(), JasonError> {
+    let source = FileSource::create("test_db_write_buffer.jdb")?.with_write_buffer(1024 * 1024);
+    let mut database: Database<Person> = Database::from_source(source)?;
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+
+    // Nothing is written to the file until it is read from.
+    let file_len = || fs::metadata("test_db_write_buffer.jdb").unwrap().len();
+    assert_eq!(file_len(), 16);
+
+    assert_eq!(database.get("bach")?.year_of_birth, 1685);
+    assert_eq!(file_len(), database.source.len + 16);
+
+    let mut database = database.with_index("year_of_birth")?;
+    database.set("brahms", Person::new("Johannes Brahms", 1833))?;
+    database.delete("mozart")?;
+    assert_eq!(database.query(query!(year_of_birth > 1700))?.count(), 1);
+
+    // Buffered entries are copied when the background compaction finishes.
+    database.set("elgar", Person::new("Edward Elgar", 1857))?;
+    let compaction = database.start_compaction()?;
+    database.set("bruckner", Person::new("Anton Bruckner", 1824))?;
+    database.finish_compaction(compaction)?;
+    assert_eq!(database.len(), 4);
+    assert_eq!(file_len(), database.source.len + 16);
+
+    database.set("bach", Person::new("J. S. Bach", 1685))?;
+    database.compact()?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    drop(database);
+
+    let mut database: Database<Person> = Database::open("test_db_write_buffer.jdb")?;
+    assert_eq!(database.len(), 5);
+    assert_eq!(database.get("bach")?.name, "J. S. Bach");
+    assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+
+    fs::remove_file("test_db_write_buffer.jdb").unwrap();
+
+    Ok(())
+}
+
 #[test]
 fn optimised_query_1() -> Result<(), JasonError> {
     let source = FileSource::create("test_db_optimised_query_1.jdb")?;
@@ -256,3 +586,144 @@ fn migration() -> Result<(), JasonError> {
 
     Ok(())
 }
+
+#[test]
+fn headerless_tombstones() -> Result<(), JasonError> {
+    let mut contents = Vec::new();
+    for (k, v) in [
+        (
+            "bach",
+            r#"{"name":"Johann Sebastian Bach","year_of_birth":1685}"#,
+        ),
+        (
+            "mozart",
+            r#"{"name":"Wolfgang Amadeus Mozart","year_of_birth":1756}"#,
+        ),
+        ("bach", "null"),
+    ] {
+        contents.extend_from_slice(&(k.len() as u64).to_le_bytes());
+        contents.extend_from_slice(k.as_bytes());
+        contents.extend_from_slice(&(v.len() as u64).to_le_bytes());
+        contents.extend_from_slice(v.as_bytes());
+    }
+    fs::write("test_db_headerless_tombstones.jdb", contents).unwrap();
+
+    let mut database: Database<Person> = Database::open("test_db_headerless_tombstones.jdb")?;
+    assert_eq!(database.len(), 1);
+    assert_eq!(database.get("bach").err(), Some(JasonError::InvalidKey));
+    assert_eq!(database.deleted_keys()?, vec!["bach"]);
+    assert_eq!(database.history("bach")?.len(), 1);
+
+    database.compact()?;
+    drop(database);
+
+    let mut database: Database<Person> = Database::open("test_db_headerless_tombstones.jdb")?;
+    assert_eq!(database.len(), 1);
+    assert_eq!(database.get("bach").err(), Some(JasonError::InvalidKey));
+    assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+
+    drop(database);
+    fs::remove_file("test_db_headerless_tombstones.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn invalid_offsets() -> Result<(), JasonError> {
+    let mut database = composers_db(FileSource::create("test_db_invalid_offsets.jdb")?)?;
+    let offset = database.offset_of("brahms").unwrap();
+    let size = database.source.size();
+
+    // A misaligned offset reads part of the key as the length of the key, which runs past the end of the source.
+    for offset in [offset + 1, size, size + 100, u64::MAX] {
+        assert_eq!(database.get_at_offset(offset), Err(JasonError::Index));
+    }
+
+    drop(database);
+
+    fs::remove_file("test_db_invalid_offsets.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn compact_with_strategies() -> Result<(), JasonError> {
+    for (path, strategy) in [
+        ("test_db_compact_temp_file.jdb", CompactStrategy::TempFile),
+        ("test_db_compact_in_memory.jdb", CompactStrategy::InMemory),
+    ] {
+        let mut database = composers_db(FileSource::create(path)?)?.with_index("year_of_birth")?;
+        database.delete("bach")?;
+        database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1757))?;
+
+        database.compact_with(strategy)?;
+
+        assert_eq!(database.len(), 5);
+        assert!(database.get("bach").is_err());
+        assert_eq!(database.query(query!(year_of_birth == 1757))?.count(), 1);
+        assert_eq!(database.query(query!(year_of_birth == 1756))?.count(), 0);
+        assert_eq!(database.get("mozart")?.year_of_birth, 1757);
+
+        drop(database);
+        fs::remove_file(path).unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_only_delete() -> Result<(), JasonError> {
+    let database = composers_db(FileSource::create("test_db_read_only_delete.jdb")?)?;
+    drop(database);
+
+    let mut reader: Database<Person> =
+        Database::open_read_only("test_db_read_only_delete.jdb")?.with_index("year_of_birth")?;
+
+    assert_eq!(reader.delete("bach"), Err(JasonError::Io));
+    assert_eq!(reader.len(), 6);
+    assert_eq!(reader.get("bach")?.year_of_birth, 1685);
+    assert_eq!(reader.query(query!(year_of_birth == 1685))?.count(), 1);
+
+    drop(reader);
+    fs::remove_file("test_db_read_only_delete.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn write_buffer_failure() -> Result<(), JasonError> {
+    let source = FileSource::create("test_db_write_buffer_failure.jdb")?.with_write_buffer(1024);
+    let mut database: Database<Person> = Database::from_source(source)?;
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.flush()?;
+    let len = database.source.len;
+
+    // Writing out the buffer fails while the file is swapped for a read-only handle.
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    let read_only = fs::File::open("test_db_write_buffer_failure.jdb").unwrap();
+    let file = std::mem::replace(&mut database.source.file, read_only);
+
+    assert_eq!(database.flush(), Err(JasonError::Io));
+    assert_eq!(database.source.len, len);
+    assert!(database.source.buffer.is_empty());
+
+    database.source.file = file;
+    database.reload()?;
+
+    assert_eq!(database.len(), 1);
+    assert_eq!(database.get("mozart").err(), Some(JasonError::InvalidKey));
+
+    let offset = database.set_returning_offset("brahms", Person::new("Johannes Brahms", 1833))?;
+    assert_eq!(offset, len);
+    drop(database);
+
+    let mut database: Database<Person> = Database::open("test_db_write_buffer_failure.jdb")?;
+    assert_eq!(database.len(), 2);
+    assert_eq!(database.get("brahms")?.year_of_birth, 1833);
+    drop(database);
+
+    fs::remove_file("test_db_write_buffer_failure.jdb").unwrap();
+
+    Ok(())
+}
@@ -1,9 +1,12 @@
-use crate::sources::{FileSource, Source};
+use crate::error::JasonError;
+use crate::sources::{CompactStrategy, FileSource, InMemory, Source, FORMAT_VERSION};
+
+use crate::tests::mock::tar_archive;
 
 use humphrey_json::prelude::*;
 
-use std::fs::{self, File};
-use std::io::{Read, Seek, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 #[test]
 fn read_write() {
@@ -24,6 +27,57 @@ fn read_write() {
     fs::remove_file("test_read_write.jdb").unwrap();
 }
 
+#[test]
+fn iter_raw() {
+    let mut database = FileSource::new("test_iter_raw.jdb").unwrap();
+
+    let index_1 = database.write_entry("key1", "this is a value").unwrap();
+    let index_2 = database.write_entry("key1", "overwritten!").unwrap();
+    let index_3 = database.write_entry("key1", "").unwrap();
+
+    let entries = database.iter_raw().collect::<Result<Vec<_>, _>>().unwrap();
+
+    assert_eq!(
+        entries,
+        vec![
+            ("key1".to_string(), b"this is a value".to_vec(), index_1),
+            ("key1".to_string(), b"overwritten!".to_vec(), index_2),
+            ("key1".to_string(), b"".to_vec(), index_3),
+        ]
+    );
+
+    drop(database);
+    fs::remove_file("test_iter_raw.jdb").unwrap();
+}
+
+#[test]
+fn truncate_to() {
+    let mut database = FileSource::new("test_truncate_to.jdb").unwrap();
+
+    database.write_entry("key1", "this is a value").unwrap();
+    let index_2 = database.write_entry("key1", "overwritten!").unwrap();
+    database.write_entry("key2", "value 2").unwrap();
+
+    let size = database.size();
+    assert!(database.truncate_to(size + 1).is_err());
+
+    database.truncate_to(index_2).unwrap();
+    assert_eq!(database.size(), index_2);
+
+    let indexes = database.load_indexes().unwrap();
+    assert_eq!(indexes.len(), 1);
+    assert_eq!(
+        database.read_entry(indexes["key1"]).unwrap().1,
+        b"this is a value".to_vec()
+    );
+
+    let index_3 = database.write_entry("key3", "value 3").unwrap();
+    assert_eq!(index_3, index_2);
+
+    drop(database);
+    fs::remove_file("test_truncate_to.jdb").unwrap();
+}
+
 #[test]
 fn load_indexes() {
     let mut database = FileSource::new("test_load_indexes.jdb").unwrap();
@@ -32,7 +86,7 @@ fn load_indexes() {
     let index_2 = database.write_entry("key2", "value 2").unwrap();
     let index_3 = database.write_entry("key1", "overwritten!").unwrap();
     database.write_entry("key3", "not null").unwrap();
-    database.write_entry("key3", "null").unwrap();
+    database.write_entry("key3", "").unwrap();
 
     let indexes = database.load_indexes().unwrap();
 
@@ -52,14 +106,19 @@ fn compact() {
     database.write_entry("key2", "value 2").unwrap();
     database.write_entry("key1", "overwritten!").unwrap();
     database.write_entry("key3", "not null").unwrap();
-    database.write_entry("key3", "null").unwrap();
+    database.write_entry("key3", "").unwrap();
 
     let indexes = database.load_indexes().unwrap();
+    let bytes_before = database.len;
 
-    database.compact(&indexes).unwrap();
+    let stats = database.compact(&indexes).unwrap();
+
+    assert_eq!(stats.bytes_before, bytes_before);
+    assert_eq!(stats.bytes_after, database.len);
+    assert_eq!(stats.bytes_after, 59);
 
     let mut buf: Vec<u8> = vec![0; database.len as usize];
-    database.file.rewind().unwrap();
+    database.file.seek(SeekFrom::Start(16)).unwrap();
     database.file.read_exact(&mut buf).unwrap();
     assert!(
         buf == b"\x04\0\0\0\0\0\0\0key2\x07\0\0\0\0\0\0\0value 2\x04\0\0\0\0\0\0\0key1\x0c\0\0\0\0\0\0\0overwritten!" ||
@@ -70,6 +129,43 @@ fn compact() {
     fs::remove_file("test_compact.jdb").unwrap();
 }
 
+#[test]
+fn compact_in_memory() {
+    let mut database = FileSource::new("test_compact_in_memory.jdb").unwrap();
+
+    database.write_entry("key1", "this is a value").unwrap();
+    database.write_entry("key2", "value 2").unwrap();
+    database.write_entry("key1", "overwritten!").unwrap();
+    database.write_entry("key2", "").unwrap();
+
+    let indexes = database.load_indexes().unwrap();
+
+    database
+        .compact_with(&indexes, CompactStrategy::InMemory)
+        .unwrap();
+
+    assert!(!fs::exists("test_compact_in_memory.jdbtmp").unwrap());
+    assert_eq!(
+        fs::metadata("test_compact_in_memory.jdb").unwrap().len(),
+        database.len + 16
+    );
+
+    let mut buf: Vec<u8> = vec![0; database.len as usize];
+    database.file.seek(SeekFrom::Start(16)).unwrap();
+    database.file.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, b"\x04\0\0\0\0\0\0\0key1\x0c\0\0\0\0\0\0\0overwritten!");
+
+    let index = database.write_entry("key3", "new value").unwrap();
+    assert_eq!(index, 32);
+    assert_eq!(
+        database.read_entry(index).unwrap(),
+        ("key3".to_string(), b"new value".to_vec())
+    );
+
+    drop(database);
+    fs::remove_file("test_compact_in_memory.jdb").unwrap();
+}
+
 #[test]
 fn open_existing() {
     {
@@ -89,6 +185,188 @@ fn open_existing() {
     fs::remove_file("test_open_existing.jdb").unwrap();
 }
 
+#[test]
+fn header() {
+    let mut database = FileSource::create("test_header.jdb").unwrap();
+    database.write_entry("key1", "value").unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    drop(database);
+
+    let contents = fs::read("test_header.jdb").unwrap();
+    assert_eq!(&contents[..8], b"JASONDB\0");
+    assert_eq!(&contents[8..16], &FORMAT_VERSION.to_le_bytes());
+
+    let mut database = FileSource::open("test_header.jdb").unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    assert_eq!(
+        database.read_entry(0).unwrap(),
+        ("key1".to_string(), b"value".to_vec())
+    );
+    drop(database);
+
+    fs::remove_file("test_header.jdb").unwrap();
+
+    let mut file = File::create("test_header_unsupported.jdb").unwrap();
+    file.write_all(b"JASONDB\0").unwrap();
+    file.write_all(&(FORMAT_VERSION + 1).to_le_bytes()).unwrap();
+    drop(file);
+
+    assert_eq!(
+        FileSource::open("test_header_unsupported.jdb").err(),
+        Some(JasonError::BadFormat)
+    );
+
+    fs::remove_file("test_header_unsupported.jdb").unwrap();
+}
+
+#[test]
+fn headerless() {
+    let mut file = File::create("test_headerless.jdb").unwrap();
+    file.write_all(b"\x04\0\0\0\0\0\0\0key1\x05\0\0\0\0\0\0\0value")
+        .unwrap();
+    drop(file);
+
+    let mut database = FileSource::open("test_headerless.jdb").unwrap();
+    assert_eq!(database.format_version(), 0);
+    assert_eq!(database.len, 25);
+
+    let index = database.write_entry("key2", "value 2").unwrap();
+    assert_eq!(index, 25);
+
+    let indexes = database.load_indexes().unwrap();
+    database.compact(&indexes).unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    drop(database);
+
+    let mut database = FileSource::open("test_headerless.jdb").unwrap();
+    let indexes = database.load_indexes().unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    assert_eq!(
+        database.read_entry(indexes["key1"]).unwrap(),
+        ("key1".to_string(), b"value".to_vec())
+    );
+    assert_eq!(
+        database.read_entry(indexes["key2"]).unwrap(),
+        ("key2".to_string(), b"value 2".to_vec())
+    );
+
+    drop(database);
+    fs::remove_file("test_headerless.jdb").unwrap();
+}
+
+/// Encodes an entry in the raw format, for building files by hand.
+fn raw_entry(k: &str, v: &str) -> Vec<u8> {
+    let mut entry = (k.len() as u64).to_le_bytes().to_vec();
+    entry.extend_from_slice(k.as_bytes());
+    entry.extend_from_slice(&(v.len() as u64).to_le_bytes());
+    entry.extend_from_slice(v.as_bytes());
+
+    entry
+}
+
+#[test]
+fn headerless_tombstones() {
+    let mut contents = raw_entry("key1", "\"value\"");
+    contents.extend(raw_entry("key2", "\"value 2\""));
+    contents.extend(raw_entry("key1", "null"));
+    fs::write("test_headerless_tombstones.jdb", &contents).unwrap();
+
+    let mut database = FileSource::open("test_headerless_tombstones.jdb").unwrap();
+    assert_eq!(database.format_version(), 0);
+
+    let indexes = database.load_indexes().unwrap();
+    assert_eq!(indexes.len(), 1);
+    assert!(indexes.contains_key("key2"));
+
+    // Writing `null` would delete the key when the file is next opened, so it isn't allowed until the file is upgraded.
+    assert_eq!(
+        database.write_entry("key3", "null").err(),
+        Some(JasonError::BadFormat)
+    );
+
+    database.compact(&indexes).unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    drop(database);
+
+    let mut database = FileSource::open("test_headerless_tombstones.jdb").unwrap();
+    let indexes = database.load_indexes().unwrap();
+    assert_eq!(indexes.len(), 1);
+    assert_eq!(
+        database.read_entry(indexes["key2"]).unwrap(),
+        ("key2".to_string(), b"\"value 2\"".to_vec())
+    );
+    assert_eq!(database.iter_raw().count(), 1);
+
+    database.write_entry("key3", "null").unwrap();
+    assert!(database.load_indexes().unwrap().contains_key("key3"));
+
+    drop(database);
+    fs::remove_file("test_headerless_tombstones.jdb").unwrap();
+}
+
+#[test]
+fn headerless_tombstones_background_compaction() {
+    let mut contents = raw_entry("key1", "\"value\"");
+    contents.extend(raw_entry("key2", "\"value 2\""));
+    fs::write("test_headerless_background.jdb", &contents).unwrap();
+
+    let mut database = FileSource::open("test_headerless_background.jdb").unwrap();
+    let indexes = database.load_indexes().unwrap();
+    let compaction = database.start_compaction(&indexes).unwrap();
+
+    // A deletion in the old format, written by an older version while the compaction is running.
+    OpenOptions::new()
+        .append(true)
+        .open("test_headerless_background.jdb")
+        .unwrap()
+        .write_all(&raw_entry("key1", "null"))
+        .unwrap();
+
+    database.refresh().unwrap();
+    database.finish_compaction(compaction).unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    drop(database);
+
+    let mut database = FileSource::open("test_headerless_background.jdb").unwrap();
+    let indexes = database.load_indexes().unwrap();
+    assert_eq!(indexes.len(), 1);
+    assert!(indexes.contains_key("key2"));
+
+    drop(database);
+    fs::remove_file("test_headerless_background.jdb").unwrap();
+}
+
+#[test]
+fn incompatible_format() {
+    let archive = tar_archive(&[("users/alice.json", br#"{"name":"Alice"}"#)]);
+    fs::write("test_incompatible_format.jdb", &archive).unwrap();
+
+    assert_eq!(
+        FileSource::open("test_incompatible_format.jdb").err(),
+        Some(JasonError::IncompatibleFormat)
+    );
+    assert_eq!(
+        FileSource::open_read_only("test_incompatible_format.jdb").err(),
+        Some(JasonError::IncompatibleFormat)
+    );
+    assert_eq!(
+        InMemory::from_bytes_checked(archive.clone()).err(),
+        Some(JasonError::IncompatibleFormat)
+    );
+
+    // A corrupted checksum means the data is no longer recognised as an archive.
+    let mut corrupted = archive;
+    corrupted[0] = b'U';
+    fs::write("test_incompatible_format.jdb", &corrupted).unwrap();
+
+    assert!(matches!(
+        FileSource::open("test_incompatible_format.jdb"),
+        Ok(source) if source.format_version() == 0
+    ));
+
+    fs::remove_file("test_incompatible_format.jdb").unwrap();
+}
+
 #[test]
 fn index_on() -> Result<(), Box<dyn std::error::Error>> {
     let mut database = FileSource::new("test_index_on.jdb")?;
@@ -1,10 +1,15 @@
 use crate::error::JasonError;
 use crate::sources::{InMemory, Source};
-use crate::Database;
+use crate::{ChangeEvent, Database};
 
-use crate::tests::mock::{composers_db, AgedPerson, Person};
+use crate::tests::mock::{composers_db, AgedPerson, Document, Person};
 
+use humphrey_json::json;
+
+use std::cell::Cell;
 use std::fs;
+use std::rc::Rc;
+use std::sync::mpsc::channel;
 
 #[test]
 fn basic() -> Result<(), JasonError> {
@@ -59,6 +64,590 @@ fn delete() -> Result<(), JasonError> {
     Ok(())
 }
 
+#[test]
+fn deleted_keys() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    database.delete("mozart")?;
+    database.delete("bach")?;
+    database.delete("brahms")?;
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+
+    assert_eq!(database.deleted_keys()?, vec!["mozart", "brahms"]);
+
+    database.compact()?;
+    assert!(database.deleted_keys()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn history() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    database.delete("bach")?;
+    database.set("bach", Person::new("Carl Philipp Emanuel Bach", 1714))?;
+
+    let years = database
+        .history("bach")?
+        .into_iter()
+        .map(|person| person.year_of_birth)
+        .collect::<Vec<_>>();
+
+    assert_eq!(years, vec![1685, 1735, 1714]);
+    assert!(database.history("elgar")?.is_empty());
+
+    database.compact()?;
+    assert_eq!(
+        database.history("bach")?,
+        vec![Person::new("Carl Philipp Emanuel Bach", 1714)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn remove() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    database.delete("brahms")?;
+    database.set("brahms", Person::new("Johannes Brahms", 1833))?;
+
+    database.remove("bach")?;
+    database.remove("brahms")?;
+
+    // Only the entries for the remaining keys should be left, in their original order.
+    let mut expected: Database<Person, InMemory> = Database::new_in_memory();
+    expected.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    expected.set("saint_saens", Person::new("Camille Saint-Saëns", 1835))?;
+    expected.set("tchaikovsky", Person::new("Pyotr Ilyich Tchaikovsky", 1840))?;
+    expected.set("shostakovich", Person::new("Dmitri Shostakovich", 1906))?;
+
+    assert_eq!(database.len(), 4);
+    assert_eq!(database.source.data, expected.source.data);
+    assert!(database.get("bach").is_err());
+    assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+    assert_eq!(
+        database.query_keys(query!(year_of_birth > 1800))?,
+        vec!["saint_saens", "tchaikovsky", "shostakovich"]
+    );
+
+    let indexes = database.primary_indexes.clone();
+    database.reload()?;
+
+    assert_eq!(database.primary_indexes, indexes);
+    assert!(database.get("brahms").is_err());
+    assert_eq!(
+        database.query_keys(query!(year_of_birth > 1800))?,
+        vec!["saint_saens", "tchaikovsky", "shostakovich"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn compact_report() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    let bytes_before = database.source.size();
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    database.delete("brahms")?;
+
+    let report = database.compact_report()?;
+
+    assert_eq!(report.entries_kept, 5);
+    assert_eq!(report.entries_dropped, 3);
+    assert_eq!(report.bytes_after, database.source.size());
+    assert!(report.bytes_before > bytes_before);
+    assert_eq!(
+        report.bytes_reclaimed(),
+        report.bytes_before - report.bytes_after
+    );
+
+    let report = database.compact_report()?;
+
+    assert_eq!(report.entries_dropped, 0);
+    assert_eq!(report.bytes_reclaimed(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn compaction_estimate() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    let estimate = database.compaction_estimate()?;
+    assert_eq!(estimate.bytes_current, database.source.size());
+    assert_eq!(estimate.bytes_reclaimable(), 0);
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    database.delete("brahms")?;
+
+    let estimate = database.compaction_estimate()?;
+    let report = database.compact_report()?;
+
+    assert_eq!(estimate.bytes_current, report.bytes_before);
+    assert_eq!(estimate.bytes_live, report.bytes_after);
+    assert_eq!(estimate.bytes_reclaimable(), report.bytes_reclaimed());
+
+    Ok(())
+}
+
+#[test]
+fn set_with_unreadable_old_value() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_sorted_index("year_of_birth")?;
+
+    // Corrupt the old value, so that it can't be removed from the indexes.
+    let offset = database.primary_indexes["bach"] as usize;
+    database.source.data[offset + 20] = b'!';
+    let size = database.source.data.len();
+
+    assert_eq!(
+        database.set("bach", Person::new("Johann Christian Bach", 1735)),
+        Err(JasonError::JsonError)
+    );
+
+    assert_eq!(database.source.data.len(), size);
+    assert_eq!(database.primary_indexes["bach"], offset as u64);
+    assert_eq!(
+        database.secondary_indexes["year_of_birth"][&json!(1685)],
+        [offset as u64].into_iter().collect()
+    );
+    assert!(!database.secondary_indexes["year_of_birth"].contains_key(&json!(1735)));
+    assert_eq!(database.sorted_indexes["year_of_birth"].len(), 6);
+
+    Ok(())
+}
+
+#[test]
+fn rebuild() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+    database.delete("shostakovich")?;
+
+    // Simulate index drift, an entry which isn't a valid value, and a partially written entry at the end.
+    database.primary_indexes.remove("bach");
+    database.source.write_entry("elgar", b"{\"name\":")?;
+    database.source.data.extend_from_slice(&[0xff; 12]);
+
+    database.rebuild()?;
+
+    assert_eq!(database.len(), 5);
+    assert_eq!(
+        database.get("bach")?,
+        Person::new("Johann Sebastian Bach", 1685)
+    );
+    assert!(matches!(database.get("elgar"), Err(JasonError::InvalidKey)));
+    assert!(matches!(
+        database.get("shostakovich"),
+        Err(JasonError::InvalidKey)
+    ));
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1685))?,
+        vec!["bach"]
+    );
+
+    let estimate = database.compaction_estimate()?;
+    assert_eq!(estimate.bytes_reclaimable(), 0);
+    assert_eq!(database.source.iter_raw().count(), 5);
+
+    Ok(())
+}
+
+#[test]
+fn capacity() -> Result<(), JasonError> {
+    let (tx, rx) = channel();
+
+    // Each of these entries is 70 bytes long, so the database can hold three of them.
+    let person = |year| Person::new("Johann Sebastian Bach", year);
+    let source = InMemory::new().with_capacity_bytes(220);
+    let mut database: Database<Person, InMemory> =
+        Database::from_source(source)?.with_index("year_of_birth")?;
+
+    database.on_change(move |event| {
+        if let ChangeEvent::Delete { key } = event {
+            tx.send(key.to_string()).unwrap();
+        }
+    });
+
+    database.set("a", person(1000))?;
+    database.set("b", person(1001))?;
+    database.set("c", person(1002))?;
+    assert_eq!(database.source.size(), 210);
+
+    // Overwriting a value moves it to the end, and only dead space is reclaimed.
+    database.set("a", person(1003))?;
+    assert_eq!(database.len(), 3);
+    assert_eq!(database.source.size(), 210);
+
+    // The least recently written value is evicted.
+    database.set("d", person(1004))?;
+    assert_eq!(database.len(), 3);
+    assert_eq!(database.get("b"), Err(JasonError::InvalidKey));
+    assert!(database
+        .query_keys(query!(year_of_birth == 1001))?
+        .is_empty());
+    assert_eq!(
+        database.query_keys(query!(year_of_birth > 1000))?,
+        vec!["c", "a", "d"]
+    );
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["b"]);
+
+    let offset = database.set_returning_offset("e", person(1005))?;
+    assert_eq!(database.offset_of("e"), Some(offset));
+    assert_eq!(database.get_at_offset(offset)?.1, person(1005));
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec!["c"]);
+
+    let huge = Person::new("x".repeat(220), 0);
+    assert_eq!(database.set("f", huge), Err(JasonError::ValueTooLarge));
+    assert_eq!(database.len(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn capacity_after_migrate() -> Result<(), JasonError> {
+    let person = |year| Person::new("Johann Sebastian Bach", year);
+    let source = InMemory::new().with_capacity_bytes(220);
+    let mut database: Database<Person, InMemory> = Database::from_source(source)?;
+
+    database.set("a", person(1000))?;
+    database.set("b", person(1001))?;
+    database.set("c", person(1002))?;
+
+    let mut database = database.migrate(|p| AgedPerson::new(p.name, 2000 - p.year_of_birth))?;
+    assert_eq!(database.source.capacity(), Some(220));
+
+    // Each migrated entry is 58 bytes long, so a fourth one evicts the least recently written.
+    database.set("d", AgedPerson::new("Johann Sebastian Bach", 996))?;
+    assert_eq!(database.len(), 3);
+    assert_eq!(database.get("a"), Err(JasonError::InvalidKey));
+    assert_eq!(database.get("b")?.age, 999);
+
+    Ok(())
+}
+
+#[test]
+fn len() -> Result<(), JasonError> {
+    let mut database: Database<Person, InMemory> = Database::new_in_memory();
+    assert_eq!(database.len(), 0);
+    assert!(database.is_empty());
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    assert_eq!(database.len(), 2);
+    assert!(!database.is_empty());
+
+    database.delete("bach")?;
+    database.delete("mozart")?;
+    assert_eq!(database.len(), 0);
+    assert!(database.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn keys() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    database.delete("shostakovich")?;
+
+    // Neither method reads from the source, so they still work without its data.
+    database.source.data.clear();
+
+    let mut keys = database.keys().collect::<Vec<_>>();
+    keys.sort();
+
+    assert_eq!(
+        keys,
+        vec!["bach", "brahms", "mozart", "saint_saens", "tchaikovsky"]
+    );
+    assert!(database.contains_key("bach"));
+    assert!(database.contains_key(String::from("mozart")));
+    assert!(!database.contains_key("shostakovich"));
+    assert!(!database.contains_key("elgar"));
+
+    Ok(())
+}
+
+#[test]
+fn extend() -> Result<(), JasonError> {
+    let mut database: Database<Person, InMemory> = Database::new_in_memory().with_index("name")?;
+
+    database.extend([
+        ("bach", Person::new("Johann Sebastian Bach", 1685)),
+        ("mozart", Person::new("Wolfgang Amadeus Mozart", 1756)),
+    ]);
+
+    database.try_extend(vec![(
+        "brahms".to_string(),
+        Person::new("Johannes Brahms", 1833),
+    )])?;
+
+    assert_eq!(database.len(), 3);
+    assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+    assert_eq!(
+        database.query(query!(name == "Johannes Brahms"))?.count(),
+        1
+    );
+
+    Ok(())
+}
+
+#[test]
+fn offsets() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    let offset = database.offset_of("brahms").unwrap();
+    assert_eq!(
+        database.get_at_offset(offset)?,
+        ("brahms".to_string(), Person::new("Johannes Brahms", 1833))
+    );
+    assert!(database.offset_of("beethoven").is_none());
+
+    database.delete("brahms")?;
+    assert!(database.offset_of("brahms").is_none());
+    assert_eq!(database.get_at_offset(offset)?.0, "brahms");
+    let size = database.source.size();
+    assert!(database.get_at_offset(size).is_err());
+
+    let offset = database.set_returning_offset("elgar", Person::new("Edward Elgar", 1857))?;
+    assert_eq!(offset, size);
+    assert_eq!(database.offset_of("elgar"), Some(offset));
+    assert_eq!(database.get_at_offset(offset)?.1.year_of_birth, 1857);
+
+    Ok(())
+}
+
+#[test]
+fn insert() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    let size = database.source.size();
+
+    assert_eq!(
+        database.insert("bach", Person::new("Johann Christian Bach", 1735)),
+        Err(JasonError::DuplicateKey)
+    );
+    assert_eq!(database.get("bach")?.year_of_birth, 1685);
+    assert_eq!(database.source.size(), size);
+
+    database.insert("elgar", Person::new("Edward Elgar", 1857))?;
+    assert_eq!(database.get("elgar")?.year_of_birth, 1857);
+
+    // Deleted keys can be inserted again.
+    database.delete("elgar")?;
+    database.insert("elgar", Person::new("Edward Elgar", 1857))?;
+    assert_eq!(database.len(), 7);
+
+    Ok(())
+}
+
+#[test]
+fn upsert() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    assert!(!database.upsert("elgar", Person::new("Edward Elgar", 1857))?);
+    assert!(database.upsert("elgar", Person::new("Edward Elgar", 1858))?);
+
+    assert_eq!(database.len(), 7);
+    assert_eq!(database.get("elgar")?.year_of_birth, 1858);
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1858))?,
+        vec!["elgar"]
+    );
+
+    database.delete("elgar")?;
+    assert!(!database.upsert("elgar", Person::new("Edward Elgar", 1857))?);
+
+    Ok(())
+}
+
+#[test]
+fn set_value() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    let elgar = json!({"name": "Edward Elgar", "year_of_birth": 1857});
+    database.set_value("elgar", &elgar)?;
+    assert_eq!(database.get("elgar")?, Person::new("Edward Elgar", 1857));
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1857))?,
+        vec!["elgar"]
+    );
+
+    // Values which don't fit the type are written and indexed, but can't be read back as the type.
+    let bach = json!({"name": "Johann Sebastian Bach", "year_of_birth": "unknown"});
+    database.set_value("bach", &bach)?;
+    assert_eq!(database.get("bach"), Err(JasonError::JsonError));
+    assert!(database
+        .query_keys(query!(year_of_birth == 1685))?
+        .is_empty());
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == "unknown"))?,
+        vec!["bach"]
+    );
+
+    database.delete("bach")?;
+    assert!(database
+        .query_keys(query!(year_of_birth == "unknown"))?
+        .is_empty());
+
+    // With observers, the value must fit the type so that they can be given it.
+    database.on_change(|_| {});
+    assert_eq!(
+        database.set_value("bach", &bach),
+        Err(JasonError::JsonError)
+    );
+    assert!(database.get("bach").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn update() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    database.update("bach", |person| person.year_of_birth += 1)?;
+    database.update("bach", |person| person.year_of_birth += 1)?;
+
+    assert_eq!(database.get("bach")?.year_of_birth, 1687);
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1687))?,
+        vec!["bach"]
+    );
+    assert!(database
+        .query_keys(query!(year_of_birth == 1685))?
+        .is_empty());
+
+    let mut called = false;
+    assert!(matches!(
+        database.update("elgar", |_| called = true),
+        Err(JasonError::InvalidKey)
+    ));
+    assert!(!called);
+    assert!(!database.contains_key("elgar"));
+
+    Ok(())
+}
+
+#[test]
+fn patch() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    database.patch("bach", "year_of_birth", json!(1735))?;
+    assert_eq!(
+        database.get("bach")?,
+        Person::new("Johann Sebastian Bach", 1735)
+    );
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1735))?,
+        vec!["bach"]
+    );
+    assert!(database
+        .query_keys(query!(year_of_birth == 1685))?
+        .is_empty());
+
+    assert_eq!(
+        database.patch("elgar", "year_of_birth", json!(1857)),
+        Err(JasonError::InvalidKey)
+    );
+    assert_eq!(
+        database.patch("bach", "name.first", json!("Johann")),
+        Err(JasonError::JsonError)
+    );
+
+    // Missing fields along the path are created.
+    let mut database: Database<Document, InMemory> = Database::new_in_memory();
+    database.set(
+        "bach",
+        Document(json!({"name": "Johann Sebastian Bach", "address": null})),
+    )?;
+    database.patch("bach", "address.city", json!("Leipzig"))?;
+    database.patch("bach", "children.count", json!(20))?;
+
+    assert_eq!(
+        database.get("bach")?.0,
+        json!({
+            "name": "Johann Sebastian Bach",
+            "address": {"city": "Leipzig"},
+            "children": {"count": 20}
+        })
+    );
+
+    Ok(())
+}
+
+#[test]
+fn get_by_keys() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    let composers = database.get_by_keys(["mozart", "bach", "elgar", "bach"])?;
+
+    assert_eq!(composers.len(), 2);
+    assert_eq!(
+        composers["bach"],
+        Person::new("Johann Sebastian Bach", 1685)
+    );
+    assert_eq!(
+        composers["mozart"],
+        Person::new("Wolfgang Amadeus Mozart", 1756)
+    );
+    assert!(database.get_by_keys(&[] as &[&str])?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn get_many() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    let composers = database.get_many(&["shostakovich", "elgar", "bach", "shostakovich"])?;
+
+    assert_eq!(
+        composers,
+        vec![
+            Some(Person::new("Dmitri Shostakovich", 1906)),
+            None,
+            Some(Person::new("Johann Sebastian Bach", 1685)),
+            Some(Person::new("Dmitri Shostakovich", 1906)),
+        ]
+    );
+    assert!(database.get_many(&[] as &[&str])?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn on_change() -> Result<(), JasonError> {
+    let (tx, rx) = channel();
+    let mut database: Database<Person, InMemory> = Database::new_in_memory();
+
+    database.on_change(move |event| {
+        let event = match event {
+            ChangeEvent::Set { key, value } => (key.to_string(), Some(value.clone())),
+            ChangeEvent::Delete { key } => (key.to_string(), None),
+        };
+
+        tx.send(event).unwrap();
+    });
+
+    let person = Person::new("Johann Sebastian Bach", 1685);
+
+    database.set("bach", &person)?;
+    database.delete("bach")?;
+    assert!(database.delete("bach").is_err());
+
+    assert_eq!(
+        rx.try_iter().collect::<Vec<_>>(),
+        vec![
+            ("bach".to_string(), Some(person)),
+            ("bach".to_string(), None)
+        ]
+    );
+
+    Ok(())
+}
+
 #[test]
 fn optimised_query_1() -> Result<(), JasonError> {
     let source = InMemory::new();
@@ -146,6 +735,217 @@ fn optimised_query_4() -> Result<(), JasonError> {
     Ok(())
 }
 
+#[test]
+fn optimised_query_5() -> Result<(), JasonError> {
+    let source = InMemory::new();
+    let mut database = composers_db(source)?.with_index(field!(year_of_birth))?;
+
+    // Get composers born between 1833 and 1840 inclusive
+    let query = query!(year_of_birth in 1833..=1840);
+
+    let composers: Vec<String> = query
+        .execute_optimised(&mut database)?
+        .flatten()
+        .map(|(_, person)| person.name)
+        .collect();
+
+    assert_eq!(composers.len(), 3);
+    assert!(composers.contains(&"Johannes Brahms".to_string()));
+    assert!(composers.contains(&"Camille Saint-Saëns".to_string()));
+    assert!(composers.contains(&"Pyotr Ilyich Tchaikovsky".to_string()));
+
+    // The upper bound of an exclusive range is not included
+    let query = query!(year_of_birth in 1833..1840);
+
+    let composers: Vec<String> = query
+        .execute_optimised(&mut database)?
+        .flatten()
+        .map(|(_, person)| person.name)
+        .collect();
+
+    assert_eq!(composers.len(), 2);
+    assert!(composers.contains(&"Johannes Brahms".to_string()));
+    assert!(composers.contains(&"Camille Saint-Saëns".to_string()));
+
+    Ok(())
+}
+
+#[test]
+fn optimised_range_query() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    let queries = || {
+        vec![
+            query!(year_of_birth in 1800..1900),
+            query!(year_of_birth in 1833..=1840),
+            query!(year_of_birth in 1835..1835),
+            query!(year_of_birth in ..1756),
+            query!(year_of_birth in 1840..),
+            query!(year_of_birth in 1700..1900) & query!(year_of_birth in 1800..2000),
+        ]
+    };
+
+    for (optimised, unoptimised) in queries().into_iter().zip(queries()) {
+        let mut optimised_keys = optimised
+            .execute_optimised(&mut database)?
+            .map(|r| r.map(|(k, _)| k))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut unoptimised_keys = unoptimised
+            .execute_unoptimised(&mut database)?
+            .map(|r| r.map(|(k, _)| k))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        optimised_keys.sort();
+        unoptimised_keys.sort();
+
+        assert_eq!(optimised_keys, unoptimised_keys);
+    }
+
+    let mut keys = database.query_keys(query!(year_of_birth in 1800..1900))?;
+    keys.sort();
+    assert_eq!(keys, vec!["brahms", "saint_saens", "tchaikovsky"]);
+
+    Ok(())
+}
+
+#[test]
+fn query_keys() -> Result<(), JasonError> {
+    let source = InMemory::new();
+    let mut database = composers_db(source)?.with_index(field!(year_of_birth))?;
+
+    // Get only 19th-century composers, both with and without an index
+    let optimised = database.query_keys(query!(year_of_birth in 1800..=1899))?;
+    let unoptimised = database.query_keys(query!(name, |n| n
+        .as_str()
+        .map(|n| n.contains("Brahms") || n.contains("Saint-Saëns") || n.contains("Tchaikovsky"))
+        .unwrap_or(false)))?;
+
+    assert_eq!(optimised, vec!["brahms", "saint_saens", "tchaikovsky"]);
+    assert_eq!(unoptimised, optimised);
+
+    database.delete("brahms")?;
+
+    assert_eq!(
+        database.query_keys(query!(year_of_birth in 1800..=1899))?,
+        vec!["saint_saens", "tchaikovsky"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn count() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    // Reading this value would fail, so counts only succeed if it isn't read.
+    database.set_value(
+        "elgar",
+        &json!({"name": "Edward Elgar", "year_of_birth": "1857"}),
+    )?;
+
+    assert_eq!(database.count(), 7);
+    assert!(database.query_count(query!(year_of_birth > 1800)).is_err());
+
+    let mut database = database.with_index("name")?.with_index("year_of_birth")?;
+
+    assert_eq!(database.query_count(query!(year_of_birth > 1800))?, 4);
+    assert_eq!(database.query_count(query!(name == "Edward Elgar"))?, 1);
+    assert_eq!(
+        query!(year_of_birth < 1800).count(&mut database)?,
+        database.query_keys(query!(year_of_birth < 1800))?.len()
+    );
+    assert_eq!(
+        database.query_count(query!(year_of_birth < 1800) | query!(name == "Edward Elgar"))?,
+        3
+    );
+    assert_eq!(
+        database.query_count(query!(year_of_birth > 1900) & query!(year_of_birth < 1800))?,
+        0
+    );
+
+    Ok(())
+}
+
+#[test]
+fn compiled_query() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index(field!(year_of_birth))?;
+    let mut unindexed = composers_db(InMemory::new())?;
+
+    // Get 19th-century composers whose names contain a space
+    let query = query!(name, |n| n
+        .as_str()
+        .map(|n| n.contains(' '))
+        .unwrap_or(false))
+        & query!(year_of_birth >= 1800)
+        & query!(year_of_birth < 1900);
+    let compiled = query.compile(&database);
+
+    for _ in 0..2 {
+        let composers: Vec<String> = database
+            .query_compiled(&compiled)?
+            .flatten()
+            .map(|(k, _)| k)
+            .collect();
+
+        assert_eq!(composers, vec!["brahms", "saint_saens", "tchaikovsky"]);
+    }
+
+    // The plan relies on an index which this database doesn't have, so the query is replanned.
+    let composers: Vec<String> = compiled
+        .execute(&mut unindexed)?
+        .flatten()
+        .map(|(_, person)| person.name)
+        .collect();
+
+    assert_eq!(composers.len(), 3);
+    assert!(composers.contains(&"Johannes Brahms".to_string()));
+    assert!(composers.contains(&"Camille Saint-Saëns".to_string()));
+    assert!(composers.contains(&"Pyotr Ilyich Tchaikovsky".to_string()));
+
+    Ok(())
+}
+
+#[test]
+fn stream_query() -> Result<(), JasonError> {
+    let checked = Rc::new(Cell::new(0));
+    let counter = checked.clone();
+
+    // Get composers with a three-word name, counting how many values are checked
+    let three_words = move || {
+        let counter = counter.clone();
+
+        query!(name, move |n| {
+            counter.set(counter.get() + 1);
+            n.as_str()
+                .map(|n| n.split(' ').count() == 3)
+                .unwrap_or(false)
+        })
+    };
+
+    let mut database = composers_db(InMemory::new())?;
+    let first = database.stream_query(three_words())?.next();
+
+    assert_eq!(
+        first.map(|r| r.map(|(k, _)| k)),
+        Some(Ok("bach".to_string()))
+    );
+    assert_eq!(checked.get(), 1);
+
+    let mut database = database.with_index(field!(year_of_birth))?;
+    checked.set(0);
+
+    let composers: Vec<String> = database
+        .stream_query(three_words() & query!(year_of_birth >= 1750))?
+        .flatten()
+        .map(|(k, _)| k)
+        .collect();
+
+    assert_eq!(composers, vec!["mozart", "tchaikovsky"]);
+    assert_eq!(checked.get(), 5);
+
+    Ok(())
+}
+
 #[test]
 fn unoptimised_query() -> Result<(), JasonError> {
     let source = InMemory::new();
@@ -168,6 +968,165 @@ fn unoptimised_query() -> Result<(), JasonError> {
     Ok(())
 }
 
+#[test]
+fn string_query() -> Result<(), JasonError> {
+    let unindexed = composers_db(InMemory::new())?;
+    let indexed = composers_db(InMemory::new())?.with_index("name")?;
+
+    for mut database in [unindexed, indexed] {
+        // Names are compared by their first letters, so "Wolfgang Amadeus Mozart" comes after "M".
+        let mut keys = database.query_keys(query!(name >= "M"))?;
+        keys.sort();
+        assert_eq!(keys, vec!["mozart", "tchaikovsky"]);
+
+        let mut keys = database.query_keys(query!(name < "D") | query!(name > "Pz"))?;
+        keys.sort();
+        assert_eq!(keys, vec!["mozart", "saint_saens"]);
+
+        let mut keys = database.query_keys(query!(name ~ "Bach") | query!(name ~ "kov"))?;
+        keys.sort();
+        assert_eq!(keys, vec!["bach", "shostakovich", "tchaikovsky"]);
+
+        // Strings and numbers are never ordered relative to each other.
+        assert!(database.query_keys(query!(name > 0))?.is_empty());
+        assert!(database.query_keys(query!(year_of_birth < "Z"))?.is_empty());
+        assert!(database.query_keys(query!(name in 0..))?.is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unsatisfiable_query() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    // Reading this value would fail, so these queries only succeed if nothing is read.
+    database.set_value(
+        "elgar",
+        &json!({"name": "Edward Elgar", "year_of_birth": "1857"}),
+    )?;
+
+    let query = || query!(year_of_birth > 1900) & query!(year_of_birth < 1800);
+
+    assert_eq!(database.query(query())?.count(), 0);
+    assert!(database.query_keys(query())?.is_empty());
+    assert_eq!(database.stream_query(query())?.count(), 0);
+
+    let compiled = query().compile(&database);
+    assert_eq!(database.query_compiled(&compiled)?.count(), 0);
+
+    assert!(database
+        .query_keys(query!(year_of_birth in 1800..1850) & query!(year_of_birth >= 1850))?
+        .is_empty());
+
+    // Bounds which only just overlap could still match.
+    assert!(database
+        .query_keys(query!(year_of_birth >= 1685) & query!(year_of_birth <= 1685))
+        .is_err());
+
+    let mut database = database.with_index("name")?;
+
+    assert!(database
+        .query_keys(query!(name == "Ludwig van Beethoven") & query!(year_of_birth == 1770))?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn debug() -> Result<(), JasonError> {
+    let source = InMemory::new();
+    let database = composers_db(source)?
+        .with_index(field!(year_of_birth))?
+        .with_index(field!(name))?;
+
+    assert_eq!(
+        format!("{:?}", database),
+        "Database { keys: 6, indexed_fields: [\"name\", \"year_of_birth\"], replicas: 0, source: \"memory\", .. }"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_bytes() -> Result<(), JasonError> {
+    let database = composers_db(InMemory::new())?;
+    let bytes = database.snapshot_bytes();
+
+    assert_eq!(&bytes[..8], b"JASONDB\0");
+    assert_eq!(&bytes[16..], database.source.as_bytes());
+
+    let mut restored: Database<Person, InMemory> =
+        Database::from_source(InMemory::from_bytes(bytes))?.with_index(field!(year_of_birth))?;
+
+    assert_eq!(restored.len(), 6);
+    assert_eq!(restored.get("bach")?.year_of_birth, 1685);
+    assert_eq!(restored.query(query!(year_of_birth >= 1900))?.count(), 1);
+    assert_eq!(restored.source.into_bytes(), database.source.into_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn max_value_size() -> Result<(), JasonError> {
+    let mut database: Database<Person, InMemory> =
+        Database::new_in_memory().with_max_value_size(54);
+
+    let bach = Person::new("Johann Sebastian Bach", 1685);
+    let mozart = Person::new("Wolfgang Amadeus Mozart", 1756);
+
+    database.set("bach", &bach)?;
+    let size = database.source.size();
+
+    assert_eq!(
+        database.set("mozart", &mozart),
+        Err(JasonError::ValueTooLarge)
+    );
+    assert_eq!(database.source.size(), size);
+    assert_eq!(database.len(), 1);
+    assert_eq!(database.get("bach"), Ok(bach));
+
+    Ok(())
+}
+
+#[test]
+fn canonical_raw_json() -> Result<(), JasonError> {
+    let mut database: Database<Person, InMemory> = Database::new_in_memory();
+    let mut sorted_database: Database<Person, InMemory> =
+        Database::new_in_memory().with_sorted_keys();
+
+    let raw_json = "{ \"year_of_birth\": 1685,\n  \"name\": \"Johann Sebastian Bach\" }";
+
+    database.set_raw("bach", raw_json.as_bytes())?;
+    sorted_database.set_raw("bach", raw_json.as_bytes())?;
+    sorted_database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+
+    let index = database.primary_indexes["bach"];
+    let sorted_index = sorted_database.primary_indexes["bach"];
+    let sorted_index_2 = sorted_database.primary_indexes["mozart"];
+
+    assert_eq!(
+        database.source.read_entry(index)?.1,
+        b"{\"year_of_birth\":1685,\"name\":\"Johann Sebastian Bach\"}"
+    );
+    assert_eq!(
+        sorted_database.source.read_entry(sorted_index)?.1,
+        b"{\"name\":\"Johann Sebastian Bach\",\"year_of_birth\":1685}"
+    );
+    assert_eq!(
+        sorted_database.source.read_entry(sorted_index_2)?.1,
+        b"{\"name\":\"Wolfgang Amadeus Mozart\",\"year_of_birth\":1756}"
+    );
+
+    assert_eq!(
+        database.get("bach")?,
+        Person::new("Johann Sebastian Bach", 1685)
+    );
+    assert!(database.set_raw("invalid", b"{").is_err());
+
+    Ok(())
+}
+
 #[test]
 fn into_file() -> Result<(), JasonError> {
     let source = InMemory::new();
@@ -244,3 +1203,17 @@ fn migration() -> Result<(), JasonError> {
 
     Ok(())
 }
+
+#[test]
+fn invalid_offsets() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    let offset = database.offset_of("brahms").unwrap();
+    let size = database.source.size();
+
+    // A misaligned offset reads part of the key as the length of the key, which runs past the end of the source.
+    for offset in [offset + 1, size, size + 100, u64::MAX] {
+        assert_eq!(database.get_at_offset(offset), Err(JasonError::Index));
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use crate::sources::{InMemory, Source};
+use crate::error::JasonError;
+use crate::sources::{InMemory, Source, FORMAT_VERSION};
 
 use humphrey_json::prelude::*;
 
@@ -19,6 +20,51 @@ fn read_write() {
     assert!(database.read_entry(1234).is_err());
 }
 
+#[test]
+fn iter_raw() {
+    let mut database = InMemory::new();
+
+    let index_1 = database.write_entry("key1", "this is a value").unwrap();
+    let index_2 = database.write_entry("key1", "overwritten!").unwrap();
+    let index_3 = database.write_entry("key1", "").unwrap();
+
+    let entries = database.iter_raw().collect::<Result<Vec<_>, _>>().unwrap();
+
+    assert_eq!(
+        entries,
+        vec![
+            ("key1".to_string(), b"this is a value".to_vec(), index_1),
+            ("key1".to_string(), b"overwritten!".to_vec(), index_2),
+            ("key1".to_string(), b"".to_vec(), index_3),
+        ]
+    );
+}
+
+#[test]
+fn truncate_to() {
+    let mut database = InMemory::new();
+
+    database.write_entry("key1", "this is a value").unwrap();
+    let index_2 = database.write_entry("key1", "overwritten!").unwrap();
+    database.write_entry("key2", "value 2").unwrap();
+
+    let size = database.size();
+    assert!(database.truncate_to(size + 1).is_err());
+
+    database.truncate_to(index_2).unwrap();
+    assert_eq!(database.size(), index_2);
+
+    let indexes = database.load_indexes().unwrap();
+    assert_eq!(indexes.len(), 1);
+    assert_eq!(
+        database.read_entry(indexes["key1"]).unwrap().1,
+        b"this is a value".to_vec()
+    );
+
+    let index_3 = database.write_entry("key3", "value 3").unwrap();
+    assert_eq!(index_3, index_2);
+}
+
 #[test]
 fn load_indexes() {
     let mut database = InMemory::new();
@@ -27,7 +73,7 @@ fn load_indexes() {
     let index_2 = database.write_entry("key2", "value 2").unwrap();
     let index_3 = database.write_entry("key1", "overwritten!").unwrap();
     database.write_entry("key3", "not null").unwrap();
-    database.write_entry("key3", "null").unwrap();
+    database.write_entry("key3", "").unwrap();
 
     let indexes = database.load_indexes().unwrap();
 
@@ -44,12 +90,16 @@ fn compact() {
     database.write_entry("key2", "value 2").unwrap();
     database.write_entry("key1", "overwritten!").unwrap();
     database.write_entry("key3", "not null").unwrap();
-    database.write_entry("key3", "null").unwrap();
+    database.write_entry("key3", "").unwrap();
 
     let indexes = database.load_indexes().unwrap();
+    let bytes_before = database.size();
 
-    database.compact(&indexes).unwrap();
+    let stats = database.compact(&indexes).unwrap();
 
+    assert_eq!(stats.bytes_before, bytes_before);
+    assert_eq!(stats.bytes_after, database.size());
+    assert_eq!(stats.bytes_reclaimed(), bytes_before - 59);
     assert!(
         database.data == b"\x04\0\0\0\0\0\0\0key2\x07\0\0\0\0\0\0\0value 2\x04\0\0\0\0\0\0\0key1\x0c\0\0\0\0\0\0\0overwritten!" ||
         database.data == b"\x04\0\0\0\0\0\0\0key1\x0c\0\0\0\0\0\0\0overwritten!\x04\0\0\0\0\0\0\0key2\x07\0\0\0\0\0\0\0value 2"
@@ -96,3 +146,62 @@ fn index_on() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn from_bytes() {
+    let data = b"\x04\0\0\0\0\0\0\0key1\x05\0\0\0\0\0\0\0value".to_vec();
+
+    let mut database = InMemory::from_bytes(data.clone());
+    assert_eq!(database.data, data);
+    assert_eq!(
+        database.read_entry(0).unwrap(),
+        ("key1".to_string(), b"value".to_vec())
+    );
+
+    let mut with_header = b"JASONDB\0\x01\0\0\0\0\0\0\0".to_vec();
+    with_header.extend_from_slice(&data);
+
+    let database = InMemory::from_bytes_checked(with_header.clone()).unwrap();
+    assert_eq!(database.data, data);
+
+    let database = InMemory::try_from(with_header.as_slice()).unwrap();
+    assert_eq!(database.data, data);
+
+    assert_eq!(
+        InMemory::from_bytes_checked(data[..data.len() - 1].to_vec()).err(),
+        Some(JasonError::Index)
+    );
+
+    with_header[8] = 0xff;
+    assert_eq!(
+        InMemory::from_bytes_checked(with_header).err(),
+        Some(JasonError::BadFormat)
+    );
+}
+
+#[test]
+fn headerless_tombstones() {
+    let data = b"\x04\0\0\0\0\0\0\0key1\x03\0\0\0\0\0\0\0\"a\"\x04\0\0\0\0\0\0\0key1\x04\0\0\0\0\0\0\0null".to_vec();
+
+    let mut database = InMemory::from_bytes(data.clone());
+    assert_eq!(database.format_version(), 0);
+    assert!(database.load_indexes().unwrap().is_empty());
+    assert_eq!(database.into_bytes(), data);
+
+    let mut database = InMemory::from_bytes(data);
+    assert_eq!(
+        database.write_entry("key2", "null").err(),
+        Some(JasonError::BadFormat)
+    );
+
+    let indexes = database.load_indexes().unwrap();
+    database.compact(&indexes).unwrap();
+    assert_eq!(database.format_version(), FORMAT_VERSION);
+    assert!(database.as_bytes().is_empty());
+
+    database.write_entry("key2", "null").unwrap();
+
+    let mut restored = InMemory::from_bytes(database.into_bytes());
+    assert_eq!(restored.format_version(), FORMAT_VERSION);
+    assert!(restored.load_indexes().unwrap().contains_key("key2"));
+}
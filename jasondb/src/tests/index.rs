@@ -1,13 +1,32 @@
 use crate::error::JasonError;
-use crate::sources::InMemory;
+use crate::query::{Predicate, Query};
+use crate::sources::{InMemory, Source};
+use crate::util::ordering::OrderedValue;
 use crate::Database;
 
-use crate::tests::mock::Person;
+use crate::tests::mock::{composers_db, Person};
 
 use humphrey_json::Value;
 
 use std::collections::{BTreeSet, HashMap};
 
+/// Asserts that every secondary index contains each live offset exactly once, and no other offsets.
+fn assert_no_stale_offsets(database: &Database<Person, InMemory>) {
+    let mut live = database
+        .primary_indexes
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    live.sort_unstable();
+
+    for index in database.secondary_indexes.values() {
+        let mut offsets = index.values().flatten().cloned().collect::<Vec<_>>();
+        offsets.sort_unstable();
+
+        assert_eq!(offsets, live);
+    }
+}
+
 #[test]
 fn test_add_new() -> Result<(), JasonError> {
     let mut database: Database<Person, InMemory> = Database::new_in_memory()
@@ -130,3 +149,352 @@ fn test_update() -> Result<(), JasonError> {
 
     Ok(())
 }
+
+#[test]
+fn test_overwrite_same_value() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?
+        .with_index("name")?
+        .with_index("year_of_birth")?;
+
+    for _ in 0..3 {
+        database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+        database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    }
+
+    assert_no_stale_offsets(&database);
+
+    let bach = *database.primary_indexes.get("bach").unwrap();
+    let year_of_birth_index = database.secondary_indexes.get("year_of_birth").unwrap();
+
+    assert_eq!(
+        year_of_birth_index[&Value::Number(1685.0)],
+        [bach].into_iter().collect()
+    );
+
+    database.delete("bach")?;
+    assert_no_stale_offsets(&database);
+
+    Ok(())
+}
+
+#[test]
+fn test_overwrite_changed_value() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?.with_index("year_of_birth")?;
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1756))?;
+
+    let bach = *database.primary_indexes.get("bach").unwrap();
+    let mozart = *database.primary_indexes.get("mozart").unwrap();
+    let year_of_birth_index = database.secondary_indexes.get("year_of_birth").unwrap();
+
+    // The old bucket is removed since it is empty, and the new bucket contains both keys.
+    assert!(!year_of_birth_index.contains_key(&Value::Number(1685.0)));
+    assert_eq!(
+        year_of_birth_index[&Value::Number(1756.0)],
+        [bach, mozart].into_iter().collect()
+    );
+    assert!(database
+        .query_keys(query!(year_of_birth == 1685))?
+        .is_empty());
+    assert_no_stale_offsets(&database);
+
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1685))?;
+
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1685))?,
+        vec!["mozart"]
+    );
+    assert_eq!(
+        database.query_keys(query!(year_of_birth == 1756))?,
+        vec!["bach"]
+    );
+    assert_no_stale_offsets(&database);
+
+    Ok(())
+}
+
+#[test]
+fn test_keys_with_value() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    database.set("bach_2", Person::new("Johann Christian Bach", 1735))?;
+    database.set("wagner", Person::new("Richard Wagner", 1813))?;
+    database.set("verdi", Person::new("Giuseppe Verdi", 1813))?;
+
+    let expected = vec!["wagner".to_string(), "verdi".to_string()];
+
+    assert_eq!(
+        database.keys_with_value("year_of_birth", &Value::Number(1813.0))?,
+        expected
+    );
+    assert!(database
+        .keys_with_value("year_of_birth", &Value::Number(1900.0))?
+        .is_empty());
+
+    let mut database = database.with_index("year_of_birth")?;
+
+    assert_eq!(
+        database.keys_with_value("year_of_birth", &Value::Number(1813.0))?,
+        expected
+    );
+    assert!(database
+        .keys_with_value("year_of_birth", &Value::Number(1900.0))?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_exists_where() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    assert!(database.exists_where("year_of_birth", &Value::Number(1833.0))?);
+    assert!(!database.exists_where("year_of_birth", &Value::Number(1900.0))?);
+
+    let mut database = database.with_index("year_of_birth")?;
+
+    assert!(database.exists_where("year_of_birth", &Value::Number(1833.0))?);
+    assert!(!database.exists_where("year_of_birth", &Value::Number(1900.0))?);
+
+    // Deleting the only match removes its bucket from the index.
+    database.delete("brahms")?;
+    assert!(!database.exists_where("year_of_birth", &Value::Number(1833.0))?);
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_estimate() -> Result<(), JasonError> {
+    let database: Database<Person, InMemory> = Database::new_in_memory();
+    assert_eq!(database.index_memory_estimate(), 0);
+
+    let database = composers_db(InMemory::new())?;
+    let primary = database.index_memory_estimate();
+
+    // Each key takes at least its own length and its offset.
+    assert!(
+        primary
+            >= database
+                .primary_indexes
+                .keys()
+                .map(|k| k.len() + 8)
+                .sum::<usize>()
+    );
+
+    let database = database.with_index("name")?;
+    let secondary = database.index_memory_estimate() - primary;
+
+    // Each name is stored once as an indexed value, alongside an offset.
+    assert!(secondary >= database.primary_indexes.len() * 8 + "Johann Sebastian Bach".len());
+
+    Ok(())
+}
+
+#[test]
+fn test_strict() -> Result<(), JasonError> {
+    let database = composers_db(InMemory::new())?.with_index_strict("year_of_birth")?;
+    assert!(database.secondary_indexes.contains_key("year_of_birth"));
+
+    let result = composers_db(InMemory::new())?.with_index_strict("year_of_brith");
+    assert_eq!(result.err(), Some(JasonError::UnknownField));
+
+    let database: Database<Person, InMemory> =
+        Database::new_in_memory().with_index_strict("year_of_brith")?;
+    assert!(database.secondary_indexes.contains_key("year_of_brith"));
+
+    Ok(())
+}
+
+#[test]
+fn test_many() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    database.delete("mozart")?;
+
+    let mut chained = composers_db(InMemory::new())?
+        .with_index("name")?
+        .with_index("year_of_birth")?;
+    chained.delete("mozart")?;
+
+    let database = database.with_indexes(["name", "year_of_birth"])?;
+
+    assert_eq!(database.secondary_indexes.len(), 2);
+    assert_eq!(
+        database.secondary_indexes["name"],
+        chained.secondary_indexes["name"]
+    );
+    assert_eq!(
+        database.secondary_indexes["year_of_birth"],
+        chained.secondary_indexes["year_of_birth"]
+    );
+    assert_no_stale_offsets(&database);
+
+    let database = database.with_indexes(Vec::<String>::new())?;
+    assert_eq!(database.secondary_indexes.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_entry() -> Result<(), JasonError> {
+    let corrupt_db = || -> Result<Database<Person, InMemory>, JasonError> {
+        let mut source = InMemory::new();
+        source.write_entry(
+            "bach",
+            br#"{"name":"Johann Sebastian Bach","year_of_birth":1685}"#,
+        )?;
+        source.write_entry("mozart", b"not json")?;
+
+        Database::from_source(source)
+    };
+
+    let error = Some(JasonError::InvalidEntry {
+        key: "mozart".to_string(),
+    });
+
+    assert_eq!(corrupt_db()?.with_index("year_of_birth").err(), error);
+    assert_eq!(
+        corrupt_db()?.with_indexes(["name", "year_of_birth"]).err(),
+        error
+    );
+    assert_eq!(
+        corrupt_db()?
+            .with_computed_index("century", |_| Value::Null)
+            .err(),
+        error
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_computed() -> Result<(), JasonError> {
+    let century = |json: &Value| {
+        let year = json["year_of_birth"].as_number().unwrap_or(0.0);
+        Value::Number((year / 100.0).floor() + 1.0)
+    };
+
+    let mut database = composers_db(InMemory::new())?
+        .with_computed_index("century", century)?
+        .with_computed_index("lowercase_name", |json| {
+            Value::String(json["name"].as_str().unwrap_or("").to_lowercase())
+        })?;
+
+    assert_eq!(
+        database.keys_with_value("century", &Value::Number(19.0))?,
+        vec!["brahms", "saint_saens", "tchaikovsky"]
+    );
+
+    database.set("brahms", Person::new("Johannes Brahms", 1933))?;
+    database.delete("tchaikovsky")?;
+    database.set("bruckner", Person::new("Anton Bruckner", 1824))?;
+
+    assert_eq!(
+        database.query_keys(query!(century == 19))?,
+        vec!["saint_saens", "bruckner"]
+    );
+    assert_eq!(
+        database.query_keys(query!(century == 20) & query!(name == "Johannes Brahms"))?,
+        vec!["brahms"]
+    );
+    assert_eq!(
+        database.query_keys(query!(lowercase_name == "anton bruckner"))?,
+        vec!["bruckner"]
+    );
+
+    // Not every predicate is indexed, so the computed value is checked directly.
+    assert_eq!(
+        database.query_keys(query!(name == "Dmitri Shostakovich") | query!(century == 18))?,
+        vec!["mozart", "shostakovich"]
+    );
+
+    database.compact()?;
+
+    assert_eq!(
+        database.secondary_indexes["century"]
+            .values()
+            .map(|offsets| offsets.len())
+            .sum::<usize>(),
+        6
+    );
+
+    Ok(())
+}
+
+/// Asserts that every sorted index holds exactly the distinct values of its secondary index, in order.
+fn assert_sorted_consistent(database: &Database<Person, InMemory>) {
+    for (field, sorted) in &database.sorted_indexes {
+        let values = sorted.iter().map(|v| v.0.clone()).collect::<Vec<_>>();
+        let mut expected = database.secondary_indexes[field]
+            .keys()
+            .cloned()
+            .map(OrderedValue)
+            .collect::<Vec<_>>();
+        expected.sort();
+
+        assert_eq!(
+            values,
+            expected.into_iter().map(|v| v.0).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn test_sorted() -> Result<(), JasonError> {
+    let mut unindexed = composers_db(InMemory::new())?;
+    let mut database = composers_db(InMemory::new())?
+        .with_sorted_index("year_of_birth")?
+        .with_sorted_index("name")?;
+
+    let queries = || {
+        vec![
+            query!(year_of_birth > 1835),
+            query!(year_of_birth >= 1835),
+            query!(year_of_birth < 1833),
+            query!(year_of_birth <= 1833),
+            query!(year_of_birth in 1800..1900),
+            Query::from(Predicate::Between(
+                "year_of_birth".to_string(),
+                1900.0,
+                1800.0,
+            )) | query!(year_of_birth == 1685),
+            query!(year_of_birth > 0) & query!(name < "P"),
+            query!(name >= "J") & query!(name <= "Johannes Brahms"),
+            query!(name > 0) | query!(year_of_birth < "Z"),
+        ]
+    };
+
+    for (indexed, unindexed_query) in queries().into_iter().zip(queries()) {
+        let mut keys = database.query_keys(indexed)?;
+        let mut expected = unindexed.query_keys(unindexed_query)?;
+        keys.sort();
+        expected.sort();
+
+        assert_eq!(keys, expected);
+    }
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    database.set("elgar", Person::new("Edward Elgar", 1857))?;
+    database.delete("shostakovich")?;
+    database.remove("mozart")?;
+    assert_sorted_consistent(&database);
+
+    let mut keys = database.query_keys(query!(year_of_birth in 1700..1860))?;
+    keys.sort();
+    assert_eq!(
+        keys,
+        vec!["bach", "brahms", "elgar", "saint_saens", "tchaikovsky"]
+    );
+
+    database.compact()?;
+    assert_sorted_consistent(&database);
+    assert_eq!(
+        database.query_keys(query!(year_of_birth > 1850))?,
+        vec!["elgar"]
+    );
+
+    // Indexing the field again replaces the sorted index with an unsorted one.
+    let database = database.with_index("name")?;
+    assert!(!database.sorted_indexes.contains_key("name"));
+    assert!(database.sorted_indexes.contains_key("year_of_birth"));
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use crate::error::JasonError;
+use crate::interop::{from_serde, to_serde, SerdeValue};
+use crate::sources::InMemory;
+use crate::Database;
+
+use crate::tests::mock::{composers_db, Person};
+
+use humphrey_json::Value;
+use serde_json::json;
+
+#[test]
+fn conversion() {
+    let serde_value = json!({
+        "name": "Johann Sebastian Bach",
+        "year_of_birth": 1685,
+        "height": 1.7,
+        "alive": false,
+        "works": ["Mass in B minor", null]
+    });
+
+    let value = from_serde(&serde_value);
+
+    assert_eq!(
+        value,
+        Value::Object(vec![
+            ("alive".into(), Value::Bool(false)),
+            ("height".into(), Value::Number(1.7)),
+            ("name".into(), Value::String("Johann Sebastian Bach".into())),
+            (
+                "works".into(),
+                Value::Array(vec![Value::String("Mass in B minor".into()), Value::Null])
+            ),
+            ("year_of_birth".into(), Value::Number(1685.0)),
+        ])
+    );
+
+    assert_eq!(to_serde(&value), serde_value);
+    assert_eq!(to_serde(&Value::Number(f64::NAN)), serde_json::Value::Null);
+    assert!(to_serde(&Value::Number(1685.0)).is_i64());
+}
+
+#[test]
+fn query() -> Result<(), JasonError> {
+    let mut db = composers_db(InMemory::new())?;
+
+    let year_of_birth = SerdeValue(json!(1685));
+    let results = db
+        .query(query!(year_of_birth == year_of_birth))?
+        .collect::<Result<Vec<_>, JasonError>>()?;
+
+    assert_eq!(
+        results,
+        vec![(
+            "bach".to_string(),
+            Person::new("Johann Sebastian Bach", 1685)
+        )]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn database() -> Result<(), JasonError> {
+    let mut db: Database<SerdeValue, InMemory> = Database::new_in_memory();
+
+    db.set(
+        "bach",
+        SerdeValue(json!({ "name": "Johann Sebastian Bach", "year_of_birth": 1685 })),
+    )?;
+    db.set(
+        "mozart",
+        SerdeValue(json!({ "name": "Wolfgang Amadeus Mozart", "year_of_birth": 1756 })),
+    )?;
+
+    let name = SerdeValue(json!("Wolfgang Amadeus Mozart"));
+    let results = db
+        .query(query!(name == name))?
+        .collect::<Result<Vec<_>, JasonError>>()?;
+
+    let mozart: serde_json::Value = results[0].1.clone().into();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(mozart["year_of_birth"], json!(1756));
+    assert_eq!(
+        serde_json::Value::from(db.get("bach")?),
+        json!({ "name": "Johann Sebastian Bach", "year_of_birth": 1685 })
+    );
+
+    Ok(())
+}
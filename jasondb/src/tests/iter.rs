@@ -1,6 +1,11 @@
+use crate::cursor::Cursor;
 use crate::error::JasonError;
-use crate::sources::InMemory;
+use crate::iter::TryIterator;
+use crate::sources::{FileSource, InMemory};
+use crate::tests::mock::Person;
+
 use crate::tests::mock::composers_db;
+use std::fs;
 
 #[test]
 fn iter_ordered() -> Result<(), JasonError> {
@@ -17,3 +22,165 @@ fn iter_ordered() -> Result<(), JasonError> {
 
     Ok(())
 }
+
+#[test]
+fn iter_by() -> Result<(), JasonError> {
+    let mut db = composers_db(InMemory::new())?.with_index("name")?;
+    let mut iter = db.iter_by("name")?.flatten().map(|(k, _)| k);
+
+    assert_eq!(iter.next(), Some("saint_saens".to_string()));
+    assert_eq!(iter.next(), Some("shostakovich".to_string()));
+    assert_eq!(iter.next(), Some("bach".to_string()));
+    assert_eq!(iter.next(), Some("brahms".to_string()));
+    assert_eq!(iter.next(), Some("tchaikovsky".to_string()));
+    assert_eq!(iter.next(), Some("mozart".to_string()));
+    assert_eq!(iter.next(), None);
+
+    assert!(db.iter_by("year_of_birth").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn into_map() -> Result<(), JasonError> {
+    let mut db = composers_db(InMemory::new())?;
+    let map = db.query(query!(year_of_birth < 1800))?.into_map()?;
+
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["bach"], Person::new("Johann Sebastian Bach", 1685));
+    assert_eq!(map["mozart"], Person::new("Wolfgang Amadeus Mozart", 1756));
+
+    db.set_raw("brahms", br#"{"name":"Johannes Brahms"}"#)?;
+    assert!(db.iter().into_map().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn adapters() -> Result<(), JasonError> {
+    let mut db = composers_db(InMemory::new())?;
+
+    let names = db
+        .iter()
+        .filter_ok(|(_, person)| (1800..1900).contains(&person.year_of_birth))
+        .map_ok(|(_, person)| person.name)
+        .collect::<Result<Vec<_>, JasonError>>()?;
+
+    assert_eq!(
+        names,
+        vec![
+            "Johannes Brahms",
+            "Camille Saint-Saëns",
+            "Pyotr Ilyich Tchaikovsky"
+        ]
+    );
+
+    // Errors are passed through rather than filtered out.
+    db.set_raw("brahms", br#"{"name":"Johannes Brahms"}"#)?;
+    let results = db
+        .iter()
+        .filter_ok(|_| false)
+        .map_ok(|(k, _)| k)
+        .collect::<Vec<_>>();
+
+    assert_eq!(results, vec![Err(JasonError::JsonError)]);
+
+    Ok(())
+}
+
+#[test]
+fn iter_ref() -> Result<(), JasonError> {
+    let mut db = composers_db(InMemory::new())?;
+    db.delete("mozart")?;
+
+    let mut expected = db.iter().collect::<Result<Vec<_>, JasonError>>()?;
+
+    // Two iterators can exist at once, since neither needs exclusive access.
+    let (mut first, second) = (db.iter_ref(), db.iter_ref().rev());
+    assert_eq!(first.len(), 5);
+    assert_eq!(first.next().transpose()?, Some(expected[0].clone()));
+    assert_eq!(second.collect::<Result<Vec<_>, JasonError>>()?.len(), 5);
+
+    let source = FileSource::create("test_iter_ref.jdb")?.with_write_buffer(1024 * 1024);
+    let mut file_db = composers_db(source)?;
+    file_db.delete("mozart")?;
+
+    // Some entries have been written to the file and the rest are still buffered.
+    file_db.get("bach")?;
+    file_db.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+
+    let actual = file_db.iter_ref().collect::<Result<Vec<_>, JasonError>>()?;
+    expected.rotate_left(1);
+    assert_eq!(actual, expected);
+
+    // Positioned reads must not disturb the sequential reads which go through the source.
+    assert_eq!(
+        file_db.iter().collect::<Result<Vec<_>, JasonError>>()?,
+        actual
+    );
+
+    drop(file_db);
+    fs::remove_file("test_iter_ref.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn iter_sequential() -> Result<(), JasonError> {
+    let mut db = composers_db(FileSource::create("test_iter_sequential.jdb")?)?;
+    db.set("bach", Person::new("Johann Christian Bach", 1735))?;
+    db.delete("brahms")?;
+
+    let entries = db.iter_sequential().collect::<Result<Vec<_>, _>>()?;
+    let keys = entries.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+
+    assert_eq!(
+        keys,
+        vec![
+            "mozart",
+            "saint_saens",
+            "tchaikovsky",
+            "shostakovich",
+            "bach"
+        ]
+    );
+    assert_eq!(entries[4].1.name, "Johann Christian Bach");
+
+    fs::remove_file("test_iter_sequential.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn iter_from() -> Result<(), JasonError> {
+    let mut db = composers_db(InMemory::new())?;
+
+    let (iter, cursor) = db.iter_from(None);
+    assert_eq!(iter.len(), 6);
+
+    let cursor = cursor.unwrap();
+    let (iter, next_cursor) = db.iter_from(Some(cursor));
+    assert_eq!(iter.len(), 0);
+    assert_eq!(next_cursor, None);
+
+    // Updated values move after the cursor, and deleted values are not returned.
+    db.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    db.set("elgar", Person::new("Edward Elgar", 1857))?;
+    db.delete("mozart")?;
+
+    let cursor: Cursor = cursor.to_string().parse()?;
+    let (iter, next_cursor) = db.iter_from(Some(cursor));
+    let keys = iter.map_ok(|(k, _)| k).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec!["bach", "elgar"]);
+
+    let (iter, _) = db.iter_from(next_cursor);
+    assert_eq!(iter.len(), 0);
+
+    assert_eq!("nonsense".parse::<Cursor>(), Err(JasonError::InvalidCursor));
+    assert_eq!(
+        "+000000000000000".parse::<Cursor>(),
+        Err(JasonError::InvalidCursor)
+    );
+
+    Ok(())
+}
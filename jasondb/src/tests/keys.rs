@@ -0,0 +1,44 @@
+use crate::error::JasonError;
+use crate::keys;
+use crate::sources::InMemory;
+
+use crate::tests::mock::{composers_db, Person};
+
+#[test]
+fn encoding() {
+    assert_eq!(keys::encode(&[0xde, 0xad, 0xbe, 0xef]), "deadbeef");
+    assert_eq!(keys::encode(&[0x00, 0x0f, 0xff]), "000fff");
+    assert_eq!(keys::encode(&[]), "");
+
+    assert_eq!(keys::decode("deadbeef"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+    assert_eq!(keys::decode("DEADBEEF"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+    assert_eq!(keys::decode(""), Some(vec![]));
+    assert_eq!(keys::decode("abc"), None);
+    assert_eq!(keys::decode("bach"), None);
+
+    let all_bytes = (0..=255).collect::<Vec<u8>>();
+    assert_eq!(keys::decode(&keys::encode(&all_bytes)), Some(all_bytes));
+}
+
+#[test]
+fn bytes_keys() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    let key = [0xff, 0x00, 0xc3, 0x28];
+
+    database.set_bytes_key(&key, Person::new("Edward Elgar", 1857))?;
+    assert_eq!(database.get_bytes_key(&key)?.name, "Edward Elgar");
+    assert_eq!(database.get("ff00c328")?.name, "Edward Elgar");
+
+    let decoded = database
+        .query_keys(query!(year_of_birth == 1857))?
+        .iter()
+        .map(|k| keys::decode(k))
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, vec![Some(key.to_vec())]);
+
+    database.delete_bytes_key(&key)?;
+    assert_eq!(database.get_bytes_key(&key), Err(JasonError::InvalidKey));
+    assert_eq!(database.len(), 6);
+
+    Ok(())
+}
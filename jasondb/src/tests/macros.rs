@@ -2,23 +2,51 @@ use crate::query::{Predicate, PredicateCombination, Query};
 
 use humphrey_json::Value;
 
+use std::ops::Bound;
+
 #[test]
 fn simple_queries() {
     let lt = query!(a < 1);
     let lte = query!(a.b <= 2);
     let gt = query!(a > 1.0);
     let gte = query!(a.b >= 2.0);
+    let between = query!(a in 1..=2);
+    let range = query!(a in 1..2);
+    let range_from = query!(a in 1..);
+    let range_to = query!(a in ..2);
+    let range_to_inclusive = query!(a.b in ..=2);
     let eq_num = query!(a == 1);
     let eq_str = query!(a == "hello");
     let eq_bool = query!(a == true);
     let eq_bool_2 = query!(a);
     let eq_null = query!(a == null);
     let eq_var = query!(a == f64::MAX);
+    let before = query!(a before "2023-01-01");
+    let after = query!(a.b after "2023-01-01");
+    let contains = query!(a ~ "ell");
+    let contains_nested = query!(a.b ~ "ell");
 
     assert_eq!(lt, Query::from(Predicate::Lt("a".to_string(), 1.0)));
     assert_eq!(lte, Query::from(Predicate::Lte("a.b".to_string(), 2.0)));
     assert_eq!(gt, Query::from(Predicate::Gt("a".to_string(), 1.0)));
     assert_eq!(gte, Query::from(Predicate::Gte("a.b".to_string(), 2.0)));
+    assert_eq!(
+        between,
+        Query::from(Predicate::Between("a".to_string(), 1.0, 2.0))
+    );
+    assert_eq!(
+        range,
+        Query::from(Predicate::Range("a".to_string(), 1.0, 2.0))
+    );
+    assert_eq!(
+        range_from,
+        Query::from(Predicate::Gte("a".to_string(), 1.0))
+    );
+    assert_eq!(range_to, Query::from(Predicate::Lt("a".to_string(), 2.0)));
+    assert_eq!(
+        range_to_inclusive,
+        Query::from(Predicate::Lte("a.b".to_string(), 2.0))
+    );
     assert_eq!(
         eq_num,
         Query::from(Predicate::Eq("a".to_string(), Value::Number(1.0)))
@@ -46,6 +74,25 @@ fn simple_queries() {
         eq_var,
         Query::from(Predicate::Eq("a".to_string(), Value::Number(f64::MAX)))
     );
+    assert_eq!(
+        before,
+        Query::from(Predicate::Before("a".to_string(), "2023-01-01".to_string()))
+    );
+    assert_eq!(
+        after,
+        Query::from(Predicate::After(
+            "a.b".to_string(),
+            "2023-01-01".to_string()
+        ))
+    );
+    assert_eq!(
+        contains,
+        Query::from(Predicate::Contains("a".to_string(), "ell".to_string()))
+    );
+    assert_eq!(
+        contains_nested,
+        Query::from(Predicate::Contains("a.b".to_string(), "ell".to_string()))
+    );
 }
 
 #[test]
@@ -75,3 +122,72 @@ fn compound_queries() {
         }
     );
 }
+
+#[test]
+fn excluded_lower_bound() {
+    let range = Query::range("a", (Bound::Excluded(1), Bound::Excluded(2)));
+    let range_from = Query::range("a", (Bound::Excluded(1), Bound::Unbounded::<i32>));
+
+    assert_eq!(
+        range,
+        Query {
+            predicates: vec![
+                Predicate::Gt("a".to_string(), 1.0),
+                Predicate::Lt("a".to_string(), 2.0),
+            ],
+            predicate_combination: PredicateCombination::And
+        }
+    );
+    assert_eq!(range_from, Query::from(Predicate::Gt("a".to_string(), 1.0)));
+}
+
+#[test]
+fn value_queries() {
+    let value = Value::Array(vec![Value::Number(1.0), Value::String("two".to_string())]);
+
+    let eq = query!(a == value.clone());
+    let ne = query!(a.b != value.clone());
+    let eq_value = Query::eq_value(format!("a.{}", "b"), value.clone());
+
+    assert_eq!(
+        eq,
+        Query::from(Predicate::Eq("a".to_string(), value.clone()))
+    );
+    assert_eq!(
+        ne,
+        Query::from(Predicate::Ne("a.b".to_string(), value.clone()))
+    );
+    assert_eq!(
+        eq_value,
+        Query::from(Predicate::Eq("a.b".to_string(), value))
+    );
+    assert_eq!(
+        Query::eq_value("a", "hello"),
+        Query::from(Predicate::Eq(
+            "a".to_string(),
+            Value::String("hello".to_string())
+        ))
+    );
+}
+
+#[test]
+fn string_ordering_queries() {
+    let name = String::from("M");
+
+    assert_eq!(
+        query!(a > "M"),
+        Query::from(Predicate::GtStr("a".to_string(), "M".to_string()))
+    );
+    assert_eq!(
+        query!(a.b >= &name),
+        Query::from(Predicate::GteStr("a.b".to_string(), "M".to_string()))
+    );
+    assert_eq!(
+        query!(a < name),
+        Query::from(Predicate::LtStr("a".to_string(), "M".to_string()))
+    );
+    assert_eq!(
+        query!(a <= "M"),
+        Query::from(Predicate::LteStr("a".to_string(), "M".to_string()))
+    );
+}
@@ -3,6 +3,7 @@ use crate::sources::Source;
 use crate::Database;
 
 use humphrey_json::prelude::*;
+use humphrey_json::Value;
 
 #[derive(FromJson, IntoJson, Clone, Debug, PartialEq, Eq)]
 pub struct Person {
@@ -16,6 +17,22 @@ pub struct AgedPerson {
     pub(crate) age: u16,
 }
 
+/// A document which keeps its JSON as it was written, so fields can be missing rather than `null`.
+#[derive(Debug, PartialEq)]
+pub struct Document(pub Value);
+
+impl IntoJson for Document {
+    fn to_json(&self) -> Value {
+        self.0.clone()
+    }
+}
+
+impl FromJson for Document {
+    fn from_json(value: &Value) -> Result<Self, humphrey_json::error::ParseError> {
+        Ok(Self(value.clone()))
+    }
+}
+
 impl Person {
     pub fn new(name: impl AsRef<str>, year_of_birth: u16) -> Person {
         Person {
@@ -56,3 +73,30 @@ where
 
     Ok(database)
 }
+
+/// Builds a tar archive containing the given files, like a database from the JasonDB server.
+pub fn tar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+
+    for (name, contents) in files {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        header[148..156].copy_from_slice(b"        ");
+        let checksum = header.iter().map(|&b| b as u32).sum::<u32>();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+    }
+
+    archive.resize(archive.len() + 1024, 0);
+
+    archive
+}
@@ -1,10 +1,24 @@
+mod batch;
+mod collection;
+mod convert;
+mod dedup;
+mod entry;
 mod file;
 mod in_memory;
 mod index;
+#[cfg(feature = "serde-interop")]
+mod interop;
 mod iter;
+mod keys;
 mod macros;
 mod null;
+mod ordering;
+#[allow(clippy::manual_is_multiple_of)]
 mod query;
 mod replica;
+mod schedule;
+mod source;
+mod spilled;
+mod timestamp;
 
 mod mock;
@@ -2,6 +2,8 @@ use crate::error::JasonError;
 use crate::sources::InMemory;
 use crate::Database;
 
+use crate::tests::mock::{Document, Person};
+
 use humphrey_json::prelude::*;
 
 #[derive(FromJson, IntoJson, Debug, PartialEq, Eq, Clone)]
@@ -130,3 +132,69 @@ fn nested_nullable_query() -> Result<(), Box<JasonError>> {
 
     Ok(())
 }
+
+#[test]
+fn top_level_nullable() -> Result<(), Box<JasonError>> {
+    let mut db: Database<Option<Person>, InMemory> = Database::new_in_memory();
+
+    db.set("some", Some(Person::new("Johann Sebastian Bach", 1685)))?;
+    db.set("none", None)?;
+    db.set("deleted", None)?;
+    db.delete("deleted")?;
+
+    assert_eq!(db.len(), 2);
+    assert_eq!(
+        db.get("some")?,
+        Some(Person::new("Johann Sebastian Bach", 1685))
+    );
+    assert_eq!(db.get("none")?, None);
+    assert_eq!(db.get("deleted"), Err(JasonError::InvalidKey));
+
+    let mut db: Database<Option<Person>, InMemory> = Database::from_source(db.source)?;
+
+    assert_eq!(db.len(), 2);
+    assert_eq!(db.get("none")?, None);
+    assert_eq!(db.get("deleted"), Err(JasonError::InvalidKey));
+
+    Ok(())
+}
+
+#[test]
+fn exists_and_missing() -> Result<(), Box<JasonError>> {
+    let mut db: Database<Document, InMemory> = Database::new_in_memory();
+
+    for (key, json) in [
+        (
+            "present",
+            json!({ "field": "a", "nested": { "field": null } }),
+        ),
+        ("null", json!({ "field": null, "nested": {} })),
+        ("missing", json!({})),
+    ] {
+        db.set(key, Document(json))?;
+    }
+
+    for indexed in [false, true] {
+        if indexed {
+            db = db.with_index("field")?;
+        }
+
+        let mut keys = |query| -> Result<Vec<String>, Box<JasonError>> {
+            let mut keys = db.query_keys(query)?;
+            keys.sort();
+            Ok(keys)
+        };
+
+        assert_eq!(keys(query!(field exists))?, vec!["null", "present"]);
+        assert_eq!(keys(query!(field missing))?, vec!["missing"]);
+        assert_eq!(keys(query!(field == null))?, vec!["missing", "null"]);
+        assert_eq!(keys(query!(nested.field exists))?, vec!["present"]);
+        assert_eq!(keys(query!(nested.field missing))?, vec!["missing", "null"]);
+        assert_eq!(
+            keys(query!(field exists) & query!(field == null))?,
+            vec!["null"]
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use crate::util::ordering::OrderedValue;
+
+use humphrey_json::prelude::*;
+use humphrey_json::Value;
+
+use std::collections::BTreeMap;
+
+/// Asserts that the values are in strictly ascending order, checking every pair in both directions.
+fn assert_ascending(values: Vec<Value>) {
+    let values = values.into_iter().map(OrderedValue).collect::<Vec<_>>();
+
+    for (i, a) in values.iter().enumerate() {
+        for (j, b) in values.iter().enumerate() {
+            assert_eq!(a.cmp(b), i.cmp(&j), "{:?} compared to {:?}", a.0, b.0);
+        }
+    }
+}
+
+#[test]
+fn cross_type() {
+    assert_ascending(vec![
+        Value::Null,
+        json!(false),
+        json!(true),
+        json!(-1000),
+        json!(0),
+        json!(1000),
+        json!(""),
+        json!("a"),
+        json!([]),
+        json!([null]),
+        json!({}),
+        json!({"a": null}),
+    ]);
+
+    // The type takes precedence over the contents.
+    assert!(OrderedValue(json!(true)) < OrderedValue(json!(-1)));
+    assert!(OrderedValue(json!(1e300)) < OrderedValue(json!("")));
+    assert!(OrderedValue(json!("zzz")) < OrderedValue(json!([])));
+    assert!(OrderedValue(json!([{"a": 1}])) < OrderedValue(json!({})));
+}
+
+#[test]
+fn numbers() {
+    assert_ascending(vec![
+        json!(-1e10),
+        json!(-2.5),
+        json!(-2),
+        json!(-0.5),
+        json!(0),
+        json!(0.1),
+        json!(1),
+        json!(1.5),
+        json!(10),
+        json!(1e10),
+    ]);
+
+    assert_eq!(OrderedValue(json!(2)), OrderedValue(json!(2.0)));
+    assert!(OrderedValue(json!(-0.0)) < OrderedValue(json!(0.0)));
+}
+
+#[test]
+fn strings() {
+    assert_ascending(vec![
+        json!(""),
+        json!("A"),
+        json!("B"),
+        json!("a"),
+        json!("aa"),
+        json!("ab"),
+        json!("b"),
+        json!("é"),
+    ]);
+}
+
+#[test]
+fn arrays() {
+    assert_ascending(vec![
+        json!([]),
+        json!([null]),
+        json!([false]),
+        json!([1]),
+        json!([1, 1]),
+        json!([1, 2]),
+        json!([1, "a"]),
+        json!([2]),
+        json!(["a"]),
+        json!([[]]),
+        json!([[1]]),
+        json!([{}]),
+    ]);
+}
+
+#[test]
+fn objects() {
+    assert_ascending(vec![
+        json!({}),
+        json!({"a": null}),
+        json!({"a": 1}),
+        json!({"a": 1, "b": 1}),
+        json!({"a": 2}),
+        json!({"a": "x"}),
+        json!({"a": {"x": 1}}),
+        json!({"b": null}),
+    ]);
+
+    // Members are compared in the order in which they are stored.
+    assert_ne!(
+        OrderedValue(json!({"a": 1, "b": 2})),
+        OrderedValue(json!({"b": 2, "a": 1}))
+    );
+}
+
+#[test]
+fn btree_map() {
+    let mut map = BTreeMap::new();
+
+    for value in [json!("b"), json!(3), Value::Null, json!("a"), json!(true)] {
+        map.insert(OrderedValue::from(value), ());
+    }
+
+    map.insert(OrderedValue(json!(3.0)), ());
+
+    let keys = map.into_keys().map(Value::from).collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        vec![Value::Null, json!(true), json!(3), json!("a"), json!("b")]
+    );
+}
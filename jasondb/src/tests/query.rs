@@ -7,6 +7,10 @@ fn test_queries() {
     let query_3 = query!(c == false); // c == false
     let query_4 = query!(d == "hello"); // d == "hello"
     let query_5 = query!(d != "hello"); // d != "hello"
+    let query_6 = query!(b.c in 1..=2); // 1 <= b.c <= 2
+    let query_7 = query!(b.c in 1..2); // 1 <= b.c < 2
+    let query_8 = query!(d ~ "bye"); // d contains "bye"
+    let query_9 = query!(a ~ "1"); // a contains "1", which never matches as a is a number
 
     let compound_query_1 = query!(a < 1) & query!(c); // a < 1 && c
     let compound_query_2 = query!(a < 1) | query!(c); // a < 1 || c
@@ -70,6 +74,22 @@ fn test_queries() {
     assert!(!query_5.matches(&testcase_2).unwrap());
     assert!(query_5.matches(&testcase_3).unwrap());
 
+    assert!(query_6.matches(&testcase_1).unwrap());
+    assert!(query_6.matches(&testcase_2).unwrap());
+    assert!(!query_6.matches(&testcase_3).unwrap());
+
+    assert!(query_7.matches(&testcase_1).unwrap());
+    assert!(!query_7.matches(&testcase_2).unwrap());
+    assert!(!query_7.matches(&testcase_3).unwrap());
+
+    assert!(query_8.matches(&testcase_1).unwrap());
+    assert!(!query_8.matches(&testcase_2).unwrap());
+    assert!(query_8.matches(&testcase_3).unwrap());
+
+    assert!(!query_9.matches(&testcase_1).unwrap());
+    assert!(!query_9.matches(&testcase_2).unwrap());
+    assert!(!query_9.matches(&testcase_3).unwrap());
+
     assert!(compound_query_1.matches(&testcase_1).unwrap());
     assert!(!compound_query_1.matches(&testcase_2).unwrap());
     assert!(!compound_query_1.matches(&testcase_3).unwrap());
@@ -86,3 +106,31 @@ fn test_queries() {
     assert!(!closure_query_2.matches(&testcase_2).unwrap());
     assert!(closure_query_2.matches(&testcase_3).unwrap());
 }
+
+#[test]
+fn timestamp_queries() {
+    let before = query!(created_at before "2023-01-01");
+    let after = query!(created_at after "2023-01-01T12:00:00+01:00");
+
+    let testcase_1 = json!({ "created_at": "2022-12-31T23:59:59.999Z" });
+    let testcase_2 = json!({ "created_at": "2023-01-01T11:30:00Z" });
+    let testcase_3 = json!({ "created_at": "2023-01-01T11:30:00-01:00" });
+    let testcase_4 = json!({ "created_at": "yesterday" });
+    let testcase_5 = json!({ "created_at": 1672531200 });
+
+    assert!(before.matches(&testcase_1).unwrap());
+    assert!(!before.matches(&testcase_2).unwrap());
+    assert!(!before.matches(&testcase_3).unwrap());
+    assert!(!before.matches(&testcase_4).unwrap());
+    assert!(!before.matches(&testcase_5).unwrap());
+
+    assert!(!after.matches(&testcase_1).unwrap());
+    assert!(after.matches(&testcase_2).unwrap());
+    assert!(after.matches(&testcase_3).unwrap());
+    assert!(!after.matches(&testcase_4).unwrap());
+    assert!(!after.matches(&testcase_5).unwrap());
+
+    assert!(!query!(created_at before "not a date")
+        .matches(&testcase_1)
+        .unwrap());
+}
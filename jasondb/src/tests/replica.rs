@@ -1,13 +1,59 @@
 use crate::error::JasonError;
-use crate::replica::Replica;
-use crate::sources::InMemory;
+use crate::replica::{Replica, TypedReplica};
+use crate::sources::{InMemory, Source};
 use crate::Database;
 
-use crate::tests::mock::Person;
+use crate::tests::mock::{composers_db, Person};
 
 use std::fs;
 use std::sync::mpsc::{channel, Sender};
 
+struct ChannelReplica(Sender<(String, String)>);
+
+impl<T> Replica<T> for ChannelReplica
+where
+    T: Send + 'static,
+{
+    fn set(&mut self, key: &str, value: &str) -> Result<(), JasonError> {
+        self.0
+            .send((key.to_string(), value.to_string()))
+            .map_err(|_| JasonError::Io)
+    }
+}
+
+/// A replica which fails on every write after the first.
+struct FailingReplica(bool);
+
+impl<T> Replica<T> for FailingReplica
+where
+    T: Send + 'static,
+{
+    fn set(&mut self, _: &str, _: &str) -> Result<(), JasonError> {
+        if self.0 {
+            Err(JasonError::Io)
+        } else {
+            self.0 = true;
+            Ok(())
+        }
+    }
+}
+
+struct TypedChannelReplica(Sender<(String, Option<Person>)>);
+
+impl TypedReplica<Person> for TypedChannelReplica {
+    fn set_typed(&mut self, key: &str, value: &Person) -> Result<(), JasonError> {
+        self.0
+            .send((key.to_string(), Some(value.clone())))
+            .map_err(|_| JasonError::Io)
+    }
+
+    fn delete_typed(&mut self, key: &str) -> Result<(), JasonError> {
+        self.0
+            .send((key.to_string(), None))
+            .map_err(|_| JasonError::Io)
+    }
+}
+
 #[test]
 fn sync_replica() -> Result<(), JasonError> {
     let mut database: Database<Person, InMemory> = Database::new_in_memory()
@@ -75,20 +121,38 @@ fn async_replica() -> Result<(), JasonError> {
 }
 
 #[test]
-fn arbitrary_replica() -> Result<(), JasonError> {
-    struct ChannelReplica(Sender<(String, String)>);
-
-    impl<T> Replica<T> for ChannelReplica
-    where
-        T: Send + 'static,
-    {
-        fn set(&mut self, key: &str, value: &str) -> Result<(), JasonError> {
-            self.0
-                .send((key.to_string(), value.to_string()))
-                .map_err(|_| JasonError::Io)
-        }
-    }
+fn checkpoint() -> Result<(), JasonError> {
+    let mut database: Database<Person, InMemory> = Database::new_in_memory()
+        .with_async_replica(Database::create("test_checkpoint_replica.jdb")?);
+
+    let person_1 = Person::new("Elizabeth II", 1926);
+    let person_2 = Person::new("George VI", 1895);
+
+    database.set("queen_elizabeth_ii", &person_1)?;
+    database.set("king_george_vi", &person_2)?;
+    database.delete("queen_elizabeth_ii")?;
+
+    let offset = database.checkpoint()?;
+    assert_eq!(offset, database.source.size());
+
+    // The asynchronous replica must have caught up by the time the checkpoint returns.
+    let mut replica: Database<Person> = Database::open_read_only("test_checkpoint_replica.jdb")?;
+
+    assert_eq!(replica.len(), 1);
+    assert_eq!(replica.get("king_george_vi"), Ok(person_2));
+    assert!(replica.get("queen_elizabeth_ii").is_err());
+    assert_eq!(replica.checkpoint()?, replica.source.size());
 
+    drop(database);
+    drop(replica);
+
+    fs::remove_file("test_checkpoint_replica.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn arbitrary_replica() -> Result<(), JasonError> {
     let (tx_1, rx_1) = channel();
     let (tx_2, rx_2) = channel();
 
@@ -145,3 +209,189 @@ fn arbitrary_replica() -> Result<(), JasonError> {
 
     Ok(())
 }
+
+#[test]
+fn incremental_replica() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+    database.delete("mozart")?;
+
+    let mut replica: Database<Person, InMemory> = Database::new_in_memory();
+    let high_water = database.replicate_from(&mut replica, 0)?;
+
+    assert_eq!(replica.len(), 5);
+    assert_eq!(replica.get("bach"), database.get("bach"));
+    assert!(replica.get("mozart").is_err());
+
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    database.delete("bach")?;
+
+    let (tx, rx) = channel();
+    let mut channel_replica = ChannelReplica(tx);
+    let new_high_water = database.replicate_from(&mut channel_replica, high_water)?;
+
+    assert!(new_high_water > high_water);
+    assert_eq!(
+        rx.try_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+        vec!["mozart".to_string(), "bach".to_string()]
+    );
+
+    database.replicate_from(&mut replica, high_water)?;
+
+    assert_eq!(replica.len(), 5);
+    assert_eq!(replica.get("mozart"), database.get("mozart"));
+    assert!(replica.get("bach").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn bootstrapped_replicas() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?
+        .with_replica_from(Database::create("test_bootstrapped_sync_replica.jdb")?, 0)?
+        .with_async_replica_from(Database::create("test_bootstrapped_async_replica.jdb")?, 0)?;
+
+    database.set("bach", Person::new("Johann Christian Bach", 1735))?;
+
+    drop(database);
+
+    for path in [
+        "test_bootstrapped_sync_replica.jdb",
+        "test_bootstrapped_async_replica.jdb",
+    ] {
+        let mut replica: Database<Person> = Database::open(path)?;
+
+        assert_eq!(replica.len(), 6);
+        assert_eq!(replica.get("bach")?.name, "Johann Christian Bach");
+        assert_eq!(replica.get("mozart")?.name, "Wolfgang Amadeus Mozart");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn typed_replica() -> Result<(), JasonError> {
+    let (tx, rx) = channel();
+
+    let mut database: Database<Person, InMemory> = Database::new_in_memory()
+        .with_typed_replica(TypedChannelReplica(tx))
+        .with_typed_replica(
+            Database::create("test_typed_replica.jdb")?.with_index("year_of_birth")?,
+        );
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    database.delete("bach")?;
+    database.checkpoint()?;
+
+    assert_eq!(
+        rx.try_iter().collect::<Vec<_>>(),
+        vec![
+            (
+                "bach".to_string(),
+                Some(Person::new("Johann Sebastian Bach", 1685))
+            ),
+            (
+                "mozart".to_string(),
+                Some(Person::new("Wolfgang Amadeus Mozart", 1756))
+            ),
+            ("bach".to_string(), None),
+        ]
+    );
+
+    drop(database);
+
+    let mut replica: Database<Person> = Database::open("test_typed_replica.jdb")?;
+
+    assert_eq!(replica.len(), 1);
+    assert_eq!(replica.get("mozart")?.year_of_birth, 1756);
+    assert!(replica.get("bach").is_err());
+
+    fs::remove_file("test_typed_replica.jdb").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn named_async_replicas() -> Result<(), JasonError> {
+    let (tx, rx) = channel();
+
+    let mut database: Database<Person, InMemory> = Database::new_in_memory()
+        .with_async_replica_named("failing", FailingReplica(false))
+        .with_async_replica_named("channel", ChannelReplica(tx));
+
+    let failed = JasonError::NamedReplicaError("failing".to_string());
+
+    // Depending on when the failing replica's thread stops, writes may already report its failure.
+    for (key, year_of_birth) in [("bach", 1685), ("mozart", 1756), ("brahms", 1833)] {
+        match database.set(key, Person::new(key, year_of_birth)) {
+            Ok(()) => (),
+            Err(e) => assert_eq!(e, failed),
+        }
+    }
+
+    assert_eq!(database.checkpoint(), Err(failed));
+    assert_eq!(database.len(), 3);
+
+    drop(database);
+
+    // The other replica must have received every write.
+    assert_eq!(
+        rx.try_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+        vec!["bach", "mozart", "brahms"]
+    );
+
+    // Replicas without a name fail with the plain error.
+    let mut database: Database<Person, InMemory> =
+        Database::new_in_memory().with_async_replica(FailingReplica(false));
+
+    database.set("bach", Person::new("bach", 1685))?;
+    database.set("mozart", Person::new("mozart", 1756)).ok();
+    assert_eq!(database.checkpoint(), Err(JasonError::ReplicaError));
+
+    Ok(())
+}
+
+#[test]
+fn compacted_replicas() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?
+        .with_replica_from(Database::create("test_compacted_sync_replica.jdb")?, 0)?
+        .with_async_replica_from(Database::create("test_compacted_async_replica.jdb")?, 0)?;
+
+    for year_of_birth in 1686..1700 {
+        database.set("bach", Person::new("Johann Sebastian Bach", year_of_birth))?;
+    }
+    database.delete("mozart")?;
+    database.checkpoint()?;
+
+    let paths = [
+        "test_compacted_sync_replica.jdb",
+        "test_compacted_async_replica.jdb",
+    ];
+    let sizes_before = paths.map(|path| fs::metadata(path).unwrap().len());
+
+    database.compact()?;
+
+    // Replicas are compacted along with the database, so their files shrink too.
+    for (path, size_before) in paths.iter().zip(sizes_before) {
+        assert!(fs::metadata(path).unwrap().len() < size_before);
+    }
+
+    // Replication carries on as normal after compaction.
+    database.set("elgar", Person::new("Edward Elgar", 1857))?;
+    drop(database);
+
+    for path in paths {
+        let mut replica: Database<Person> = Database::open(path)?;
+
+        assert_eq!(replica.len(), 6);
+        assert_eq!(replica.get("bach")?.year_of_birth, 1699);
+        assert_eq!(replica.get("elgar")?.year_of_birth, 1857);
+        assert!(replica.get("mozart").is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    Ok(())
+}
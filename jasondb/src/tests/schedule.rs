@@ -0,0 +1,40 @@
+use crate::error::JasonError;
+use crate::sources::{InMemory, Source};
+
+use crate::tests::mock::{composers_db, Person};
+
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[test]
+fn background_compaction() -> Result<(), JasonError> {
+    let mut database = composers_db(InMemory::new())?;
+
+    for year in 1685..1785 {
+        database.set("bach", Person::new("Johann Sebastian Bach", year))?;
+    }
+
+    let live_size = database.compaction_estimate()?.bytes_live;
+    let database = database.with_background_compaction(Duration::from_millis(10));
+
+    let start = Instant::now();
+    while database.lock().source.size() > live_size {
+        assert!(start.elapsed() < Duration::from_secs(5), "not compacted");
+        sleep(Duration::from_millis(10));
+    }
+
+    // The database can still be used while it is being compacted in the background.
+    database
+        .lock()
+        .set("elgar", Person::new("Edward Elgar", 1857))?;
+    assert_eq!(database.shared().lock().unwrap().len(), 7);
+
+    let database = Arc::try_unwrap(database.stop()).ok().unwrap();
+    let mut database = database.into_inner().unwrap();
+
+    assert_eq!(database.get("bach")?.year_of_birth, 1784);
+    assert_eq!(database.get("elgar")?.year_of_birth, 1857);
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use crate::error::JasonError;
+use crate::sources::{CompactionStats, InMemory, Source};
+use crate::Database;
+
+use crate::tests::mock::{composers_db, Person};
+
+use humphrey_json::prelude::*;
+use humphrey_json::Value;
+
+use std::collections::{BTreeSet, HashMap};
+
+/// A source which only implements the required methods, so that the defaults are used for everything else.
+struct MinimalSource(InMemory);
+
+impl Source for MinimalSource {
+    fn read_entry(&mut self, offset: u64) -> Result<(String, Vec<u8>), JasonError> {
+        self.0.read_entry(offset)
+    }
+
+    fn write_entry(&mut self, k: impl AsRef<str>, v: impl AsRef<[u8]>) -> Result<u64, JasonError> {
+        self.0.write_entry(k, v)
+    }
+
+    fn load_indexes(&mut self) -> Result<HashMap<String, u64>, JasonError> {
+        self.0.load_indexes()
+    }
+
+    fn index_on(
+        &mut self,
+        k: impl AsRef<str>,
+        indexes: &HashMap<String, u64>,
+    ) -> Result<HashMap<Value, BTreeSet<u64>>, JasonError> {
+        self.0.index_on(k, indexes)
+    }
+
+    fn compact(&mut self, indexes: &HashMap<String, u64>) -> Result<CompactionStats, JasonError> {
+        self.0.compact(indexes)
+    }
+
+    fn migrate<Old, New, F>(
+        &mut self,
+        indexes: &HashMap<String, u64>,
+        f: F,
+    ) -> Result<(), JasonError>
+    where
+        Old: IntoJson + FromJson,
+        New: IntoJson + FromJson,
+        F: Fn(Old) -> New,
+    {
+        self.0.migrate(indexes, f)
+    }
+}
+
+#[test]
+fn default_methods() -> Result<(), JasonError> {
+    let mut expected = composers_db(InMemory::new())?;
+    let mut database = composers_db(MinimalSource(InMemory::new()))?;
+
+    assert_eq!(database.source.size(), expected.source.size());
+    assert_eq!(
+        database.source.iter_raw().collect::<Result<Vec<_>, _>>()?,
+        expected.source.iter_raw().collect::<Result<Vec<_>, _>>()?
+    );
+
+    let offset = database.offset_of("brahms").unwrap();
+    assert_eq!(
+        database
+            .source
+            .iter_raw_from(offset)
+            .map(|entry| entry.map(|(k, _, _)| k))
+            .collect::<Result<Vec<_>, _>>()?,
+        vec!["brahms", "saint_saens", "tchaikovsky", "shostakovich"]
+    );
+
+    let mut replica: Database<Person, InMemory> = Database::new_in_memory();
+    assert_eq!(
+        database.replicate_from(&mut replica, 0)?,
+        expected.source.size()
+    );
+    assert_eq!(replica.len(), 6);
+
+    assert_eq!(
+        database.source.truncate_to(offset),
+        Err(JasonError::Unsupported)
+    );
+    assert_eq!(database.source.remove_entry(offset), Ok(None));
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use crate::error::JasonError;
+use crate::sources::index_file::IndexSorter;
+use crate::sources::{FileSource, Source};
+use crate::{Database, SpilledDatabase};
+
+use crate::tests::mock::{composers_db, Person};
+
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn spilled() -> Result<(), JasonError> {
+    let mut database: SpilledDatabase<Person> = SpilledDatabase::new("test_db_spilled.jdb")?;
+    assert!(database.is_empty());
+
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    database.set("brahms", Person::new("Johannes Brahms", 1833))?;
+    database.set("bach", Person::new("Johann Sebastian Bach", 1686))?;
+    database.delete("brahms")?;
+
+    assert_eq!(database.len(), 2);
+    assert_eq!(database.get("bach")?.year_of_birth, 1686);
+    assert_eq!(database.get("brahms").err(), Some(JasonError::InvalidKey));
+    assert_eq!(
+        database.delete("brahms").err(),
+        Some(JasonError::InvalidKey)
+    );
+    assert_eq!(database.hot.len(), 2);
+
+    database.compact()?;
+    assert!(database.hot.is_empty());
+    assert!(fs::exists("test_db_spilled.jdbindex").unwrap());
+    drop(database);
+
+    // Keys are looked up from the index file, then cached.
+    let mut database: SpilledDatabase<Person> =
+        SpilledDatabase::open("test_db_spilled.jdb")?.with_cache_capacity(1);
+    assert_eq!(database.len(), 2);
+    assert!(database.hot.is_empty());
+    assert_eq!(database.get("mozart")?.year_of_birth, 1756);
+    assert_eq!(database.get("bach")?.year_of_birth, 1686);
+    assert_eq!(database.cache.keys().collect::<Vec<_>>(), vec!["bach"]);
+    assert!(!database.contains_key("brahms")?);
+
+    // Writes after the index file was generated are found again when the database is reopened.
+    database.delete("mozart")?;
+    database.set("brahms", Person::new("Johannes Brahms", 1833))?;
+    drop(database);
+
+    let mut database: SpilledDatabase<Person> = SpilledDatabase::open("test_db_spilled.jdb")?;
+    assert_eq!(database.len(), 2);
+    assert_eq!(database.hot.len(), 2);
+    assert!(!database.contains_key("mozart")?);
+    assert_eq!(database.get("brahms")?.year_of_birth, 1833);
+    assert_eq!(database.get("bach")?.year_of_birth, 1686);
+    drop(database);
+
+    let mut database: Database<Person> = Database::open("test_db_spilled.jdb")?;
+    assert_eq!(database.len(), 2);
+    assert_eq!(database.get("bach")?.year_of_birth, 1686);
+    drop(database);
+
+    fs::remove_file("test_db_spilled.jdb").unwrap();
+    fs::remove_file("test_db_spilled.jdbindex").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn spilled_stale_index() -> Result<(), JasonError> {
+    let mut database = composers_db(FileSource::create("test_db_spilled_stale.jdb")?)?;
+    database.delete("bach")?;
+    drop(database);
+
+    let spilled: SpilledDatabase<Person> = SpilledDatabase::open("test_db_spilled_stale.jdb")?;
+    assert_eq!(spilled.len(), 5);
+    assert!(spilled.hot.is_empty());
+    drop(spilled);
+
+    // Compacting the database elsewhere moves every entry, so the index file must be regenerated.
+    let mut database: Database<Person> = Database::open("test_db_spilled_stale.jdb")?;
+    database.compact()?;
+    drop(database);
+
+    let mut spilled: SpilledDatabase<Person> = SpilledDatabase::open("test_db_spilled_stale.jdb")?;
+    assert_eq!(spilled.len(), 5);
+    assert_eq!(spilled.get("shostakovich")?.year_of_birth, 1906);
+    assert_eq!(spilled.get("mozart")?.year_of_birth, 1756);
+    assert!(!spilled.contains_key("bach")?);
+    drop(spilled);
+
+    // A corrupt index file is regenerated too.
+    fs::write("test_db_spilled_stale.jdbindex", b"JASONIDX").unwrap();
+
+    let mut spilled: SpilledDatabase<Person> = SpilledDatabase::open("test_db_spilled_stale.jdb")?;
+    assert_eq!(spilled.get("brahms")?.year_of_birth, 1833);
+    drop(spilled);
+
+    fs::remove_file("test_db_spilled_stale.jdb").unwrap();
+    fs::remove_file("test_db_spilled_stale.jdbindex").unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn spilled_sorted_runs() -> Result<(), JasonError> {
+    let path = Path::new("test_db_spilled_runs.jdbindex");
+    let mut sorter = IndexSorter::new(path)?;
+    sorter.run_len = 3;
+
+    // Later offsets for the same key replace earlier ones, across runs as well as within them.
+    for (i, key) in ["e", "b", "a", "b", "d", "c", "a", "f", "e"]
+        .iter()
+        .enumerate()
+    {
+        sorter.insert(key.to_string(), i as u64)?;
+    }
+
+    sorter.remove("d".to_string())?;
+    sorter.remove("f".to_string())?;
+    sorter.insert("f".to_string(), 10)?;
+
+    let index = sorter.finish(100, 0)?;
+    let records = index.iter().collect::<Result<Vec<_>, _>>()?;
+
+    assert_eq!(
+        records,
+        vec![
+            ("a".to_string(), 6),
+            ("b".to_string(), 3),
+            ("c".to_string(), 5),
+            ("e".to_string(), 8),
+            ("f".to_string(), 10)
+        ]
+    );
+    assert_eq!(index.get("c")?, Some(5));
+    assert_eq!(index.get("d")?, None);
+    assert_eq!(index.data_len, 100);
+    drop(index);
+
+    assert!(!fs::exists("test_db_spilled_runs.jdbindexruns").unwrap());
+    assert!(!fs::exists("test_db_spilled_runs.jdbindextable").unwrap());
+    fs::remove_file(path).unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn spilled_compact_order() -> Result<(), JasonError> {
+    let mut database: SpilledDatabase<Person> = SpilledDatabase::new("test_db_spilled_order.jdb")?;
+    database.set("mozart", Person::new("Wolfgang Amadeus Mozart", 1756))?;
+    database.set("brahms", Person::new("Johannes Brahms", 1833))?;
+    database.compact()?;
+
+    // Keys written since the compaction are merged into the index file's keys.
+    database.set("bach", Person::new("Johann Sebastian Bach", 1685))?;
+    database.set("shostakovich", Person::new("Dmitri Shostakovich", 1906))?;
+    database.set("brahms", Person::new("Johannes Brahms", 1834))?;
+    database.delete("mozart")?;
+
+    let stats = database.compact()?;
+    assert!(stats.bytes_after < stats.bytes_before);
+    assert_eq!(database.len(), 3);
+    assert_eq!(database.get("brahms")?.year_of_birth, 1834);
+    assert!(!database.contains_key("mozart")?);
+    drop(database);
+
+    let mut source = FileSource::open("test_db_spilled_order.jdb")?;
+    let keys = source
+        .iter_raw()
+        .map(|entry| entry.map(|(k, _, _)| k))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(keys, vec!["bach", "brahms", "shostakovich"]);
+    drop(source);
+
+    let mut database: SpilledDatabase<Person> = SpilledDatabase::open("test_db_spilled_order.jdb")?;
+    assert!(database.hot.is_empty());
+    assert_eq!(database.get("shostakovich")?.year_of_birth, 1906);
+    drop(database);
+
+    fs::remove_file("test_db_spilled_order.jdb").unwrap();
+    fs::remove_file("test_db_spilled_order.jdbindex").unwrap();
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use crate::util::timestamp::parse;
+
+#[test]
+fn parse_dates() {
+    assert_eq!(parse("1970-01-01"), Some(0));
+    assert_eq!(parse("1970-01-02"), Some(86400 * 1_000_000_000));
+    assert_eq!(parse("1969-12-31"), Some(-86400 * 1_000_000_000));
+    assert_eq!(parse("2000-02-29"), Some(951782400 * 1_000_000_000));
+
+    assert_eq!(parse("2001-02-29"), None);
+    assert_eq!(parse("2023-13-01"), None);
+    assert_eq!(parse("2023-1-01"), None);
+    assert_eq!(parse("not a date"), None);
+    assert_eq!(parse(""), None);
+}
+
+#[test]
+fn parse_times() {
+    let midday = parse("2023-01-01T12:00:00Z").unwrap();
+
+    assert_eq!(parse("2023-01-01T12:00"), Some(midday));
+    assert_eq!(parse("2023-01-01 12:00:00"), Some(midday));
+    assert_eq!(parse("2023-01-01T12:00:00.000"), Some(midday));
+    assert_eq!(parse("2023-01-01T12:00:00.5Z"), Some(midday + 500_000_000));
+    assert_eq!(
+        parse("2023-01-01T12:00:00.123456789Z"),
+        Some(midday + 123_456_789)
+    );
+
+    assert_eq!(parse("2023-01-01T24:00:00"), None);
+    assert_eq!(parse("2023-01-01T12:00:00."), None);
+    assert_eq!(parse("2023-01-01T12"), None);
+    assert_eq!(parse("2023-01-01T12:00:00 UTC"), None);
+}
+
+#[test]
+fn parse_offsets() {
+    let midday = parse("2023-01-01T12:00:00Z").unwrap();
+
+    assert_eq!(parse("2023-01-01T13:00:00+01:00"), Some(midday));
+    assert_eq!(parse("2023-01-01T06:30:00-0530"), Some(midday));
+    assert_eq!(parse("2023-01-01T14:00:00+02"), Some(midday));
+
+    assert_eq!(parse("2023-01-01T12:00:00+1"), None);
+    assert_eq!(parse("2023-01-01T12:00:00+24:00"), None);
+}
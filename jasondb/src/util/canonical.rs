@@ -0,0 +1,29 @@
+use crate::error::JasonError;
+
+use humphrey_json::Value;
+
+/// Parses the given raw JSON and re-serializes it in canonical minified form.
+///
+/// If `sort_keys` is set, the keys of all objects are sorted too, so logically-equal values are byte-equal.
+pub fn canonicalise(json: &[u8], sort_keys: bool) -> Result<String, JasonError> {
+    let json = std::str::from_utf8(json).map_err(|_| JasonError::JsonError)?;
+    let mut value = Value::parse(json).map_err(|_| JasonError::JsonError)?;
+
+    if sort_keys {
+        sort_object_keys(&mut value);
+    }
+
+    Ok(value.serialize())
+}
+
+/// Recursively sorts the keys of all objects in the given value.
+pub fn sort_object_keys(value: &mut Value) {
+    match value {
+        Value::Array(array) => array.iter_mut().for_each(sort_object_keys),
+        Value::Object(object) => {
+            object.sort_by(|(a, _), (b, _)| a.cmp(b));
+            object.iter_mut().for_each(|(_, v)| sort_object_keys(v));
+        }
+        _ => (),
+    }
+}
@@ -2,22 +2,46 @@ use crate::error::JasonError;
 
 use humphrey_json::Value;
 
+/// Parses the value of an entry so that it can be indexed.
+///
+/// If the value is not valid JSON, the error includes the key of the entry, so that it can be found and fixed.
+pub fn parse_entry_value(key: String, value: Vec<u8>) -> Result<Value, JasonError> {
+    let json = unsafe { String::from_utf8_unchecked(value) };
+
+    Value::parse(json).map_err(|_| JasonError::InvalidEntry { key })
+}
+
 pub fn get_value(index: &str, json: &Value) -> Value {
+    get_value_opt(index, json).cloned().unwrap_or(Value::Null)
+}
+
+/// Gets the value at the given path, or `None` if any part of the path is missing.
+///
+/// Unlike `get_value`, this distinguishes a field which is explicitly `null` from one which is not present at all.
+pub fn get_value_opt<'a>(index: &str, json: &'a Value) -> Option<&'a Value> {
     let indexing_path = index.split('.');
     let mut current_json = json;
     for index in indexing_path {
-        match current_json.get(index) {
-            Some(value) => current_json = value,
-            None => return Value::Null,
-        }
+        current_json = current_json.get(index)?;
     }
 
-    current_json.clone()
+    Some(current_json)
 }
 
-pub fn get_number(index: &str, json: &Value) -> Result<f64, JasonError> {
-    let value = get_value(index, json);
-    let number = value.as_number().ok_or(JasonError::JsonError)?;
+/// Gets a mutable reference to the value at the given path, so that it can be replaced.
+///
+/// Missing fields along the path are created, and `null` ones are replaced with empty objects, so the path always
+///   exists afterwards. Returns `None` if any part of the path is neither an object nor `null`.
+pub fn get_value_mut<'a>(index: &str, json: &'a mut Value) -> Option<&'a mut Value> {
+    let indexing_path = index.split('.');
+    let mut current_json = json;
+    for index in indexing_path {
+        if let Value::Null = current_json {
+            *current_json = Value::Object(Vec::new());
+        }
+
+        current_json = current_json.get_mut(index)?;
+    }
 
-    Ok(number)
+    Some(current_json)
 }
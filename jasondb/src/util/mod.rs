@@ -1,4 +1,9 @@
+pub mod canonical;
 pub mod indexing;
+pub mod ordering;
 mod quiet_assert;
+pub mod timestamp;
+mod trace;
 
 pub use quiet_assert::quiet_assert;
+pub(crate) use trace::trace;
@@ -0,0 +1,84 @@
+use humphrey_json::Value;
+
+use std::cmp::Ordering;
+
+/// Compares two JSON values, giving a total ordering over all values.
+///
+/// Values of different types are ordered `null < bool < number < string < array < object`.
+/// Arrays and objects are compared lexicographically by their elements.
+pub fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => a.total_cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| compare(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|((ak, av), (bk, bv))| ak.cmp(bk).then_with(|| compare(av, bv)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// A JSON value which is totally ordered by [`compare`], so it can be sorted or used as the key of a `BTreeMap`.
+///
+/// Values of different types are ordered `null < bool < number < string < array < object`. Within a type, `false` is
+///   less than `true`, numbers are ordered numerically, strings are ordered by their bytes, and arrays and objects
+///   are ordered lexicographically by their elements, with object members compared key first in the order in which
+///   they are stored.
+///
+/// Equality is defined by the same ordering, so unlike `Value`, `-0.0` and `0.0` are not equal.
+#[derive(Clone, Debug)]
+pub struct OrderedValue(pub Value);
+
+impl PartialEq for OrderedValue {
+    fn eq(&self, other: &Self) -> bool {
+        compare(&self.0, &other.0).is_eq()
+    }
+}
+
+impl Eq for OrderedValue {}
+
+impl PartialOrd for OrderedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
+
+impl From<Value> for OrderedValue {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
+}
+
+impl From<OrderedValue> for Value {
+    fn from(value: OrderedValue) -> Self {
+        value.0
+    }
+}
+
+/// Returns the rank of the value's type for cross-type comparisons.
+fn rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
@@ -0,0 +1,151 @@
+/// Parses an ISO-8601 timestamp into the number of nanoseconds since the Unix epoch.
+///
+/// Supports dates (`2023-01-01`), optionally followed by a time with minute, second or fractional precision
+///   (`2023-01-01T12:30`, `2023-01-01T12:30:15.25`) and an optional UTC offset (`Z`, `+01:00`, `-0530`).
+///   Timestamps without an offset are assumed to be in UTC. Returns `None` if the timestamp is invalid.
+pub fn parse(s: &str) -> Option<i128> {
+    let mut cursor = Cursor(s.as_bytes());
+
+    let year = cursor.digits(4)? as i64;
+    cursor.expect(b'-')?;
+    let month = cursor.digits(2)?;
+    cursor.expect(b'-')?;
+    let day = cursor.digits(2)?;
+
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut seconds = days_from_civil(year, month, day) * 86400;
+    let mut nanos: i128 = 0;
+
+    if matches!(cursor.peek(), Some(b'T' | b't' | b' ')) {
+        cursor.next();
+
+        let hour = cursor.digits(2)?;
+        cursor.expect(b':')?;
+        let minute = cursor.digits(2)?;
+        let second = if cursor.peek() == Some(b':') {
+            cursor.next();
+            cursor.digits(2)?
+        } else {
+            0
+        };
+
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+
+        seconds += (hour * 3600 + minute * 60 + second) as i64;
+
+        if matches!(cursor.peek(), Some(b'.' | b',')) {
+            cursor.next();
+
+            let mut precision = 0;
+            while let Some(digit @ b'0'..=b'9') = cursor.peek() {
+                cursor.next();
+
+                if precision < 9 {
+                    nanos = nanos * 10 + (digit - b'0') as i128;
+                    precision += 1;
+                }
+            }
+
+            if precision == 0 {
+                return None;
+            }
+
+            nanos *= 10i128.pow(9 - precision);
+        }
+
+        match cursor.next() {
+            None => (),
+            Some(b'Z' | b'z') => (),
+            Some(sign @ (b'+' | b'-')) => {
+                let offset_hours = cursor.digits(2)?;
+                if cursor.peek() == Some(b':') {
+                    cursor.next();
+                }
+                let offset_minutes = if cursor.peek().is_some() {
+                    cursor.digits(2)?
+                } else {
+                    0
+                };
+
+                if offset_hours > 23 || offset_minutes > 59 {
+                    return None;
+                }
+
+                let offset = (offset_hours * 3600 + offset_minutes * 60) as i64;
+
+                if sign == b'+' {
+                    seconds -= offset;
+                } else {
+                    seconds += offset;
+                }
+            }
+            Some(_) => return None,
+        }
+    }
+
+    if cursor.peek().is_some() {
+        return None;
+    }
+
+    Some(seconds as i128 * 1_000_000_000 + nanos)
+}
+
+/// A simple cursor over the bytes of a timestamp.
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let (&first, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(first)
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.next()? == byte).then_some(())
+    }
+
+    fn digits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+
+        for _ in 0..n {
+            match self.next()? {
+                digit @ b'0'..=b'9' => value = value * 10 + (digit - b'0') as u32,
+                _ => return None,
+            }
+        }
+
+        Some(value)
+    }
+}
+
+/// Returns the number of days in the given month of the given year.
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days since the Unix epoch of the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
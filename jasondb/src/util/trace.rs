@@ -0,0 +1,9 @@
+/// Emits a `tracing` event with the given arguments if the `tracing` feature is enabled, and does nothing otherwise.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!($($arg)*);
+    };
+}
+
+pub(crate) use trace;